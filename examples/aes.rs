@@ -86,7 +86,7 @@ fn main() -> ! {
     assert_eq!(hw_block, block);
 
     // // Finally, PUF key
-    // let cipher = hashcrypt.puf_aes();
+    // let cipher = hashcrypt.puf_aes(&puf.load_aes_key(&kc).unwrap());
     // dbg!(hw_block);
     // dbg!(cipher.decrypt_block(&mut hw_block));
     // dbg!(hw_block);
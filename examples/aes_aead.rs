@@ -0,0 +1,131 @@
+#![no_main]
+#![no_std]
+
+///
+/// Checks AES-GCM against test case 4 of the GCM specification (as used in the NIST
+/// validation), and AES-CCM against examples 1-3 of NIST SP 800-38C, appendix C.
+///
+extern crate panic_semihosting;
+use cortex_m_rt::entry;
+
+use cortex_m_semihosting::hprintln;
+
+use generic_array::{typenum, GenericArray};
+use hal::drivers::aes;
+use hal::traits::aead::AeadInPlace;
+use lpc55_hal as hal;
+
+const GCM_KEY: [u8; 16] = [
+    0xfe, 0xff, 0xe9, 0x92, 0x86, 0x65, 0x73, 0x1c, 0x6d, 0x6a, 0x8f, 0x94, 0x67, 0x30, 0x83, 0x08,
+];
+const GCM_NONCE: [u8; 12] = [
+    0xca, 0xfe, 0xba, 0xbe, 0xfa, 0xce, 0xdb, 0xad, 0xde, 0xca, 0xf8, 0x88,
+];
+const GCM_AAD: [u8; 20] = [
+    0xfe, 0xed, 0xfa, 0xce, 0xde, 0xad, 0xbe, 0xef, 0xfe, 0xed, 0xfa, 0xce, 0xde, 0xad, 0xbe, 0xef,
+    0xab, 0xad, 0xda, 0xd2,
+];
+const GCM_PLAINTEXT: [u8; 60] = [
+    0xd9, 0x31, 0x32, 0x25, 0xf8, 0x84, 0x06, 0xe5, 0xa5, 0x59, 0x09, 0xc5, 0xaf, 0xf5, 0x26, 0x9a,
+    0x86, 0xa7, 0xa9, 0x53, 0x15, 0x34, 0xf7, 0xda, 0x2e, 0x4c, 0x30, 0x3d, 0x8a, 0x31, 0x8a, 0x72,
+    0x1c, 0x3c, 0x0c, 0x95, 0x95, 0x68, 0x09, 0x53, 0x2f, 0xcf, 0x0e, 0x24, 0x49, 0xa6, 0xb5, 0x25,
+    0xb1, 0x6a, 0xed, 0xf5, 0xaa, 0x0d, 0xe6, 0x57, 0xba, 0x63, 0x7b, 0x39,
+];
+const GCM_CIPHERTEXT: [u8; 60] = [
+    0x42, 0x83, 0x1e, 0xc2, 0x21, 0x77, 0x74, 0x24, 0x4b, 0x72, 0x21, 0xb7, 0x84, 0xd0, 0xd4, 0x9c,
+    0xe3, 0xaa, 0x21, 0x2f, 0x2c, 0x02, 0xa4, 0xe0, 0x35, 0xc1, 0x7e, 0x23, 0x29, 0xac, 0xa1, 0x2e,
+    0x21, 0xd5, 0x14, 0xb2, 0x54, 0x66, 0x93, 0x1c, 0x7d, 0x8f, 0x6a, 0x5a, 0xac, 0x84, 0xaa, 0x05,
+    0x1b, 0xa3, 0x0b, 0x39, 0x6a, 0x0a, 0xac, 0x97, 0x3d, 0x58, 0xe0, 0x91,
+];
+const GCM_TAG: [u8; 16] = [
+    0x5b, 0xc9, 0x4f, 0xbc, 0x32, 0x21, 0xa5, 0xdb, 0x94, 0xfa, 0xe9, 0x5a, 0xe7, 0x12, 0x1a, 0x47,
+];
+
+const CCM_KEY: [u8; 16] = [
+    0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4a, 0x4b, 0x4c, 0x4d, 0x4e, 0x4f,
+];
+// the examples use prefixes of these
+const CCM_NONCE: [u8; 12] = [
+    0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b,
+];
+const CCM_AAD: [u8; 20] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    0x10, 0x11, 0x12, 0x13,
+];
+const CCM_PLAINTEXT: [u8; 24] = [
+    0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d, 0x2e, 0x2f,
+    0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37,
+];
+// ciphertext || tag
+const CCM_1: [u8; 8] = [0x71, 0x62, 0x01, 0x5b, 0x4d, 0xac, 0x25, 0x5d];
+const CCM_2: [u8; 22] = [
+    0xd2, 0xa1, 0xf0, 0xe0, 0x51, 0xea, 0x5f, 0x62, 0x08, 0x1a, 0x77, 0x92, 0x07, 0x3d, 0x59, 0x3d,
+    0x1f, 0xc6, 0x4f, 0xbf, 0xac, 0xcd,
+];
+const CCM_3: [u8; 32] = [
+    0xe3, 0xb2, 0x01, 0xa9, 0xf5, 0xb7, 0x1a, 0x7a, 0x9b, 0x1c, 0xea, 0xec, 0xcd, 0x97, 0xe7, 0x0b,
+    0x61, 0x76, 0xaa, 0xd9, 0xa4, 0x42, 0x8a, 0xa5, 0x48, 0x43, 0x92, 0xfb, 0xc1, 0xb0, 0x99, 0x51,
+];
+
+fn check_ccm<M: aes::CcmTagSize, N: aes::CcmNonceSize>(
+    hashcrypt: &mut hal::Hashcrypt<hal::typestates::init_state::Enabled>,
+    aad_len: usize,
+    len: usize,
+    expected: &[u8],
+) {
+    let key = aes::Aes128Key::User(GenericArray::clone_from_slice(&CCM_KEY));
+    let ccm = aes::AesCcm::<_, M, N>::new(hashcrypt, key);
+    let nonce = GenericArray::from_slice(&CCM_NONCE[..N::to_usize()]);
+    let aad = &CCM_AAD[..aad_len];
+
+    let mut buf = [0u8; 24];
+    let buf = &mut buf[..len];
+    buf.copy_from_slice(&CCM_PLAINTEXT[..len]);
+    let tag = ccm.encrypt_in_place_detached(nonce, aad, buf).unwrap();
+    assert_eq!(buf, &expected[..len]);
+    assert_eq!(tag.as_slice(), &expected[len..]);
+
+    ccm.decrypt_in_place_detached(nonce, aad, buf, &tag)
+        .unwrap();
+    assert_eq!(buf, &CCM_PLAINTEXT[..len]);
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = hal::raw::Peripherals::take().unwrap();
+    let mut syscon = hal::Syscon::from(dp.SYSCON);
+    let mut hashcrypt = hal::Hashcrypt::from(dp.HASHCRYPT).enabled(&mut syscon);
+
+    let key = aes::Aes128Key::User(GenericArray::clone_from_slice(&GCM_KEY));
+    let gcm = aes::Aes128Gcm::new(&mut hashcrypt, key);
+    let nonce = GenericArray::from_slice(&GCM_NONCE);
+
+    let mut buf = GCM_PLAINTEXT;
+    let tag = gcm
+        .encrypt_in_place_detached(nonce, &GCM_AAD, &mut buf)
+        .unwrap();
+    assert_eq!(buf, GCM_CIPHERTEXT);
+    assert_eq!(tag.as_slice(), &GCM_TAG);
+
+    // tampered
+    let mut tampered = buf;
+    tampered[0] ^= 1;
+    assert!(gcm
+        .decrypt_in_place_detached(nonce, &GCM_AAD, &mut tampered, &tag)
+        .is_err());
+
+    gcm.decrypt_in_place_detached(nonce, &GCM_AAD, &mut buf, &tag)
+        .unwrap();
+    assert_eq!(buf, GCM_PLAINTEXT);
+    hprintln!("GCM ok");
+
+    check_ccm::<typenum::U4, typenum::U7>(&mut hashcrypt, 8, 4, &CCM_1);
+    check_ccm::<typenum::U6, typenum::U8>(&mut hashcrypt, 16, 16, &CCM_2);
+    check_ccm::<typenum::U8, typenum::U12>(&mut hashcrypt, 20, 24, &CCM_3);
+    hprintln!("CCM ok");
+
+    hprintln!("all done");
+    loop {
+        continue;
+    }
+}
@@ -0,0 +1,118 @@
+#![no_main]
+#![no_std]
+
+///
+/// Checks the AES modes against the AES-128 vectors of NIST SP 800-38A, appendix F.
+///
+extern crate panic_semihosting;
+use cortex_m_rt::entry;
+
+use cortex_m_semihosting::hprintln;
+
+use generic_array::GenericArray;
+use hal::drivers::aes;
+use hal::traits::cipher::{AsyncStreamCipher, BlockDecryptMut, BlockEncryptMut, StreamCipher};
+use lpc55_hal as hal;
+
+const KEY: [u8; 16] = [
+    0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f, 0x3c,
+];
+
+const IV: [u8; 16] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+];
+
+const COUNTER: [u8; 16] = [
+    0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa, 0xfb, 0xfc, 0xfd, 0xfe, 0xff,
+];
+
+const PLAINTEXT: [u8; 64] = [
+    0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a,
+    0xae, 0x2d, 0x8a, 0x57, 0x1e, 0x03, 0xac, 0x9c, 0x9e, 0xb7, 0x6f, 0xac, 0x45, 0xaf, 0x8e, 0x51,
+    0x30, 0xc8, 0x1c, 0x46, 0xa3, 0x5c, 0xe4, 0x11, 0xe5, 0xfb, 0xc1, 0x19, 0x1a, 0x0a, 0x52, 0xef,
+    0xf6, 0x9f, 0x24, 0x45, 0xdf, 0x4f, 0x9b, 0x17, 0xad, 0x2b, 0x41, 0x7b, 0xe6, 0x6c, 0x37, 0x10,
+];
+
+// F.2.1
+const CBC: [u8; 64] = [
+    0x76, 0x49, 0xab, 0xac, 0x81, 0x19, 0xb2, 0x46, 0xce, 0xe9, 0x8e, 0x9b, 0x12, 0xe9, 0x19, 0x7d,
+    0x50, 0x86, 0xcb, 0x9b, 0x50, 0x72, 0x19, 0xee, 0x95, 0xdb, 0x11, 0x3a, 0x91, 0x76, 0x78, 0xb2,
+    0x73, 0xbe, 0xd6, 0xb8, 0xe3, 0xc1, 0x74, 0x3b, 0x71, 0x16, 0xe6, 0x9e, 0x22, 0x22, 0x95, 0x16,
+    0x3f, 0xf1, 0xca, 0xa1, 0x68, 0x1f, 0xac, 0x09, 0x12, 0x0e, 0xca, 0x30, 0x75, 0x86, 0xe1, 0xa7,
+];
+
+// F.3.13
+const CFB: [u8; 64] = [
+    0x3b, 0x3f, 0xd9, 0x2e, 0xb7, 0x2d, 0xad, 0x20, 0x33, 0x34, 0x49, 0xf8, 0xe8, 0x3c, 0xfb, 0x4a,
+    0xc8, 0xa6, 0x45, 0x37, 0xa0, 0xb3, 0xa9, 0x3f, 0xcd, 0xe3, 0xcd, 0xad, 0x9f, 0x1c, 0xe5, 0x8b,
+    0x26, 0x75, 0x1f, 0x67, 0xa3, 0xcb, 0xb1, 0x40, 0xb1, 0x80, 0x8c, 0xf1, 0x87, 0xa4, 0xf4, 0xdf,
+    0xc0, 0x4b, 0x05, 0x35, 0x7c, 0x5d, 0x1c, 0x0e, 0xea, 0xc4, 0xc6, 0x6f, 0x9f, 0xf7, 0xf2, 0xe6,
+];
+
+// F.4.1
+const OFB: [u8; 64] = [
+    0x3b, 0x3f, 0xd9, 0x2e, 0xb7, 0x2d, 0xad, 0x20, 0x33, 0x34, 0x49, 0xf8, 0xe8, 0x3c, 0xfb, 0x4a,
+    0x77, 0x89, 0x50, 0x8d, 0x16, 0x91, 0x8f, 0x03, 0xf5, 0x3c, 0x52, 0xda, 0xc5, 0x4e, 0xd8, 0x25,
+    0x97, 0x40, 0x05, 0x1e, 0x9c, 0x5f, 0xec, 0xf6, 0x43, 0x44, 0xf7, 0xa8, 0x22, 0x60, 0xed, 0xcc,
+    0x30, 0x4c, 0x65, 0x28, 0xf6, 0x59, 0xc7, 0x78, 0x66, 0xa5, 0x10, 0xd9, 0xc1, 0xd6, 0xae, 0x5e,
+];
+
+// F.5.1
+const CTR: [u8; 64] = [
+    0x87, 0x4d, 0x61, 0x91, 0xb6, 0x20, 0xe3, 0x26, 0x1b, 0xef, 0x68, 0x64, 0x99, 0x0d, 0xb6, 0xce,
+    0x98, 0x06, 0xf6, 0x6b, 0x79, 0x70, 0xfd, 0xff, 0x86, 0x17, 0x18, 0x7b, 0xb9, 0xff, 0xfd, 0xff,
+    0x5a, 0xe4, 0xdf, 0x3e, 0xdb, 0xd5, 0xd3, 0x5e, 0x5b, 0x4f, 0x09, 0x02, 0x0d, 0xb0, 0x3e, 0xab,
+    0x1e, 0x03, 0x1d, 0xda, 0x2f, 0xbe, 0x03, 0xd1, 0x79, 0x21, 0x70, 0xa0, 0xf3, 0x00, 0x9c, 0xee,
+];
+
+fn blocks(bytes: &[u8; 64]) -> [GenericArray<u8, generic_array::typenum::U16>; 4] {
+    let mut blocks: [GenericArray<u8, _>; 4] = Default::default();
+    for (block, chunk) in blocks.iter_mut().zip(bytes.chunks(16)) {
+        block.copy_from_slice(chunk);
+    }
+    blocks
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = hal::raw::Peripherals::take().unwrap();
+    let mut syscon = hal::Syscon::from(dp.SYSCON);
+    let mut hashcrypt = hal::Hashcrypt::from(dp.HASHCRYPT).enabled(&mut syscon);
+
+    let key = || aes::Aes128Key::User(GenericArray::clone_from_slice(&KEY));
+    let iv = GenericArray::from_slice(&IV);
+
+    let mut buf = blocks(&PLAINTEXT);
+    aes::CbcEncrypt::new(&mut hashcrypt, key(), iv).encrypt_blocks_mut(&mut buf);
+    assert_eq!(buf, blocks(&CBC));
+    aes::CbcDecrypt::new(&mut hashcrypt, key(), iv).decrypt_blocks_mut(&mut buf);
+    assert_eq!(buf, blocks(&PLAINTEXT));
+    hprintln!("CBC ok");
+
+    let mut buf = PLAINTEXT;
+    aes::CfbEncrypt::new(&mut hashcrypt, key(), iv).encrypt(&mut buf);
+    assert_eq!(buf, CFB);
+    aes::CfbDecrypt::new(&mut hashcrypt, key(), iv).decrypt(&mut buf);
+    assert_eq!(buf, PLAINTEXT);
+    hprintln!("CFB ok");
+
+    let mut buf = PLAINTEXT;
+    aes::Ofb::from_core(aes::OfbCore::new(&mut hashcrypt, key(), iv)).apply_keystream(&mut buf);
+    assert_eq!(buf, OFB);
+    hprintln!("OFB ok");
+
+    // in uneven pieces, to exercise the keystream buffering
+    let mut buf = PLAINTEXT;
+    let counter = GenericArray::from_slice(&COUNTER);
+    let mut ctr = aes::Ctr::from_core(aes::CtrCore::new(&mut hashcrypt, key(), counter));
+    let (head, tail) = buf.split_at_mut(7);
+    ctr.apply_keystream(head);
+    ctr.apply_keystream(tail);
+    assert_eq!(buf, CTR);
+    hprintln!("CTR ok");
+
+    hprintln!("all done");
+    loop {
+        continue;
+    }
+}
@@ -0,0 +1,78 @@
+#![no_main]
+#![no_std]
+
+///
+/// Runs the same multiprecision arithmetic on CASPER and on the software model,
+/// and compares the results.
+///
+extern crate panic_semihosting;
+use cortex_m_rt::entry;
+
+use cortex_m_semihosting::hprintln;
+
+use core::cmp::Ordering;
+use hal::drivers::casper::{Bignum, Engine, Model};
+use lpc55_hal as hal;
+
+const A: [u64; 4] = [
+    0xffff_ffff_ffff_ffff,
+    0x0123_4567_89ab_cdef,
+    0xfedc_ba98_7654_3210,
+    0xffff_ffff_0000_0001,
+];
+const B: [u64; 3] = [
+    0xdead_beef_dead_beef,
+    0xffff_ffff_ffff_ffff,
+    0x8000_0000_0000_0000,
+];
+
+// a * b, (a << 13) >> 7, a + a, a - b and the comparisons
+fn compute<E: Engine>(bignum: &mut Bignum<E>) -> ([u64; 20], [Ordering; 2]) {
+    let a = bignum.alloc(4);
+    let b = bignum.alloc(3);
+    let product = bignum.alloc(7);
+    bignum.load(a, &A);
+    bignum.load(b, &B);
+    bignum.mul(product, a, b);
+
+    let shifted = bignum.alloc(5);
+    bignum.shl(shifted, a, 13);
+    bignum.shr(shifted, shifted, 7);
+
+    let sum = bignum.alloc(4);
+    bignum.copy(sum, a);
+    bignum.add(sum, a);
+
+    let b_long = bignum.alloc(4);
+    bignum.load(b_long, &B);
+    let difference = bignum.alloc(4);
+    bignum.copy(difference, a);
+    bignum.sub(difference, b_long);
+
+    let orderings = [bignum.compare(a, b_long), bignum.compare(a, a)];
+
+    let mut words = [0; 20];
+    bignum.store(product, &mut words[..7]);
+    bignum.store(shifted, &mut words[7..12]);
+    bignum.store(sum, &mut words[12..16]);
+    bignum.store(difference, &mut words[16..]);
+    (words, orderings)
+}
+
+#[entry]
+fn main() -> ! {
+    let dp = hal::raw::Peripherals::take().unwrap();
+    let mut syscon = hal::Syscon::from(dp.SYSCON);
+    let mut casper = hal::Casper::from(dp.CASPER).enabled(&mut syscon);
+
+    let hardware = compute(&mut Bignum::new(&mut casper));
+    let software = compute(&mut Bignum::new(Model::new()));
+    assert_eq!(hardware, software);
+    assert_eq!(hardware.1, [Ordering::Greater, Ordering::Equal]);
+    hprintln!("CASPER matches the model");
+
+    hprintln!("all done");
+    loop {
+        continue;
+    }
+}
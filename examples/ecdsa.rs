@@ -0,0 +1,67 @@
+#![no_main]
+#![no_std]
+
+///
+/// Signs and verifies with ECDSA on P-256 (CASPER, with the hash on HASHCRYPT and
+/// nonces from the RNG), times both, and agrees on an ECDH shared secret.
+///
+extern crate panic_semihosting;
+use cortex_m_rt::entry;
+
+use cortex_m_semihosting::hprintln;
+
+use core::cell::RefCell;
+use hal::drivers::{
+    casper::Bignum,
+    ecdsa::{SigningKey, P256},
+};
+use hal::traits::{
+    digest::{FixedOutputReset, Update},
+    signature::hazmat::{PrehashVerifier, RandomizedPrehashSigner},
+};
+use lpc55_hal as hal;
+
+const MESSAGE: &[u8] = b"firmware image";
+
+#[entry]
+fn main() -> ! {
+    let dp = hal::raw::Peripherals::take().unwrap();
+    let mut syscon = hal::Syscon::from(dp.SYSCON);
+    let mut hashcrypt = hal::Hashcrypt::from(dp.HASHCRYPT).enabled(&mut syscon);
+    let mut casper = hal::Casper::from(dp.CASPER).enabled(&mut syscon);
+    let mut rng = hal::Rng::from(dp.RNG).enabled(&mut syscon);
+
+    hal::enable_cycle_counter();
+
+    let mut sha = hashcrypt.sha256();
+    sha.update(MESSAGE);
+    let digest = sha.finalize_fixed_reset();
+
+    let bignum = RefCell::new(Bignum::new(&mut casper));
+    let key = SigningKey::<P256, _>::random(&bignum, &mut rng);
+    let public = key.verifying_key();
+
+    let (cycles, signature) = hal::count_cycles(|| key.sign_prehash_with_rng(&mut rng, &digest));
+    let signature = signature.unwrap();
+    hprintln!("signed, {} cycles", cycles);
+
+    let (cycles, result) = hal::count_cycles(|| public.verify_prehash(&digest, &signature));
+    result.unwrap();
+    hprintln!("verified, {} cycles", cycles);
+
+    sha.update(b"other image");
+    let other = sha.finalize_fixed_reset();
+    assert!(public.verify_prehash(&other, &signature).is_err());
+    hprintln!("other message rejected");
+
+    let peer = SigningKey::<P256, _>::random(&bignum, &mut rng);
+    let ours = key.diffie_hellman(&peer.verifying_key()).unwrap();
+    let theirs = peer.diffie_hellman(&public).unwrap();
+    assert_eq!(ours, theirs);
+    hprintln!("ECDH ok");
+
+    hprintln!("all done");
+    loop {
+        continue;
+    }
+}
@@ -0,0 +1,84 @@
+#![no_main]
+#![no_std]
+
+///
+/// Ed25519ph with the RFC 8032 test vector (as `salty.rs.hidden` in software) and X25519
+/// with the RFC 7748 one, on CASPER, with SHA-512 from the `sha2` crate.
+///
+extern crate panic_semihosting;
+use cortex_m_rt::entry;
+
+use cortex_m_semihosting::hprintln;
+
+use hal::drivers::{
+    casper::Bignum,
+    ed25519::{x25519, SigningKey, X25519_BASEPOINT},
+};
+use lpc55_hal as hal;
+use sha2::{Digest, Sha512};
+
+const SEED: [u8; 32] = [
+    0x83, 0x3f, 0xe6, 0x24, 0x09, 0x23, 0x7b, 0x9d, 0x62, 0xec, 0x77, 0x58, 0x75, 0x20, 0x91, 0x1e,
+    0x9a, 0x75, 0x9c, 0xec, 0x1d, 0x19, 0x75, 0x5b, 0x7d, 0xa9, 0x01, 0xb9, 0x6d, 0xca, 0x3d, 0x42,
+];
+
+const SIGNATURE: [u8; 64] = [
+    0x98, 0xa7, 0x02, 0x22, 0xf0, 0xb8, 0x12, 0x1a, 0xa9, 0xd3, 0x0f, 0x81, 0x3d, 0x68, 0x3f, 0x80,
+    0x9e, 0x46, 0x2b, 0x46, 0x9c, 0x7f, 0xf8, 0x76, 0x39, 0x49, 0x9b, 0xb9, 0x4e, 0x6d, 0xae, 0x41,
+    0x31, 0xf8, 0x50, 0x42, 0x46, 0x3c, 0x2a, 0x35, 0x5a, 0x20, 0x03, 0xd0, 0x62, 0xad, 0xf5, 0xaa,
+    0xa1, 0x0b, 0x8c, 0x61, 0xe6, 0x36, 0x06, 0x2a, 0xaa, 0xd1, 0x1c, 0x2a, 0x26, 0x08, 0x34, 0x06,
+];
+
+const ALICE: [u8; 32] = [
+    0x77, 0x07, 0x6d, 0x0a, 0x73, 0x18, 0xa5, 0x7d, 0x3c, 0x16, 0xc1, 0x72, 0x51, 0xb2, 0x66, 0x45,
+    0xdf, 0x4c, 0x2f, 0x87, 0xeb, 0xc0, 0x99, 0x2a, 0xb1, 0x77, 0xfb, 0xa5, 0x1d, 0xb9, 0x2c, 0x2a,
+];
+
+const BOB: [u8; 32] = [
+    0x5d, 0xab, 0x08, 0x7e, 0x62, 0x4a, 0x8a, 0x4b, 0x79, 0xe1, 0x7f, 0x8b, 0x83, 0x80, 0x0e, 0xe6,
+    0x6f, 0x3b, 0xb1, 0x29, 0x26, 0x18, 0xb6, 0xfd, 0x1c, 0x2f, 0x8b, 0x27, 0xff, 0x88, 0xe0, 0xeb,
+];
+
+const SHARED: [u8; 32] = [
+    0x4a, 0x5d, 0x9d, 0x5b, 0xa4, 0xce, 0x2d, 0xe1, 0x72, 0x8e, 0x3b, 0xf4, 0x80, 0x35, 0x0f, 0x25,
+    0xe0, 0x7e, 0x21, 0xc9, 0x47, 0xd1, 0x9e, 0x33, 0x76, 0xf0, 0x9b, 0x3c, 0x1e, 0x16, 0x17, 0x42,
+];
+
+#[entry]
+fn main() -> ! {
+    let dp = hal::raw::Peripherals::take().unwrap();
+    let mut syscon = hal::Syscon::from(dp.SYSCON);
+    let mut casper = hal::Casper::from(dp.CASPER).enabled(&mut syscon);
+
+    hal::enable_cycle_counter();
+
+    let mut bignum = Bignum::new(&mut casper);
+    let mut sha = Sha512::new();
+    let key = SigningKey::from_seed(&mut bignum, &mut sha, &SEED);
+    let prehash: [u8; 64] = Sha512::digest(b"abc").into();
+
+    let (cycles, signature) =
+        hal::count_cycles(|| key.sign_prehashed(&mut bignum, &mut sha, &prehash, None));
+    let signature = signature.unwrap();
+    assert_eq!(signature.to_bytes(), SIGNATURE);
+    hprintln!("signing took {} cycles", cycles);
+
+    let (cycles, result) = hal::count_cycles(|| {
+        key.verifying_key()
+            .verify_prehashed(&mut bignum, &mut sha, &prehash, None, &signature)
+    });
+    result.unwrap();
+    hprintln!("verification took {} cycles", cycles);
+
+    let alice = x25519(&mut bignum, &ALICE, &X25519_BASEPOINT);
+    let bob = x25519(&mut bignum, &BOB, &X25519_BASEPOINT);
+    let (cycles, shared) = hal::count_cycles(|| x25519(&mut bignum, &ALICE, &bob));
+    assert_eq!(shared, SHARED);
+    assert_eq!(x25519(&mut bignum, &BOB, &alice), SHARED);
+    hprintln!("X25519 took {} cycles", cycles);
+
+    hprintln!("all done");
+    loop {
+        continue;
+    }
+}
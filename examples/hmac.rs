@@ -0,0 +1,128 @@
+#![no_main]
+#![no_std]
+
+///
+/// Checks HMAC-SHA256 against RFC 4231 and HKDF-SHA256 against RFC 5869.
+///
+extern crate panic_semihosting;
+use cortex_m_rt::entry;
+
+use cortex_m_semihosting::hprintln;
+
+use hal::drivers::{HkdfSha256, HmacSha256};
+use hal::traits::digest::Mac;
+use lpc55_hal as hal;
+
+// RFC 4231, test cases 1, 2, 6 and 7: (key, data, HMAC-SHA256)
+const HMAC_VECTORS: [(&[u8], &[u8], [u8; 32]); 4] = [
+    (
+        &[0x0b; 20],
+        b"Hi There",
+        [
+            0xb0, 0x34, 0x4c, 0x61, 0xd8, 0xdb, 0x38, 0x53, 0x5c, 0xa8, 0xaf, 0xce, 0xaf, 0x0b,
+            0xf1, 0x2b, 0x88, 0x1d, 0xc2, 0x00, 0xc9, 0x83, 0x3d, 0xa7, 0x26, 0xe9, 0x37, 0x6c,
+            0x2e, 0x32, 0xcf, 0xf7,
+        ],
+    ),
+    (
+        b"Jefe",
+        b"what do ya want for nothing?",
+        [
+            0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95,
+            0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9,
+            0x64, 0xec, 0x38, 0x43,
+        ],
+    ),
+    (
+        &[0xaa; 131],
+        b"Test Using Larger Than Block-Size Key - Hash Key First",
+        [
+            0x60, 0xe4, 0x31, 0x59, 0x1e, 0xe0, 0xb6, 0x7f, 0x0d, 0x8a, 0x26, 0xaa, 0xcb, 0xf5,
+            0xb7, 0x7f, 0x8e, 0x0b, 0xc6, 0x21, 0x37, 0x28, 0xc5, 0x14, 0x05, 0x46, 0x04, 0x0f,
+            0x0e, 0xe3, 0x7f, 0x54,
+        ],
+    ),
+    (
+        &[0xaa; 131],
+        b"This is a test using a larger than block-size key and a larger than block-size data. \
+          The key needs to be hashed before being used by the HMAC algorithm.",
+        [
+            0x9b, 0x09, 0xff, 0xa7, 0x1b, 0x94, 0x2f, 0xcb, 0x27, 0x63, 0x5f, 0xbc, 0xd5, 0xb0,
+            0xe9, 0x44, 0xbf, 0xdc, 0x63, 0x64, 0x4f, 0x07, 0x13, 0x93, 0x8a, 0x7f, 0x51, 0x53,
+            0x5c, 0x3a, 0x35, 0xe2,
+        ],
+    ),
+];
+
+// RFC 5869, test case 1
+const HKDF_SALT: [u8; 13] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c,
+];
+const HKDF_INFO: [u8; 10] = [0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9];
+const HKDF_PRK: [u8; 32] = [
+    0x07, 0x77, 0x09, 0x36, 0x2c, 0x2e, 0x32, 0xdf, 0x0d, 0xdc, 0x3f, 0x0d, 0xc4, 0x7b, 0xba, 0x63,
+    0x90, 0xb6, 0xc7, 0x3b, 0xb5, 0x0f, 0x9c, 0x31, 0x22, 0xec, 0x84, 0x4a, 0xd7, 0xc2, 0xb3, 0xe5,
+];
+const HKDF_OKM: [u8; 42] = [
+    0x3c, 0xb2, 0x5f, 0x25, 0xfa, 0xac, 0xd5, 0x7a, 0x90, 0x43, 0x4f, 0x64, 0xd0, 0x36, 0x2f, 0x2a,
+    0x2d, 0x2d, 0x0a, 0x90, 0xcf, 0x1a, 0x5a, 0x4c, 0x5d, 0xb0, 0x2d, 0x56, 0xec, 0xc4, 0xc5, 0xbf,
+    0x34, 0x00, 0x72, 0x08, 0xd5, 0xb8, 0x87, 0x18, 0x58, 0x65,
+];
+
+// RFC 5869, test case 3 (no salt, no info)
+const HKDF_OKM_3: [u8; 42] = [
+    0x8d, 0xa4, 0xe7, 0x75, 0xa5, 0x63, 0xc1, 0x8f, 0x71, 0x5f, 0x80, 0x2a, 0x06, 0x3c, 0x5a, 0x31,
+    0xb8, 0xa1, 0x1f, 0x5c, 0x5e, 0xe1, 0x87, 0x9e, 0xc3, 0x45, 0x4e, 0x5f, 0x3c, 0x73, 0x8d, 0x2d,
+    0x9d, 0x20, 0x13, 0x95, 0xfa, 0xa4, 0xb6, 0x1a, 0x96, 0xc8,
+];
+
+#[entry]
+fn main() -> ! {
+    let dp = hal::raw::Peripherals::take().unwrap();
+    let mut syscon = hal::Syscon::from(dp.SYSCON);
+    let mut hashcrypt = hal::Hashcrypt::from(dp.HASHCRYPT).enabled(&mut syscon);
+
+    for (key, data, tag) in HMAC_VECTORS.iter() {
+        let mut mac = hashcrypt.hmac_sha256(key);
+        mac.update(data);
+        assert_eq!(mac.finalize().into_bytes().as_slice(), tag);
+
+        // twice, with the same key
+        let mut mac = hashcrypt.hmac_sha256(key);
+        mac.update(data);
+        mac.verify_slice_reset(tag).unwrap();
+        mac.update(data);
+        let key = mac.key();
+        mac.verify_slice(tag).unwrap();
+
+        // and once more, with the prepared key
+        let mut mac = HmacSha256::with_key(&mut hashcrypt, &key);
+        mac.update(data);
+        mac.verify_slice(tag).unwrap();
+    }
+    hprintln!("HMAC-SHA256 ok");
+
+    let (prk, mut hkdf) = HkdfSha256::extract(&mut hashcrypt, Some(&HKDF_SALT), &[0x0b; 22]);
+    assert_eq!(prk.as_slice(), &HKDF_PRK);
+    let mut okm = [0u8; 42];
+    hkdf.expand(&HKDF_INFO, &mut okm).unwrap();
+    assert_eq!(okm, HKDF_OKM);
+
+    let mut hkdf = HkdfSha256::from_prk(&mut hashcrypt, &HKDF_PRK).unwrap();
+    let mut okm = [0u8; 42];
+    hkdf.expand(&HKDF_INFO, &mut okm).unwrap();
+    assert_eq!(okm, HKDF_OKM);
+
+    let mut okm = [0u8; 42];
+    hashcrypt
+        .hkdf_sha256(None, &[0x0b; 22])
+        .expand(&[], &mut okm)
+        .unwrap();
+    assert_eq!(okm, HKDF_OKM_3);
+    hprintln!("HKDF-SHA256 ok");
+
+    hprintln!("all done");
+    loop {
+        continue;
+    }
+}
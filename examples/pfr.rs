@@ -62,17 +62,19 @@ fn main() -> ! {
     dump_hex!(hal::uuid(), 16);
     heprintln!("chip revision: {}", hal::chip_revision()).ok();
     let mut pfr = hal.pfr.enabled(&clocks).unwrap();
-    let mut cfpa = pfr.read_latest_cfpa().unwrap();
+    let cfpa = pfr.read_latest_cfpa().unwrap();
     heprintln!("CFPA:").ok();
     dump_cfpa(&cfpa);
 
     heprintln!("Increment the version and write back cfpa!").ok();
-    cfpa.version = cfpa.version + 1;
-    cfpa.secure_fw_version += 1;
-    cfpa.ns_fw_version += 1;
-    // increment a byte of customer data (with overflow)
-    cfpa.customer_data[0] = ((1 + (cfpa.customer_data[0] as u16)) & 0xff) as u8;
-    pfr.write_cfpa(&cfpa).unwrap();
+    let cfpa = pfr
+        .update_cfpa(None, |cfpa| {
+            cfpa.secure_fw_version += 1;
+            cfpa.ns_fw_version += 1;
+            // increment a byte of customer data (with overflow)
+            cfpa.customer_data[0] = cfpa.customer_data[0].wrapping_add(1);
+        })
+        .unwrap();
 
     heprintln!("Rerun this program and check that Version, firmware versions, and custom data byte all increment.").ok();
 
@@ -82,6 +84,13 @@ fn main() -> ! {
     heprintln!("usb.vid = {:08X}", cmpa.usb_vid).ok();
     heprintln!("usb.pid = {:08X}", cmpa.usb_pid).ok();
     heprintln!("secure_boot_cfg = {:08X}", cmpa.secure_boot_cfg).ok();
+    heprintln!("isp mode = {:?}", cmpa.boot_config().isp_mode()).ok();
+    heprintln!(
+        "secure boot = {}",
+        cmpa.secure_boot_config().secure_boot_enabled()
+    )
+    .ok();
+    heprintln!("debug policy = {:?}", cmpa.debug_policy()).ok();
     dump_hex!(cmpa.rotkh, cmpa.rotkh.len());
     dump_hex!(cfpa.customer_data, 10);
     dump_hex!(cmpa.customer_data, cmpa.customer_data.len());
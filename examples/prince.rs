@@ -7,6 +7,10 @@ use cortex_m_rt::entry;
 #[allow(unused_imports)]
 use cortex_m_semihosting::{dbg, hprint, hprintln};
 
+use hal::peripherals::{
+    pfr::KeyType,
+    prince::{Layout, Region},
+};
 use hal::prelude::*;
 use lpc55_hal as hal;
 
@@ -31,7 +35,7 @@ fn main() -> ! {
     // prince region 2 (128KB)
     const DATA_ADDR: usize = 0x00080000 + 0;
 
-    let _clocks = hal::ClockRequirements::default()
+    let clocks = hal::ClockRequirements::default()
         .system_frequency(12.MHz())
         .configure(&mut anactrl, &mut pmc, &mut syscon)
         .unwrap();
@@ -41,16 +45,31 @@ fn main() -> ! {
 
     let mut rng = hal.rng.enabled(&mut syscon);
 
-    let mut prince = hal.prince.enabled(&mut rng);
+    let mut prince = hal.prince.enabled(&mut rng, &Layout::default());
 
-    prince.enable_all_region_2();
+    // the PRINCE key of region 2, as provisioned in the keystore
+    let mut pfr = hal.pfr.enabled(&clocks).unwrap();
+    let puf = hal
+        .puf
+        .enabled(&mut syscon)
+        .unwrap()
+        .try_start_from_keystore(&mut pfr)
+        .unwrap();
+    let key_code = pfr.read_key_code(KeyType::PrinceRegion2).unwrap();
+    let key = puf.load_prince_key(Region::Region2, &key_code).unwrap();
+
+    // only the first 8KB sub-region of region 2
+    let mut guard = prince
+        .enable_range(&key, DATA_ADDR..DATA_ADDR + 1024)
+        .unwrap();
+    assert_eq!(guard.region(), Region::Region2);
 
     hprintln!("writing AA's to flash data.").ok();
 
     flash.erase_page((DATA_ADDR / 512) + 0).unwrap();
     flash.erase_page((DATA_ADDR / 512) + 1).unwrap();
 
-    prince.write_encrypted(|_prince| {
+    guard.write_encrypted(|| {
         let vector = [0xAA; 1024];
         flash.write(DATA_ADDR, &vector).unwrap();
     });
@@ -66,7 +85,7 @@ fn main() -> ! {
     dump_hex!(&buf[0..32]);
 
     // Turn off PRINCE.
-    prince.disable_all_region_2();
+    drop(guard);
 
     for i in 0..buf.len() {
         let ptr = DATA_ADDR as *const u8;
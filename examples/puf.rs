@@ -76,7 +76,7 @@ fn main() -> ! {
 
         dbg!("Generate 2 IP-direct keys, and 2 normal keys.");
         puf_enrolled.generate_key(256, 0, &mut kc1).unwrap();
-        puf_enrolled.generate_key(256, 0, &mut kc2).unwrap();
+        puf_enrolled.generate_key(128, 0, &mut kc2).unwrap();
         puf_enrolled.generate_key(256, 1, &mut kc3).unwrap();
         puf_enrolled.generate_key(256, 2, &mut kc4).unwrap();
 
@@ -160,30 +160,14 @@ fn main() -> ! {
 
         dbg!("Loading AES and Prince Keys..");
         // Load into AES IP, and Prince IP for 3 address regions
-        puf_started
-            .get_key(hal::raw::puf::keyenable::KEY_A::AES, &kc1, &mut [0u8; 0])
-            .unwrap();
-        puf_started
-            .get_key(
-                hal::raw::puf::keyenable::KEY_A::PRINCE0,
-                &kc2,
-                &mut [0u8; 0],
-            )
-            .unwrap();
-        puf_started
-            .get_key(
-                hal::raw::puf::keyenable::KEY_A::PRINCE1,
-                &kc2,
-                &mut [0u8; 0],
-            )
-            .unwrap();
-        puf_started
-            .get_key(
-                hal::raw::puf::keyenable::KEY_A::PRINCE2,
-                &kc2,
-                &mut [0u8; 0],
-            )
-            .unwrap();
+        let _aes_key = puf_started.load_aes_key(&kc1).unwrap();
+        for region in [
+            hal::peripherals::prince::Region::Region0,
+            hal::peripherals::prince::Region::Region1,
+            hal::peripherals::prince::Region::Region2,
+        ] {
+            puf_started.load_prince_key(region, &kc2).unwrap();
+        }
 
         dbg!("Loading SW Keys..");
         let mut key1 = [0u8; 32];
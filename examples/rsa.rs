@@ -0,0 +1,105 @@
+#![no_main]
+#![no_std]
+
+///
+/// Verifies RSA-2048 signatures (PKCS #1 v1.5 and PSS with SHA-256) on CASPER, with
+/// the hashes on HASHCRYPT, and times them.
+///
+extern crate panic_semihosting;
+use cortex_m_rt::entry;
+
+use cortex_m_semihosting::hprintln;
+
+use hal::drivers::{casper::Bignum, RsaPublicKey};
+use lpc55_hal as hal;
+
+const MESSAGE: &[u8] = b"firmware image";
+
+const MODULUS: [u8; 256] = [
+    0xb5, 0x08, 0x3f, 0x07, 0xa7, 0xa4, 0x26, 0xee, 0x13, 0x4c, 0x63, 0xaf, 0x82, 0x9f, 0xe0, 0x0d,
+    0xba, 0x7f, 0xda, 0x5e, 0x3d, 0x42, 0x16, 0xaf, 0x3f, 0x2c, 0x41, 0xe6, 0xf0, 0xb4, 0x32, 0x68,
+    0x66, 0xb6, 0x0e, 0x9d, 0x52, 0xec, 0xb5, 0x4c, 0x3e, 0xd2, 0x95, 0x5e, 0xa7, 0xde, 0xf9, 0x05,
+    0xfa, 0xc1, 0xf3, 0x16, 0x66, 0x40, 0x7b, 0x86, 0x39, 0x7a, 0x2c, 0xc8, 0x03, 0xd4, 0xf1, 0x84,
+    0x27, 0xbc, 0x6c, 0x01, 0x77, 0x74, 0x37, 0x7e, 0x6c, 0xa9, 0x2e, 0xac, 0x6a, 0x71, 0x4a, 0x57,
+    0xee, 0x7d, 0x17, 0x42, 0x15, 0xaa, 0xa1, 0x12, 0x44, 0x1e, 0x78, 0x26, 0x22, 0x89, 0xc7, 0x21,
+    0xd3, 0xe7, 0xba, 0x89, 0x5f, 0x6d, 0xbb, 0xad, 0xc9, 0xca, 0xe1, 0xcd, 0xac, 0xc4, 0x4b, 0xa8,
+    0x76, 0x44, 0xaa, 0x4c, 0xe1, 0x5f, 0xf6, 0xc1, 0x7a, 0xf4, 0x5b, 0x65, 0x99, 0xf5, 0x86, 0xd8,
+    0xa6, 0x08, 0xaf, 0x4b, 0x7a, 0x06, 0x31, 0x40, 0xac, 0x01, 0xec, 0xbc, 0x44, 0x61, 0xd2, 0x66,
+    0x15, 0x85, 0xe5, 0x1e, 0x8f, 0x23, 0x7f, 0x6d, 0x82, 0x70, 0x84, 0xe8, 0x98, 0x49, 0x7b, 0xab,
+    0x81, 0x20, 0x2f, 0x18, 0x01, 0x84, 0xc6, 0x12, 0xeb, 0x36, 0x2f, 0x96, 0xfb, 0x7e, 0x61, 0xa2,
+    0x75, 0xc5, 0xa1, 0x58, 0x5d, 0x52, 0xf0, 0x21, 0x16, 0xc7, 0x34, 0x26, 0xdb, 0xf2, 0x4b, 0x8f,
+    0xed, 0xb0, 0x1e, 0xbf, 0x5a, 0x66, 0x52, 0x57, 0x7e, 0x5d, 0xd9, 0x01, 0x1d, 0x85, 0x5f, 0x4c,
+    0x0d, 0x7e, 0x1d, 0xf7, 0xfc, 0x64, 0x3b, 0x92, 0x03, 0x61, 0xae, 0xc2, 0xa9, 0xba, 0xe5, 0xd8,
+    0x00, 0xa2, 0x51, 0xab, 0x20, 0xd8, 0x80, 0xc4, 0xa5, 0x64, 0x04, 0x11, 0x1e, 0x36, 0xb9, 0x9f,
+    0x90, 0x18, 0x9e, 0xd1, 0xc4, 0x93, 0xb9, 0x9a, 0x73, 0xa7, 0x23, 0xe5, 0x72, 0x21, 0x2f, 0xe7,
+];
+
+const PKCS1V15: [u8; 256] = [
+    0x71, 0x94, 0x47, 0x13, 0x98, 0x26, 0x7f, 0x73, 0x24, 0x56, 0x57, 0xb2, 0xc4, 0xe2, 0xf0, 0xa7,
+    0x65, 0x15, 0xe6, 0xa8, 0x41, 0x16, 0xa6, 0x15, 0xaa, 0xf6, 0xf0, 0x30, 0x1a, 0x5e, 0x53, 0x86,
+    0x1a, 0xdb, 0xd6, 0x3c, 0x09, 0xc5, 0xbe, 0x95, 0x89, 0x46, 0xe5, 0x85, 0xbf, 0xb2, 0x6d, 0x48,
+    0xd9, 0x31, 0x24, 0x85, 0x4c, 0xe2, 0xd4, 0x34, 0xa0, 0x66, 0xe0, 0xae, 0x92, 0xbd, 0xcc, 0x80,
+    0xbd, 0x42, 0x07, 0x36, 0xb4, 0x6c, 0x30, 0x0a, 0xa3, 0x04, 0xe6, 0xa9, 0x27, 0x82, 0xb3, 0x69,
+    0x4a, 0x2c, 0x33, 0x99, 0x31, 0x42, 0xb6, 0x29, 0xbb, 0xd3, 0x44, 0xdb, 0x3e, 0xa9, 0x56, 0x44,
+    0x8d, 0x8b, 0x62, 0xaf, 0xe6, 0xe7, 0xaf, 0x07, 0xb9, 0x3e, 0x50, 0x69, 0x65, 0x4b, 0x37, 0x10,
+    0x0e, 0xbe, 0x36, 0x35, 0x63, 0xbd, 0xe5, 0xff, 0x20, 0xbf, 0x72, 0x4c, 0x3e, 0x3f, 0x87, 0x82,
+    0x91, 0x16, 0xab, 0xa9, 0x4e, 0x29, 0x4a, 0x68, 0xdf, 0xa8, 0x25, 0xfe, 0x4f, 0xc6, 0x44, 0x15,
+    0x66, 0x6c, 0x7b, 0x99, 0xf6, 0x55, 0x1c, 0x1a, 0x16, 0xec, 0xe7, 0x23, 0xb5, 0xba, 0xc5, 0xd0,
+    0x38, 0xe4, 0x16, 0x94, 0x28, 0xc2, 0xb9, 0xc4, 0x10, 0xbc, 0x9d, 0x53, 0x60, 0xf2, 0x5d, 0x78,
+    0x0a, 0xd1, 0x92, 0x84, 0x0d, 0x02, 0xa6, 0xa0, 0xb1, 0xad, 0xf1, 0xed, 0x8a, 0x9a, 0x38, 0x1f,
+    0xdd, 0x25, 0x8d, 0x5b, 0x3f, 0x57, 0x5f, 0xdb, 0xcc, 0xd7, 0xaf, 0x90, 0x50, 0xd4, 0x18, 0xbf,
+    0xbe, 0xb6, 0x7c, 0x88, 0xf6, 0x87, 0x81, 0xaf, 0xbe, 0x3f, 0x76, 0x96, 0xf0, 0x94, 0xc5, 0x36,
+    0x25, 0x27, 0x06, 0x64, 0x53, 0xf1, 0xc0, 0x8d, 0xe0, 0x65, 0xf2, 0x71, 0x7d, 0x07, 0xc0, 0x51,
+    0xf4, 0x24, 0x22, 0x79, 0x78, 0xfe, 0x9f, 0x4f, 0x48, 0x78, 0x1c, 0xa1, 0x87, 0xb4, 0xf7, 0x8b,
+];
+
+// random 32 byte salt
+const PSS: [u8; 256] = [
+    0x24, 0xc3, 0xc2, 0x82, 0xf1, 0xb8, 0xdd, 0x29, 0x86, 0xdd, 0xa8, 0xea, 0x29, 0xd1, 0x70, 0xda,
+    0x70, 0xb1, 0xf0, 0xa6, 0x1f, 0x05, 0x71, 0x71, 0xae, 0xe9, 0xaa, 0xfe, 0x3e, 0xe3, 0xac, 0xcd,
+    0xdc, 0x88, 0xae, 0x70, 0xf5, 0xeb, 0x67, 0x55, 0xe3, 0x4a, 0x74, 0x91, 0xdb, 0xce, 0xa9, 0xb8,
+    0xd0, 0x9f, 0x35, 0x10, 0xf3, 0x34, 0xfc, 0x1c, 0xc8, 0x97, 0xbe, 0x33, 0xa9, 0x1c, 0x26, 0x91,
+    0x70, 0x6b, 0x9a, 0x16, 0x43, 0xee, 0xcc, 0xf0, 0xd0, 0xa2, 0xfe, 0xa3, 0x17, 0xa1, 0x57, 0x5d,
+    0x3a, 0xce, 0x03, 0x90, 0x2b, 0x01, 0x3b, 0x43, 0xcf, 0xcc, 0x47, 0x5d, 0xef, 0x3b, 0x17, 0x27,
+    0x0e, 0x1f, 0xbd, 0xb6, 0x15, 0x00, 0x9b, 0x2b, 0xa6, 0x34, 0xd6, 0xe3, 0x8e, 0x7b, 0xc5, 0xcc,
+    0x31, 0x90, 0x63, 0xe0, 0x56, 0x1f, 0x41, 0xc5, 0xb3, 0x65, 0xcb, 0xb3, 0xe2, 0xc1, 0x5e, 0x0f,
+    0xd7, 0x5b, 0xb5, 0x1c, 0x95, 0x96, 0x59, 0x10, 0xf6, 0xb2, 0x96, 0x60, 0xb7, 0x80, 0x32, 0x9f,
+    0x38, 0xe0, 0x4a, 0x2c, 0xa8, 0x90, 0x74, 0x95, 0x29, 0x53, 0xf3, 0x79, 0x07, 0xb0, 0xb0, 0xb1,
+    0xbb, 0x7a, 0xf2, 0xf1, 0x94, 0x51, 0x7a, 0xe7, 0x2f, 0x91, 0x27, 0xf6, 0x61, 0xc3, 0xef, 0x8a,
+    0xc6, 0x7c, 0x8c, 0xed, 0x74, 0x2f, 0x46, 0x6d, 0x69, 0x80, 0xe8, 0xcd, 0xdf, 0x3d, 0x8f, 0x44,
+    0x58, 0xb4, 0x55, 0x84, 0x09, 0x72, 0x05, 0x40, 0x66, 0xc9, 0x2a, 0xbe, 0x6c, 0x17, 0x37, 0xac,
+    0xf9, 0xe0, 0xf2, 0xe5, 0x62, 0xac, 0x40, 0xcb, 0xa5, 0x4f, 0x8b, 0xc4, 0x78, 0xac, 0xb2, 0xb5,
+    0x24, 0x52, 0x83, 0x09, 0xad, 0xff, 0x9a, 0x56, 0x69, 0x85, 0x86, 0x71, 0x60, 0xaf, 0x07, 0x9b,
+    0x0d, 0xf8, 0x21, 0xf7, 0x5d, 0x60, 0x3e, 0xdc, 0xb5, 0x97, 0x50, 0x8e, 0x44, 0xe3, 0x58, 0x86,
+];
+
+#[entry]
+fn main() -> ! {
+    let dp = hal::raw::Peripherals::take().unwrap();
+    let mut syscon = hal::Syscon::from(dp.SYSCON);
+    let mut hashcrypt = hal::Hashcrypt::from(dp.HASHCRYPT).enabled(&mut syscon);
+    let mut casper = hal::Casper::from(dp.CASPER).enabled(&mut syscon);
+
+    hal::enable_cycle_counter();
+
+    let key = RsaPublicKey::new(&MODULUS, 65537).unwrap();
+    let mut bignum = Bignum::new(&mut casper);
+    let mut sha = hashcrypt.sha256();
+
+    let (cycles, result) =
+        hal::count_cycles(|| key.verify_pkcs1v15_sha256(&mut bignum, &mut sha, MESSAGE, &PKCS1V15));
+    result.unwrap();
+    hprintln!("PKCS #1 v1.5 ok, {} cycles", cycles);
+
+    key.verify_pss_sha256(&mut bignum, &mut sha, MESSAGE, Some(32), &PSS)
+        .unwrap();
+    assert!(key
+        .verify_pss_sha256(&mut bignum, &mut sha, b"other image", None, &PSS)
+        .is_err());
+    hprintln!("PSS ok");
+
+    hprintln!("all done");
+    loop {
+        continue;
+    }
+}
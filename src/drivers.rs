@@ -12,18 +12,31 @@ pub mod prelude {
 pub mod aes;
 pub use aes::{Aes, Key as AesKey};
 
+pub mod casper;
+pub use casper::Bignum;
+
 pub mod clocks;
 pub use clocks::ClockRequirements;
 
 pub mod pins;
 pub use pins::{Pin, Pins};
 
+pub mod ecdsa;
+
+pub mod ed25519;
+
 pub mod flash;
 pub use flash::FlashGordon;
 
 pub mod gint;
 pub use gint::GroupInterrupt;
 
+pub mod hkdf;
+pub use hkdf::HkdfSha256;
+
+pub mod hmac;
+pub use hmac::{HmacKey, HmacSha256};
+
 pub mod i2c;
 pub use i2c::I2cMaster;
 
@@ -38,8 +51,11 @@ pub use serial::Serial;
 
 pub mod rng;
 
+pub mod rsa;
+pub use rsa::RsaPublicKey;
+
 pub mod sha;
-pub use sha::{Sha1, Sha256};
+pub use sha::{Sha1, Sha256, SharedSha1, SharedSha256};
 
 pub mod usbd;
 pub use usbd::UsbBus;
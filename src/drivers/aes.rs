@@ -1,13 +1,17 @@
-use core::convert::TryInto;
+use core::{convert::TryInto, marker::PhantomData};
 
 use crate::traits::aligned::{Aligned, A4};
 
 use crate::{
-    peripherals::hashcrypt::Hashcrypt,
+    peripherals::{hashcrypt::Hashcrypt, puf::AesKeyLoaded},
     traits::{
-        cipher::{Block, BlockCipher, BlockDecrypt, BlockEncrypt},
+        cipher::{
+            inout::{InOut, InOutBuf},
+            Block, BlockBackend, BlockCipher, BlockClosure, BlockDecrypt, BlockEncrypt, ParBlocks,
+            ParBlocksSizeUser,
+        },
         digest::generic_array::{
-            typenum::{U1, U16, U24, U32},
+            typenum::{U16, U24, U32, U8},
             GenericArray,
         },
     },
@@ -26,28 +30,64 @@ mod sealed {
 }
 
 use cipher::BlockSizeUser;
-use sealed::KeySize;
+pub(crate) use sealed::KeySize;
+
+mod aead;
+pub use self::aead::{Aes128Gcm, Aes256Gcm, AesCcm, AesGcm, CcmNonceSize, CcmTagSize};
+
+pub mod icb;
+pub use icb::IcbAes;
+
+mod modes;
+pub use modes::{CbcDecrypt, CbcEncrypt, CfbDecrypt, CfbEncrypt, Ctr, CtrCore, Ofb, OfbCore};
 
 #[derive(Clone, Debug, PartialEq)]
-pub enum Key<Size: KeySize> {
-    Puf,
+pub enum Key<'a, Size: KeySize> {
+    /// The hidden key loaded into AES by the PUF, see `Key::puf`
+    Puf(PufKey<'a, Size>),
     User(GenericArray<u8, Size>),
 }
 
+/// A hidden PUF key of `Size`. The PUF only loads 256-bit keys into AES
+/// (`Puf::load_aes_key`), so it can only be constructed as `Aes256Key::puf`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PufKey<'a, Size: KeySize> {
+    _loaded: &'a AesKeyLoaded<'a>,
+    _size: PhantomData<Size>,
+}
+
+impl<'a> Key<'a, U32> {
+    pub fn puf(loaded: &'a AesKeyLoaded<'a>) -> Self {
+        Key::Puf(PufKey {
+            _loaded: loaded,
+            _size: PhantomData,
+        })
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Mode {
     Encrypt,
     Decrypt,
 }
 
-pub type Aes128Key = Key<U16>;
-pub type Aes192Key = Key<U24>;
-pub type Aes256Key = Key<U32>;
+/// Chaining done by the peripheral itself, see `modes` for the others.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Chaining {
+    Ecb,
+    Cbc,
+    Ctr,
+    Icb(icb::CounterSize, icb::StreamSize),
+}
+
+pub type Aes128Key<'a> = Key<'a, U16>;
+pub type Aes192Key<'a> = Key<'a, U24>;
+pub type Aes256Key<'a> = Key<'a, U32>;
 
 // pub struct Aes<'a, Key: KeySize> {
 pub struct Aes<'a, Size: KeySize> {
     inner: &'a mut Hashcrypt<Enabled>,
-    key: Key<Size>,
+    key: Key<'a, Size>,
 }
 
 pub type Aes128<'a> = Aes<'a, U16>;
@@ -56,7 +96,7 @@ pub type Aes256<'a> = Aes<'a, U32>;
 
 impl<'a, Size: KeySize> Aes<'a, Size> {
     /// New AES struct implementing `block-cipher`.
-    pub fn new(hashcrypt: &'a mut Hashcrypt<Enabled>, key: Key<Size>, mode: Mode) -> Self {
+    pub fn new(hashcrypt: &'a mut Hashcrypt<Enabled>, key: Key<'a, Size>, mode: Mode) -> Self {
         let aes = Self {
             inner: hashcrypt,
             key,
@@ -66,15 +106,19 @@ impl<'a, Size: KeySize> Aes<'a, Size> {
     }
 
     /// New AES starting in decryption mode
-    pub fn new_decrypt(hashcrypt: &'a mut Hashcrypt<Enabled>, key: Key<Size>) -> Self {
+    pub fn new_decrypt(hashcrypt: &'a mut Hashcrypt<Enabled>, key: Key<'a, Size>) -> Self {
         Self::new(hashcrypt, key, Mode::Decrypt)
     }
 
     /// New AES starting in encryption mode
-    pub fn new_encrypt(hashcrypt: &'a mut Hashcrypt<Enabled>, key: Key<Size>) -> Self {
+    pub fn new_encrypt(hashcrypt: &'a mut Hashcrypt<Enabled>, key: Key<'a, Size>) -> Self {
         Self::new(hashcrypt, key, Mode::Encrypt)
     }
 
+    pub fn into_inner(self) -> &'a mut Hashcrypt<Enabled> {
+        self.inner
+    }
+
     /// Optionally, configure peripheral for decryption ahead of time.
     pub fn prime_for_decryption(&self) {
         self.configure(Mode::Encrypt);
@@ -90,14 +134,30 @@ impl<'a, Size: KeySize> Aes<'a, Size> {
     // this, which besides the context-switching cost would avoid having to store the
     // key inside the struct.
     fn configure(&self, mode: Mode) {
+        self.configure_chained(mode, Chaining::Ecb, None);
+    }
+
+    // For CBC and CTR, the peripheral keeps the chaining value resp. counter
+    // (starting from `iv`) across blocks, until it is configured anew.
+    fn configure_chained(&self, mode: Mode, chaining: Chaining, iv: Option<&Block<Self>>) {
         //
         // CRYPTCFG
         //
 
         self.cryptcfg.write(|w| {
-            let mut w = w
-                .aesmode()
-                .ecb()
+            let mut w = match chaining {
+                Chaining::Ecb => w.aesmode().ecb(),
+                Chaining::Cbc => w.aesmode().cbc(),
+                Chaining::Ctr => w.aesmode().ctr(),
+                Chaining::Icb(counter_size, stream_size) => w
+                    .aesmode()
+                    .ecb()
+                    .icbsz()
+                    .variant(counter_size.into())
+                    .icbstrm()
+                    .variant(stream_size.into()),
+            };
+            w = w
                 .msw1st_out()
                 .set_bit()
                 .swapkey()
@@ -113,7 +173,7 @@ impl<'a, Size: KeySize> Aes<'a, Size> {
             }
 
             match self.key {
-                Key::Puf => w = w.aessecret().hidden_way(),
+                Key::Puf(_) => w = w.aessecret().hidden_way(),
                 _ => w = w.aessecret().normal_way(),
             }
 
@@ -132,14 +192,17 @@ impl<'a, Size: KeySize> Aes<'a, Size> {
         //
 
         self.ctrl.write(|w| w.new_hash().start());
-        self.ctrl.write(|w| w.new_hash().start().mode().aes());
+        match chaining {
+            Chaining::Icb(..) => self.ctrl.write(|w| w.new_hash().start().mode().icb_aes()),
+            _ => self.ctrl.write(|w| w.new_hash().start().mode().aes()),
+        }
 
         //
         // KEY
         //
 
         match &self.key {
-            Key::Puf => {
+            Key::Puf(_) => {
                 // fsl driver "waits" a bit here
                 while self.status.read().bits() == 0 {
                     continue;
@@ -159,61 +222,130 @@ impl<'a, Size: KeySize> Aes<'a, Size> {
         }
 
         assert!(self.status.read().needkey().is_not_need());
+
+        //
+        // IV
+        //
+
+        if let Some(iv) = iv {
+            let iv: Aligned<A4, Block<Self>> = Aligned(*iv);
+            self.indata
+                .write(|w| unsafe { w.bits(u32::from_le_bytes(iv[..4].try_into().unwrap())) });
+            for (i, chunk) in iv[4..].chunks(4).enumerate() {
+                self.alias[i]
+                    .write(|w| unsafe { w.bits(u32::from_le_bytes(chunk.try_into().unwrap())) });
+            }
+        }
+
+        assert!(self.status.read().neediv().is_not_need());
     }
 
     fn one_block(&self, block: &mut Block<Self>) {
-        // needs to be word-aligned
-        let aligned_block: Aligned<A4, Block<Self>> = Aligned(block.clone());
-        let addr: u32 = &aligned_block as *const _ as _;
+        self.blocks(InOutBuf::from_mut(block));
+    }
 
-        self.memaddr.write(|w| unsafe { w.bits(addr) });
+    // The engine fetches the input blocks itself, as many per transfer as possible,
+    // while each output block is read as soon as it is ready.
+    fn blocks(&self, blocks: InOutBuf<'_, '_, Block<Self>>) {
+        let len = blocks.len();
+        let (input, output) = blocks.into_raw();
+
+        // the input needs to be word-aligned
+        if input as usize & 3 == 0 {
+            for offset in (0..len).step_by(MAX_TRANSFER) {
+                let count = core::cmp::min(len - offset, MAX_TRANSFER);
+                unsafe { self.transfer(input.add(offset), output.add(offset), count) };
+            }
+        } else {
+            let mut aligned: Aligned<A4, ParBlocks<Backend<'_, '_, Size>>> =
+                Aligned(Default::default());
+            for offset in (0..len).step_by(aligned.len()) {
+                let count = core::cmp::min(len - offset, aligned.len());
+                for i in 0..count {
+                    aligned[i] = unsafe { *input.add(offset + i) };
+                }
+                unsafe { self.transfer(aligned.as_ptr(), output.add(offset), count) };
+            }
+        }
+    }
+
+    // `input` must be word-aligned, `output` may be the same as `input`:
+    // an output block is only written once its input has been consumed.
+    unsafe fn transfer(&self, input: *const Block<Self>, output: *mut Block<Self>, count: usize) {
+        self.memaddr.write(|w| w.bits(input as u32));
         self.memctrl
-            .write(|w| unsafe { w.master().enabled().count().bits(1) });
+            .write(|w| w.master().enabled().count().bits(count as u16));
 
-        while self.status.read().digest().is_not_ready() {
-            continue;
-        }
+        for i in 0..count {
+            while self.status.read().digest().is_not_ready() {
+                continue;
+            }
 
-        for i in 0..4 {
-            block.as_mut_slice()[4 * i..4 * i + 4]
-                .copy_from_slice(&self.digest0[i].read().bits().to_be_bytes());
+            let block = &mut *output.add(i);
+            for j in 0..4 {
+                block[4 * j..4 * j + 4]
+                    .copy_from_slice(&self.digest0[j].read().bits().to_be_bytes());
+            }
         }
     }
 }
 
+// `MEMCTRL.COUNT` is 11 bits
+const MAX_TRANSFER: usize = 0x7ff;
+
+/// `BlockBackend` for the block cipher traits, processing several blocks per transfer
+/// with the key as configured.
+struct Backend<'b, 'a, Size: KeySize>(&'b Aes<'a, Size>);
+
+impl<Size: KeySize> BlockSizeUser for Backend<'_, '_, Size> {
+    type BlockSize = U16;
+}
+
+impl<Size: KeySize> ParBlocksSizeUser for Backend<'_, '_, Size> {
+    type ParBlocksSize = U8;
+}
+
+impl<Size: KeySize> BlockBackend for Backend<'_, '_, Size> {
+    fn proc_block(&mut self, block: InOut<'_, '_, Block<Self>>) {
+        let (input, output) = block.into_raw();
+        self.0
+            .blocks(unsafe { InOutBuf::from_raw(input, output, 1) });
+    }
+
+    fn proc_par_blocks(&mut self, blocks: InOut<'_, '_, ParBlocks<Self>>) {
+        self.0.blocks(blocks.into_buf());
+    }
+
+    fn proc_tail_blocks(&mut self, blocks: InOutBuf<'_, '_, Block<Self>>) {
+        self.0.blocks(blocks);
+    }
+}
+
 // the `block-cipher` traits
 
 impl<'a, Size: KeySize> BlockSizeUser for Aes<'a, Size> {
-    type BlockSize = Size;
+    type BlockSize = U16;
 }
 
 impl<'a, Size: KeySize> BlockCipher for Aes<'a, Size> {}
 
 impl<'a, Size: KeySize> BlockEncrypt for Aes<'a, Size> {
-    fn encrypt_block(&self, block: &mut Block<Self>) {
+    fn encrypt_with_backend(&self, f: impl BlockClosure<BlockSize = Self::BlockSize>) {
         // unfortunate implementation detail
         if self.cryptcfg.read().aesdecrypt().is_decrypt() {
             self.configure(Mode::Encrypt);
         }
-        self.one_block(block);
-    }
-
-    fn encrypt_with_backend(&self, f: impl cipher::BlockClosure<BlockSize = Self::BlockSize>) {
-        todo!()
+        f.call(&mut Backend(self));
     }
 }
 
 impl<'a, Size: KeySize> BlockDecrypt for Aes<'a, Size> {
-    fn decrypt_block(&self, block: &mut Block<Self>) {
+    fn decrypt_with_backend(&self, f: impl BlockClosure<BlockSize = Self::BlockSize>) {
         // unfortunate implementation detail
         if self.cryptcfg.read().aesdecrypt().is_encrypt() {
             self.configure(Mode::Decrypt);
         }
-        self.one_block(block);
-    }
-
-    fn decrypt_with_backend(&self, f: impl cipher::BlockClosure<BlockSize = Self::BlockSize>) {
-        todo!()
+        f.call(&mut Backend(self));
     }
 }
 
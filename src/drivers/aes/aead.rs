@@ -0,0 +1,635 @@
+//! AES-GCM (NIST SP 800-38D) and AES-CCM (NIST SP 800-38C).
+//!
+//! Both run their counter mode as ECB over software counter blocks, several blocks
+//! per transfer, with the authentication (GHASH resp. CBC-MAC) in software.
+//! As the ciphers borrow the peripheral, there is no `KeyInit`, construct them with a `Key`
+//! (which may also be `Aes256Key::puf`) instead; then use them through the `aead` traits:
+//!
+//! ```ignore
+//! let gcm = aes::Aes128Gcm::new(&mut hashcrypt, key);
+//! let tag = gcm.encrypt_in_place_detached(&nonce, aad, &mut buffer)?;
+//! ```
+
+use core::marker::PhantomData;
+
+use crate::{
+    peripherals::hashcrypt::Hashcrypt,
+    traits::{
+        aead::{
+            consts::{U0, U12, U16},
+            AeadCore, AeadInPlace, Error, Nonce, Tag,
+        },
+        cipher::{Block, BlockEncrypt},
+        digest::generic_array::GenericArray,
+    },
+    typestates::init_state::Enabled,
+};
+
+use super::{Aes, Key, KeySize, Mode};
+
+type AesBlock = Block<Aes<'static, U16>>;
+
+// intention of this module is to prevent users from implementing the CCM sizes
+// for anything other than the valid sizes.
+mod sealed {
+    use crate::traits::aead::consts::{U10, U11, U12, U13, U14, U16, U4, U6, U7, U8, U9};
+    use crate::traits::digest::generic_array::ArrayLength;
+
+    pub trait CcmTagSize: ArrayLength<u8> {}
+
+    impl CcmTagSize for U4 {}
+    impl CcmTagSize for U6 {}
+    impl CcmTagSize for U8 {}
+    impl CcmTagSize for U10 {}
+    impl CcmTagSize for U12 {}
+    impl CcmTagSize for U14 {}
+    impl CcmTagSize for U16 {}
+
+    pub trait CcmNonceSize: ArrayLength<u8> {}
+
+    impl CcmNonceSize for U7 {}
+    impl CcmNonceSize for U8 {}
+    impl CcmNonceSize for U9 {}
+    impl CcmNonceSize for U10 {}
+    impl CcmNonceSize for U11 {}
+    impl CcmNonceSize for U12 {}
+    impl CcmNonceSize for U13 {}
+}
+
+pub use sealed::{CcmNonceSize, CcmTagSize};
+
+// counter blocks encrypted per transfer
+const PAR_BLOCKS: usize = 8;
+
+/// XORs the keystream of the counter blocks following `counter` into `buffer`,
+/// incrementing the last 32 bits of the counter (`inc32` of SP 800-38D).
+fn apply_ctr32<C: BlockEncrypt<BlockSize = U16>>(aes: &C, counter: &AesBlock, buffer: &mut [u8]) {
+    let mut count = u32::from_be_bytes([counter[12], counter[13], counter[14], counter[15]]);
+    let mut keystream = [AesBlock::default(); PAR_BLOCKS];
+
+    for chunk in buffer.chunks_mut(16 * PAR_BLOCKS) {
+        let blocks = chunk.chunks(16).len();
+        for block in keystream[..blocks].iter_mut() {
+            count = count.wrapping_add(1);
+            *block = *counter;
+            block[12..].copy_from_slice(&count.to_be_bytes());
+        }
+        aes.encrypt_blocks(&mut keystream[..blocks]);
+
+        for (bytes, block) in chunk.chunks_mut(16).zip(keystream.iter()) {
+            xor(bytes, block);
+        }
+    }
+}
+
+fn xor(bytes: &mut [u8], other: &[u8]) {
+    for (byte, other) in bytes.iter_mut().zip(other.iter()) {
+        *byte ^= other;
+    }
+}
+
+// does not bail out early
+fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// GHASH, bit by bit without tables, for constant timing.
+struct GHash {
+    h: u128,
+    y: u128,
+}
+
+impl GHash {
+    fn new(h: &AesBlock) -> Self {
+        Self {
+            h: u128::from_be_bytes((*h).into()),
+            y: 0,
+        }
+    }
+
+    // Algorithm 1 of SP 800-38D
+    fn mul(x: u128, y: u128) -> u128 {
+        const R: u128 = 0xe1 << 120;
+        let mut z = 0;
+        let mut v = y;
+        for i in (0..128).rev() {
+            z ^= v & 0u128.wrapping_sub((x >> i) & 1);
+            v = (v >> 1) ^ (R & 0u128.wrapping_sub(v & 1));
+        }
+        z
+    }
+
+    // zero-padded to whole blocks
+    fn update_padded(&mut self, data: &[u8]) {
+        for chunk in data.chunks(16) {
+            let mut block = [0u8; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.y = Self::mul(self.y ^ u128::from_be_bytes(block), self.h);
+        }
+    }
+
+    fn finalize(mut self, aad_len: usize, text_len: usize) -> [u8; 16] {
+        let lengths = ((aad_len as u128 * 8) << 64) | (text_len as u128 * 8);
+        self.y = Self::mul(self.y ^ lengths, self.h);
+        self.y.to_be_bytes()
+    }
+}
+
+// GCM over any AES, the peripheral's or (in the tests) a software one
+struct Gcm<C> {
+    aes: C,
+    h: AesBlock,
+}
+
+// 2^36 - 32 bytes, i.e. 2^32 - 2 blocks
+const GCM_MAX_LEN: u64 = (1 << 36) - 32;
+
+impl<C: BlockEncrypt<BlockSize = U16>> Gcm<C> {
+    fn new(aes: C) -> Self {
+        let mut h = AesBlock::default();
+        aes.encrypt_block(&mut h);
+        Self { aes, h }
+    }
+
+    fn j0(nonce: &GenericArray<u8, U12>) -> AesBlock {
+        let mut j0 = AesBlock::default();
+        j0[..12].copy_from_slice(nonce);
+        j0[15] = 1;
+        j0
+    }
+
+    fn tag(&self, j0: &AesBlock, aad: &[u8], ciphertext: &[u8]) -> GenericArray<u8, U16> {
+        let mut ghash = GHash::new(&self.h);
+        ghash.update_padded(aad);
+        ghash.update_padded(ciphertext);
+        let mut tag = GenericArray::from(ghash.finalize(aad.len(), ciphertext.len()));
+
+        let mut mask = *j0;
+        self.aes.encrypt_block(&mut mask);
+        xor(&mut tag, &mask);
+        tag
+    }
+
+    fn encrypt(
+        &self,
+        nonce: &GenericArray<u8, U12>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<GenericArray<u8, U16>, Error> {
+        if buffer.len() as u64 > GCM_MAX_LEN || associated_data.len() as u64 > GCM_MAX_LEN {
+            return Err(Error);
+        }
+        let j0 = Self::j0(nonce);
+        apply_ctr32(&self.aes, &j0, buffer);
+        Ok(self.tag(&j0, associated_data, buffer))
+    }
+
+    fn decrypt(
+        &self,
+        nonce: &GenericArray<u8, U12>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+        tag: &GenericArray<u8, U16>,
+    ) -> Result<(), Error> {
+        if buffer.len() as u64 > GCM_MAX_LEN || associated_data.len() as u64 > GCM_MAX_LEN {
+            return Err(Error);
+        }
+        let j0 = Self::j0(nonce);
+        // only decrypt authentic ciphertexts
+        if !ct_eq(&self.tag(&j0, associated_data, buffer), tag) {
+            return Err(Error);
+        }
+        apply_ctr32(&self.aes, &j0, buffer);
+        Ok(())
+    }
+}
+
+/// AES-GCM, with 96-bit nonces and 128-bit tags
+pub struct AesGcm<'a, Size: KeySize> {
+    gcm: Gcm<Aes<'a, Size>>,
+}
+
+pub type Aes128Gcm<'a> = AesGcm<'a, U16>;
+pub type Aes256Gcm<'a> = AesGcm<'a, super::U32>;
+
+impl<'a, Size: KeySize> AesGcm<'a, Size> {
+    pub fn new(hashcrypt: &'a mut Hashcrypt<Enabled>, key: Key<'a, Size>) -> Self {
+        Self {
+            gcm: Gcm::new(Aes::new(hashcrypt, key, Mode::Encrypt)),
+        }
+    }
+
+    pub fn into_inner(self) -> &'a mut Hashcrypt<Enabled> {
+        self.gcm.aes.into_inner()
+    }
+}
+
+impl<Size: KeySize> AeadCore for AesGcm<'_, Size> {
+    type NonceSize = U12;
+    type TagSize = U16;
+    type CiphertextOverhead = U0;
+}
+
+impl<Size: KeySize> AeadInPlace for AesGcm<'_, Size> {
+    fn encrypt_in_place_detached(
+        &self,
+        nonce: &Nonce<Self>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<Tag<Self>, Error> {
+        self.gcm.encrypt(nonce, associated_data, buffer)
+    }
+
+    fn decrypt_in_place_detached(
+        &self,
+        nonce: &Nonce<Self>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+        tag: &Tag<Self>,
+    ) -> Result<(), Error> {
+        self.gcm.decrypt(nonce, associated_data, buffer, tag)
+    }
+}
+
+// CCM over any AES, as `Gcm`
+struct Ccm<C, M, N> {
+    aes: C,
+    sizes: PhantomData<(M, N)>,
+}
+
+impl<C: BlockEncrypt<BlockSize = U16>, M: CcmTagSize, N: CcmNonceSize> Ccm<C, M, N> {
+    fn new(aes: C) -> Self {
+        Self {
+            aes,
+            sizes: PhantomData,
+        }
+    }
+
+    // size of the length field, `q` of SP 800-38C
+    fn q() -> usize {
+        15 - N::to_usize()
+    }
+
+    fn check_len(len: usize) -> Result<(), Error> {
+        match Self::q() {
+            q if q < 8 && len as u64 >> (8 * q) != 0 => Err(Error),
+            _ => Ok(()),
+        }
+    }
+
+    /// The first counter block; the keystream starts with the next one.
+    fn ctr0(nonce: &GenericArray<u8, N>) -> AesBlock {
+        let mut ctr0 = AesBlock::default();
+        ctr0[0] = (Self::q() - 1) as u8;
+        ctr0[1..1 + N::to_usize()].copy_from_slice(nonce);
+        ctr0
+    }
+
+    // the first block of the CBC-MAC, with the flags, nonce and message length
+    fn b0(nonce: &GenericArray<u8, N>, aad_len: usize, len: usize) -> AesBlock {
+        let mut b0 = AesBlock::default();
+        let adata = if aad_len == 0 { 0 } else { 0x40 };
+        b0[0] = adata | (((M::to_usize() - 2) / 2) << 3) as u8 | (Self::q() - 1) as u8;
+        b0[1..1 + N::to_usize()].copy_from_slice(nonce);
+        let len = (len as u64).to_be_bytes();
+        b0[1 + N::to_usize()..].copy_from_slice(&len[8 - Self::q()..]);
+        b0
+    }
+
+    // CBC-MAC over the formatted B0, associated data and plaintext, masked with the first keystream block
+    fn tag(
+        &self,
+        nonce: &GenericArray<u8, N>,
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> GenericArray<u8, M> {
+        let mut mac = CbcMac::new(&self.aes, Self::b0(nonce, aad.len(), plaintext.len()));
+        if !aad.is_empty() {
+            if aad.len() < 0xff00 {
+                mac.update(&(aad.len() as u16).to_be_bytes());
+            } else {
+                mac.update(&[0xff, 0xfe]);
+                mac.update(&(aad.len() as u32).to_be_bytes());
+            }
+            mac.update(aad);
+            mac.pad();
+        }
+        mac.update(plaintext);
+        mac.pad();
+
+        let mut s0 = Self::ctr0(nonce);
+        self.aes.encrypt_block(&mut s0);
+        let mut tag = GenericArray::<u8, M>::default();
+        tag.copy_from_slice(&mac.y[..M::to_usize()]);
+        xor(&mut tag, &s0);
+        tag
+    }
+
+    fn encrypt(
+        &self,
+        nonce: &GenericArray<u8, N>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<GenericArray<u8, M>, Error> {
+        Self::check_len(buffer.len())?;
+        if associated_data.len() as u64 > u32::MAX as u64 {
+            return Err(Error);
+        }
+        let tag = self.tag(nonce, associated_data, buffer);
+        apply_ctr32(&self.aes, &Self::ctr0(nonce), buffer);
+        Ok(tag)
+    }
+
+    fn decrypt(
+        &self,
+        nonce: &GenericArray<u8, N>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+        tag: &GenericArray<u8, M>,
+    ) -> Result<(), Error> {
+        Self::check_len(buffer.len())?;
+        if associated_data.len() as u64 > u32::MAX as u64 {
+            return Err(Error);
+        }
+        apply_ctr32(&self.aes, &Self::ctr0(nonce), buffer);
+        if !ct_eq(&self.tag(nonce, associated_data, buffer), tag) {
+            // do not hand out unauthenticated plaintext
+            apply_ctr32(&self.aes, &Self::ctr0(nonce), buffer);
+            return Err(Error);
+        }
+        Ok(())
+    }
+}
+
+/// AES-CCM, with tag size `M` and nonce size `N`
+///
+/// The nonce size determines the maximal message length: up to 2^(8 * (15 - N)) bytes.
+pub struct AesCcm<'a, Size: KeySize, M: CcmTagSize, N: CcmNonceSize> {
+    ccm: Ccm<Aes<'a, Size>, M, N>,
+}
+
+impl<'a, Size: KeySize, M: CcmTagSize, N: CcmNonceSize> AesCcm<'a, Size, M, N> {
+    pub fn new(hashcrypt: &'a mut Hashcrypt<Enabled>, key: Key<'a, Size>) -> Self {
+        Self {
+            ccm: Ccm::new(Aes::new(hashcrypt, key, Mode::Encrypt)),
+        }
+    }
+
+    pub fn into_inner(self) -> &'a mut Hashcrypt<Enabled> {
+        self.ccm.aes.into_inner()
+    }
+}
+
+impl<Size: KeySize, M: CcmTagSize, N: CcmNonceSize> AeadCore for AesCcm<'_, Size, M, N> {
+    type NonceSize = N;
+    type TagSize = M;
+    type CiphertextOverhead = U0;
+}
+
+impl<Size: KeySize, M: CcmTagSize, N: CcmNonceSize> AeadInPlace for AesCcm<'_, Size, M, N> {
+    fn encrypt_in_place_detached(
+        &self,
+        nonce: &Nonce<Self>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<Tag<Self>, Error> {
+        self.ccm.encrypt(nonce, associated_data, buffer)
+    }
+
+    fn decrypt_in_place_detached(
+        &self,
+        nonce: &Nonce<Self>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+        tag: &Tag<Self>,
+    ) -> Result<(), Error> {
+        self.ccm.decrypt(nonce, associated_data, buffer, tag)
+    }
+}
+
+/// CBC-MAC over a byte stream, block by block in ECB.
+struct CbcMac<'b, C> {
+    aes: &'b C,
+    y: AesBlock,
+    pos: usize,
+}
+
+impl<'b, C: BlockEncrypt<BlockSize = U16>> CbcMac<'b, C> {
+    fn new(aes: &'b C, mut b0: AesBlock) -> Self {
+        aes.encrypt_block(&mut b0);
+        Self { aes, y: b0, pos: 0 }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let n = core::cmp::min(16 - self.pos, data.len());
+            xor(&mut self.y[self.pos..self.pos + n], &data[..n]);
+            self.pos += n;
+            data = &data[n..];
+            if self.pos == 16 {
+                self.aes.encrypt_block(&mut self.y);
+                self.pos = 0;
+            }
+        }
+    }
+
+    // pads a partial block with zeros, which leaves `y` as is
+    fn pad(&mut self) {
+        if self.pos != 0 {
+            self.aes.encrypt_block(&mut self.y);
+            self.pos = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::hex;
+    use crate::traits::aead::consts::{U13, U4, U6, U7, U8};
+    use aes::{cipher::KeyInit, Aes128};
+
+    fn aes128(key: &str) -> Aes128 {
+        Aes128::new(&hex::<16>(key).into())
+    }
+
+    // GCM test case 2 (McGrew-Viega), with H = E(K, 0^128) and E(K, Y0) given
+    #[test]
+    fn ghash() {
+        let h = hex::<16>("66e94bd4ef8a2c3b884cfa59ca342b2e").into();
+        let mut ghash = GHash::new(&h);
+        ghash.update_padded(&hex::<16>("0388dace60b6a392f328c2b971b2fe78"));
+        let mut tag = ghash.finalize(0, 16);
+        xor(&mut tag, &hex::<16>("58e2fccefa7e3061367f1d57a4e7455a"));
+        assert_eq!(tag, hex("ab6e47d42cec13bdf53a67b21257bddf"));
+    }
+
+    // the field's one is the leftmost bit; multiplying by x shifts right and reduces
+    #[test]
+    fn ghash_multiply() {
+        let x = u128::from_be_bytes(hex("66e94bd4ef8a2c3b884cfa59ca342b2e"));
+        let one = 1 << 127;
+        assert_eq!(GHash::mul(x, one), x);
+        assert_eq!(GHash::mul(one, x), x);
+        assert_eq!(GHash::mul(x, 0), 0);
+        assert_eq!(GHash::mul(one >> 1, one >> 1), one >> 2);
+        // x^127 * x = x^128 = 1 + x + x^2 + x^7
+        assert_eq!(GHash::mul(1, one >> 1), 0xe1 << 120);
+        let y = u128::from_be_bytes(hex("0388dace60b6a392f328c2b971b2fe78"));
+        assert_eq!(GHash::mul(x, y), GHash::mul(y, x));
+    }
+
+    // GCM test cases 2-4 (McGrew-Viega), AES-128
+    #[test]
+    fn gcm() {
+        let gcm = Gcm::new(aes128("00000000000000000000000000000000"));
+        let mut buffer = [0u8; 16];
+        let tag = gcm.encrypt(&[0; 12].into(), &[], &mut buffer).unwrap();
+        assert_eq!(buffer, hex("0388dace60b6a392f328c2b971b2fe78"));
+        assert_eq!(tag[..], hex::<16>("ab6e47d42cec13bdf53a67b21257bddf"));
+
+        let gcm = Gcm::new(aes128("feffe9928665731c6d6a8f9467308308"));
+        let nonce = hex::<12>("cafebabefacedbaddecaf888").into();
+        let plaintext: [u8; 64] = hex(
+            "d9313225f88406e5a55909c5aff5269a 86a7a9531534f7da2e4c303d8a318a72
+             1c3c0c95956809532fcf0e2449a6b525 b16aedf5aa0de657ba637b391aafd255",
+        );
+        let ciphertext: [u8; 64] = hex(
+            "42831ec2217774244b7221b784d0d49c e3aa212f2c02a4e035c17e2329aca12e
+             21d514b25466931c7d8f6a5aac84aa05 1ba30b396a0aac973d58e091473f5985",
+        );
+        let mut buffer = plaintext;
+        let tag = gcm.encrypt(&nonce, &[], &mut buffer).unwrap();
+        assert_eq!(buffer, ciphertext);
+        assert_eq!(tag[..], hex::<16>("4d5c2af327cd64a62cf35abd2ba6fab4"));
+
+        // partial last block, with associated data
+        let aad: [u8; 20] = hex("feedfacedeadbeeffeedfacedeadbeefabaddad2");
+        let mut buffer = plaintext;
+        let tag = gcm.encrypt(&nonce, &aad, &mut buffer[..60]).unwrap();
+        assert_eq!(buffer[..60], ciphertext[..60]);
+        assert_eq!(tag[..], hex::<16>("5bc94fbc3221a5db94fae95ae7121a47"));
+
+        gcm.decrypt(&nonce, &aad, &mut buffer[..60], &tag).unwrap();
+        assert_eq!(buffer[..60], plaintext[..60]);
+    }
+
+    #[test]
+    fn gcm_rejects_forgeries() {
+        let gcm = Gcm::new(aes128("feffe9928665731c6d6a8f9467308308"));
+        let nonce = hex::<12>("cafebabefacedbaddecaf888").into();
+        let mut buffer = [0x5a; 20];
+        let tag = gcm.encrypt(&nonce, b"header", &mut buffer).unwrap();
+        let ciphertext = buffer;
+
+        let mut bad_tag = tag;
+        bad_tag[15] ^= 1;
+        assert!(gcm
+            .decrypt(&nonce, b"header", &mut buffer, &bad_tag)
+            .is_err());
+        assert!(gcm.decrypt(&nonce, b"footer", &mut buffer, &tag).is_err());
+        buffer[0] ^= 1;
+        assert!(gcm.decrypt(&nonce, b"header", &mut buffer, &tag).is_err());
+        buffer[0] ^= 1;
+        // nothing was decrypted
+        assert_eq!(buffer, ciphertext);
+        assert!(gcm.decrypt(&nonce, b"header", &mut buffer, &tag).is_ok());
+        assert_eq!(buffer, [0x5a; 20]);
+    }
+
+    // SP 800-38C, appendix C.1: flags 0x4f (Adata, t = 4, q = 8)
+    #[test]
+    fn ccm_formatting() {
+        let nonce = hex::<7>("10111213141516").into();
+        assert_eq!(
+            Ccm::<Aes128, U4, U7>::b0(&nonce, 8, 4)[..],
+            hex::<16>("4f101112131415160000000000000004")
+        );
+        assert_eq!(
+            Ccm::<Aes128, U4, U7>::ctr0(&nonce)[..],
+            hex::<16>("07101112131415160000000000000000")
+        );
+        // no Adata, t = 16, q = 2
+        let nonce = hex::<13>("101112131415161718191a1b1c").into();
+        assert_eq!(
+            Ccm::<Aes128, U16, U13>::b0(&nonce, 0, 0x0102)[..],
+            hex::<16>("39101112131415161718191a1b1c0102")
+        );
+    }
+
+    #[test]
+    fn ccm() {
+        // SP 800-38C, appendix C.1 and C.2
+        let ccm = Ccm::<_, U4, U7>::new(aes128("404142434445464748494a4b4c4d4e4f"));
+        let mut buffer = hex::<4>("20212223");
+        let tag = ccm
+            .encrypt(
+                &hex::<7>("10111213141516").into(),
+                &hex::<8>("0001020304050607"),
+                &mut buffer,
+            )
+            .unwrap();
+        assert_eq!(buffer, hex("7162015b"));
+        assert_eq!(tag[..], hex::<4>("4dac255d"));
+
+        let ccm = Ccm::<_, U6, U8>::new(aes128("404142434445464748494a4b4c4d4e4f"));
+        let nonce = hex::<8>("1011121314151617").into();
+        let aad = hex::<16>("000102030405060708090a0b0c0d0e0f");
+        let mut buffer = hex::<16>("202122232425262728292a2b2c2d2e2f");
+        let tag = ccm.encrypt(&nonce, &aad, &mut buffer).unwrap();
+        assert_eq!(buffer, hex("d2a1f0e051ea5f62081a7792073d593d"));
+        assert_eq!(tag[..], hex::<6>("1fc64fbfaccd"));
+        ccm.decrypt(&nonce, &aad, &mut buffer, &tag).unwrap();
+        assert_eq!(buffer, hex("202122232425262728292a2b2c2d2e2f"));
+
+        // RFC 3610, packet vector #1
+        let ccm = Ccm::<_, U8, U13>::new(aes128("c0c1c2c3c4c5c6c7c8c9cacbcccdcecf"));
+        let nonce = hex::<13>("00000003020100a0a1a2a3a4a5").into();
+        let mut buffer: [u8; 23] = hex("08090a0b0c0d0e0f101112131415161718191a1b1c1d1e");
+        let tag = ccm
+            .encrypt(&nonce, &hex::<8>("0001020304050607"), &mut buffer)
+            .unwrap();
+        assert_eq!(
+            buffer,
+            hex("588c979a61c663d2f066d0c2c0f989806d5f6b61dac384")
+        );
+        assert_eq!(tag[..], hex::<8>("17e8d12cfdf926e0"));
+    }
+
+    // the associated data length takes two bytes below 0xff00, six from there
+    #[test]
+    fn ccm_long_associated_data() {
+        let ccm = Ccm::<_, U16, U13>::new(aes128("404142434445464748494a4b4c4d4e4f"));
+        let nonce = hex::<13>("101112131415161718191a1b1c").into();
+        let aad = [0u8; 0xff00];
+        let tag = ccm.encrypt(&nonce, &aad[..0xfeff], &mut []).unwrap();
+        assert_eq!(tag[..], hex::<16>("0f7e7152d764e3286c9584ca64d9cea2"));
+        let tag = ccm.encrypt(&nonce, &aad, &mut []).unwrap();
+        assert_eq!(tag[..], hex::<16>("70bd6df150f83d86c6f67e7278774fc5"));
+    }
+
+    #[test]
+    fn ccm_rejects_forgeries() {
+        let ccm = Ccm::<_, U8, U13>::new(aes128("c0c1c2c3c4c5c6c7c8c9cacbcccdcecf"));
+        let nonce = hex::<13>("00000003020100a0a1a2a3a4a5").into();
+        let mut buffer = [0x5a; 20];
+        let mut tag = ccm.encrypt(&nonce, b"header", &mut buffer).unwrap();
+        let ciphertext = buffer;
+
+        tag[0] ^= 1;
+        assert!(ccm.decrypt(&nonce, b"header", &mut buffer, &tag).is_err());
+        // the plaintext is not handed out
+        assert_eq!(buffer, ciphertext);
+        tag[0] ^= 1;
+        assert!(ccm.decrypt(&nonce, b"header", &mut buffer, &tag).is_ok());
+        assert_eq!(buffer, [0x5a; 20]);
+    }
+
+    // with a 13-byte nonce, q = 2 limits messages to 2^16 - 1 bytes
+    #[test]
+    fn ccm_message_length() {
+        assert!(Ccm::<Aes128, U8, U13>::check_len(0xffff).is_ok());
+        assert!(Ccm::<Aes128, U8, U13>::check_len(0x10000).is_err());
+        assert!(Ccm::<Aes128, U8, U7>::check_len(usize::MAX).is_ok());
+    }
+}
@@ -0,0 +1,307 @@
+//! ICB-AES, the side-channel protected counter mode of the peripheral (UM11126, "ICB-AES").
+//!
+//! Selected with `CTRL.MODE = ICB-AES`. Like CTR, the peripheral encrypts a counter and
+//! XORs the result into the data, so encryption and decryption are the same operation.
+//! Unlike CTR, only the first block of a stream needs the full AES computation from the
+//! counter (`STATUS.ICBIDX = 0`); the following blocks, up to `CRYPTCFG.ICBSTRM` of them,
+//! are derived faster and without data-dependent timing. `CRYPTCFG.STREAMLAST` marks the
+//! last block of a stream.
+//!
+//! `CRYPTCFG.ICBSZ` sets how many low-order bits of the IV form the counter; the remaining
+//! high-order bits stay fixed. The counter wraps within its size.
+//!
+//! The keystream is *not* that of standard AES-CTR, ciphertexts only interoperate with
+//! other ICB-AES implementations with the same counter and stream sizes.
+//!
+//! `apply_blocks` splits its blocks into streams of `ICBSTRM` blocks, the last one possibly
+//! shorter. How the peripheral continues a stream past `ICBSTRM` blocks is not documented,
+//! so the driver never lets it: each stream is configured anew from the current counter,
+//! which then advances by the stream's blocks. Processing a message in several calls
+//! therefore gives the same result as in one call only if each call but the last is a
+//! multiple of `ICBSTRM` blocks. The counter can also be read and set explicitly, e.g. to
+//! seek:
+//!
+//! ```ignore
+//! let mut icb = aes::IcbAes::new(&mut hashcrypt, key, &iv, CounterSize::Bits32, StreamSize::Blocks8);
+//! icb.apply_blocks(&mut blocks);
+//! ```
+
+use crate::{
+    peripherals::hashcrypt::Hashcrypt,
+    raw::hashcrypt::cryptcfg::{ICBSTRM_A, ICBSZ_A},
+    traits::cipher::{inout::InOutBuf, Block, BlockSizeUser},
+    typestates::init_state::Enabled,
+};
+
+use super::{Aes, Chaining, Key, KeySize, Mode, U16};
+
+type Iv = Block<Aes<'static, U16>>;
+
+/// `CRYPTCFG.ICBSZ`: the low-order bits of the IV that form the counter
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CounterSize {
+    Bits32,
+    Bits64,
+    Bits96,
+    Bits128,
+}
+
+impl CounterSize {
+    fn bytes(self) -> usize {
+        match self {
+            CounterSize::Bits32 => 4,
+            CounterSize::Bits64 => 8,
+            CounterSize::Bits96 => 12,
+            CounterSize::Bits128 => 16,
+        }
+    }
+}
+
+impl From<CounterSize> for ICBSZ_A {
+    fn from(size: CounterSize) -> Self {
+        match size {
+            CounterSize::Bits32 => ICBSZ_A::BITS_32,
+            CounterSize::Bits64 => ICBSZ_A::BITS_64,
+            CounterSize::Bits96 => ICBSZ_A::BITS_96,
+            CounterSize::Bits128 => ICBSZ_A::BIT_128,
+        }
+    }
+}
+
+/// `CRYPTCFG.ICBSTRM`: the blocks following a full AES computation
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StreamSize {
+    Blocks8,
+    Blocks16,
+    Blocks32,
+    Blocks64,
+}
+
+impl StreamSize {
+    fn blocks(self) -> usize {
+        match self {
+            StreamSize::Blocks8 => 8,
+            StreamSize::Blocks16 => 16,
+            StreamSize::Blocks32 => 32,
+            StreamSize::Blocks64 => 64,
+        }
+    }
+}
+
+impl From<StreamSize> for ICBSTRM_A {
+    fn from(size: StreamSize) -> Self {
+        match size {
+            StreamSize::Blocks8 => ICBSTRM_A::BLOCKS_8,
+            StreamSize::Blocks16 => ICBSTRM_A::BLOCKS_16,
+            StreamSize::Blocks32 => ICBSTRM_A::BLOCKS_32,
+            StreamSize::Blocks64 => ICBSTRM_A::BLOCKS_64,
+        }
+    }
+}
+
+/// ICB-AES, see the module documentation
+pub struct IcbAes<'a, Size: KeySize> {
+    aes: Aes<'a, Size>,
+    counter: Block<Self>,
+    counter_size: CounterSize,
+    stream_size: StreamSize,
+}
+
+impl<'a, Size: KeySize> IcbAes<'a, Size> {
+    /// Panics if the peripheral has no ICB-AES (`CONFIG.ICB`).
+    pub fn new(
+        hashcrypt: &'a mut Hashcrypt<Enabled>,
+        key: Key<'a, Size>,
+        iv: &Block<Self>,
+        counter_size: CounterSize,
+        stream_size: StreamSize,
+    ) -> Self {
+        assert!(hashcrypt.config.read().icb().bit_is_set());
+        Self {
+            aes: Aes {
+                inner: hashcrypt,
+                key,
+            },
+            counter: *iv,
+            counter_size,
+            stream_size,
+        }
+    }
+
+    pub fn into_inner(self) -> &'a mut Hashcrypt<Enabled> {
+        self.aes.into_inner()
+    }
+
+    /// The counter for the next block, i.e. the IV of the next stream.
+    pub fn counter(&self) -> Block<Self> {
+        self.counter
+    }
+
+    pub fn set_counter(&mut self, counter: &Block<Self>) {
+        self.counter = *counter;
+    }
+
+    /// En- or decrypts the blocks in place, as streams of at most `ICBSTRM` blocks.
+    pub fn apply_blocks(&mut self, blocks: &mut [Block<Self>]) {
+        let Self {
+            aes,
+            counter,
+            counter_size,
+            stream_size,
+        } = self;
+
+        streams(
+            *counter_size,
+            *stream_size,
+            counter,
+            blocks,
+            |counter, blocks| {
+                let (last, blocks) = blocks.split_last_mut().unwrap();
+                aes.configure_chained(
+                    Mode::Encrypt,
+                    Chaining::Icb(*counter_size, *stream_size),
+                    Some(counter),
+                );
+                aes.blocks(InOutBuf::from(blocks));
+                aes.cryptcfg.modify(|_, w| w.streamlast().set_bit());
+                aes.blocks(InOutBuf::from_mut(last));
+            },
+        );
+    }
+}
+
+// The driver's side of ICB-AES: `stream` runs the peripheral on one non-empty stream,
+// starting from the given counter.
+fn streams(
+    counter_size: CounterSize,
+    stream_size: StreamSize,
+    counter: &mut Iv,
+    blocks: &mut [Iv],
+    mut stream: impl FnMut(&Iv, &mut [Iv]),
+) {
+    for blocks in blocks.chunks_mut(stream_size.blocks()) {
+        stream(counter, blocks);
+        advance(counter_size, counter, blocks.len() as u128);
+    }
+}
+
+// the counter is big-endian in the last `counter_size` bytes of the IV
+fn advance(counter_size: CounterSize, counter: &mut Iv, blocks: u128) {
+    let bytes = counter_size.bytes();
+    let mut value = [0u8; 16];
+    value[16 - bytes..].copy_from_slice(&counter[16 - bytes..]);
+    let value = u128::from_be_bytes(value).wrapping_add(blocks);
+    counter[16 - bytes..].copy_from_slice(&value.to_be_bytes()[16 - bytes..]);
+}
+
+impl<Size: KeySize> BlockSizeUser for IcbAes<'_, Size> {
+    type BlockSize = U16;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::hex;
+
+    fn block(hex_block: &str) -> Iv {
+        hex::<16>(hex_block).into()
+    }
+
+    #[test]
+    fn counter_wraps_within_its_size() {
+        let mut counter = block("000102030405060708090a0bfffffffe");
+        advance(CounterSize::Bits32, &mut counter, 3);
+        assert_eq!(counter, block("000102030405060708090a0b00000001"));
+
+        let mut counter = block("0001020304050607fffffffffffffffe");
+        advance(CounterSize::Bits64, &mut counter, 2);
+        assert_eq!(counter, block("00010203040506070000000000000000"));
+
+        let mut counter = block("00010203ffffffffffffffffffffffff");
+        advance(CounterSize::Bits96, &mut counter, 1);
+        assert_eq!(counter, block("00010203000000000000000000000000"));
+
+        let mut counter = block("ffffffffffffffffffffffffffffffff");
+        advance(CounterSize::Bits128, &mut counter, 0x11);
+        assert_eq!(counter, block("00000000000000000000000000000010"));
+    }
+
+    #[test]
+    fn streams_are_split() {
+        let iv = block("000102030405060708090a0bfffffff8");
+        let mut counter = iv;
+        let mut blocks = [Iv::default(); 20];
+        let expected = [
+            (block("000102030405060708090a0bfffffff8"), 8),
+            (block("000102030405060708090a0b00000000"), 8),
+            (block("000102030405060708090a0b00000008"), 4),
+        ];
+        let mut count = 0;
+        streams(
+            CounterSize::Bits32,
+            StreamSize::Blocks8,
+            &mut counter,
+            &mut blocks,
+            |counter, blocks| {
+                assert_eq!((*counter, blocks.len()), expected[count]);
+                count += 1;
+            },
+        );
+        assert_eq!(count, 3);
+        assert_eq!(counter, block("000102030405060708090a0b0000000c"));
+
+        // no stream at all
+        streams(
+            CounterSize::Bits32,
+            StreamSize::Blocks8,
+            &mut counter,
+            &mut [],
+            |_, _| panic!(),
+        );
+        assert_eq!(counter, block("000102030405060708090a0b0000000c"));
+    }
+
+    // stands in for the peripheral: block `i` of a stream from `counter`
+    // gets the counter's last byte and `i` XORed in
+    fn model(counter: &Iv, blocks: &mut [Iv]) {
+        for (i, block) in blocks.iter_mut().enumerate() {
+            block[0] ^= counter[15];
+            block[1] ^= i as u8;
+        }
+    }
+
+    fn apply(counter: &mut Iv, blocks: &mut [Iv]) {
+        streams(
+            CounterSize::Bits32,
+            StreamSize::Blocks16,
+            counter,
+            blocks,
+            model,
+        );
+    }
+
+    #[test]
+    fn split_calls() {
+        let iv = block("00000000000000000000000000000000");
+        let mut whole = [Iv::default(); 40];
+        apply(&mut { iv }, &mut whole);
+
+        // split at a multiple of the stream size
+        let mut split = [Iv::default(); 40];
+        let mut counter = iv;
+        let (first, second) = split.split_at_mut(32);
+        apply(&mut counter, first);
+        apply(&mut counter, second);
+        assert_eq!(split, whole);
+        assert_eq!(counter, block("00000000000000000000000000000028"));
+
+        // anywhere else, a new stream starts early
+        let mut split = [Iv::default(); 40];
+        let mut counter = iv;
+        let (first, second) = split.split_at_mut(20);
+        apply(&mut counter, first);
+        apply(&mut counter, second);
+        assert_ne!(split, whole);
+        assert_eq!(counter, block("00000000000000000000000000000028"));
+    }
+}
@@ -0,0 +1,506 @@
+//! AES block modes of NIST SP 800-38A.
+//!
+//! CBC and CTR run in the peripheral, which keeps the chaining value resp. counter
+//! between blocks. The peripheral has no CFB or OFB mode, these encrypt the feedback
+//! value in ECB mode and XOR in software.
+//!
+//! The host tests check the driver's side of CBC and CTR (IV state, counter restarts)
+//! against SP 800-38A with a software model of the peripheral; the peripheral's own
+//! chaining is only verified on target (`examples/aes_modes.rs`).
+//!
+//! The block modes implement `BlockEncryptMut`/`BlockDecryptMut` (and CFB also
+//! `AsyncStreamCipher`), the stream modes are used through `StreamCipherCoreWrapper`:
+//!
+//! ```ignore
+//! let mut cipher = aes::Ctr::from_core(aes::CtrCore::new(&mut hashcrypt, key, &counter));
+//! cipher.apply_keystream(&mut buf);
+//! ```
+
+use crate::{
+    peripherals::hashcrypt::Hashcrypt,
+    traits::cipher::{
+        consts::{U1, U16},
+        inout::InOut,
+        AsyncStreamCipher, Block, BlockBackend, BlockClosure, BlockDecryptMut, BlockEncryptMut,
+        BlockSizeUser, IvSizeUser, IvState, ParBlocksSizeUser, StreamBackend, StreamCipherCore,
+        StreamCipherCoreWrapper, StreamClosure,
+    },
+    typestates::init_state::Enabled,
+};
+
+use super::{Aes, Chaining, Key, KeySize, Mode};
+
+type Iv = Block<Aes<'static, U16>>;
+
+// One step of a mode, on one block.
+trait BlockStep {
+    fn step(&mut self, block: InOut<'_, '_, Iv>);
+}
+
+// One step of a stream mode, generating one block of keystream.
+trait KeystreamStep {
+    fn step(&mut self, block: &mut Iv);
+}
+
+struct Backend<'b, M>(&'b mut M);
+
+impl<M> BlockSizeUser for Backend<'_, M> {
+    type BlockSize = U16;
+}
+
+impl<M> ParBlocksSizeUser for Backend<'_, M> {
+    type ParBlocksSize = U1;
+}
+
+impl<M: BlockStep> BlockBackend for Backend<'_, M> {
+    fn proc_block(&mut self, block: InOut<'_, '_, Iv>) {
+        self.0.step(block);
+    }
+}
+
+impl<M: KeystreamStep> StreamBackend for Backend<'_, M> {
+    fn gen_ks_block(&mut self, block: &mut Iv) {
+        self.0.step(block);
+    }
+}
+
+fn xor(block: &mut Iv, other: &Iv) {
+    for (byte, other) in block.iter_mut().zip(other.iter()) {
+        *byte ^= other;
+    }
+}
+
+// The software chaining of CFB and OFB, given ECB encryption in place.
+
+fn cfb_encrypt(encrypt: impl FnOnce(&mut Iv), iv: &mut Iv, mut block: InOut<'_, '_, Iv>) {
+    encrypt(iv);
+    xor(iv, block.get_in());
+    *block.get_out() = *iv;
+}
+
+fn cfb_decrypt(encrypt: impl FnOnce(&mut Iv), iv: &mut Iv, mut block: InOut<'_, '_, Iv>) {
+    let mut keystream = *iv;
+    encrypt(&mut keystream);
+    *iv = block.clone_in();
+    block.xor_in2out(&keystream);
+}
+
+fn ofb(encrypt: impl FnOnce(&mut Iv), iv: &mut Iv, block: &mut Iv) {
+    encrypt(iv);
+    *block = *iv;
+}
+
+// The driver's side of CBC and CTR, given the peripheral configured for that mode.
+
+fn cbc_encrypt(encrypt: impl FnOnce(&mut Iv), iv: &mut Iv, mut block: InOut<'_, '_, Iv>) {
+    let mut buf = block.clone_in();
+    encrypt(&mut buf);
+    *iv = buf;
+    *block.get_out() = buf;
+}
+
+fn cbc_decrypt(decrypt: impl FnOnce(&mut Iv), iv: &mut Iv, mut block: InOut<'_, '_, Iv>) {
+    let mut buf = block.clone_in();
+    decrypt(&mut buf);
+    *iv = block.clone_in();
+    *block.get_out() = buf;
+}
+
+// `restart` configures the peripheral anew with the given counter.
+fn ctr(encrypt: impl FnOnce(&mut Iv), restart: impl FnOnce(&Iv), counter: &mut Iv, block: &mut Iv) {
+    // CTR of a zero block is the keystream
+    *block = Iv::default();
+    encrypt(block);
+
+    // the peripheral only counts in the last 16 bits; on their
+    // overflow, restart it with the carry included
+    if increment(counter) {
+        restart(counter);
+    }
+}
+
+// Increments the big-endian counter, returns whether its last 16 bits wrapped.
+fn increment(counter: &mut Iv) -> bool {
+    for byte in counter.iter_mut().rev() {
+        *byte = byte.wrapping_add(1);
+        if *byte != 0 {
+            break;
+        }
+    }
+    counter[14..] == [0, 0]
+}
+
+macro_rules! mode {
+    ($(#[$doc:meta])* $Name:ident, $chaining:expr, $mode:expr) => {
+        $(#[$doc])*
+        pub struct $Name<'a, Size: KeySize> {
+            aes: Aes<'a, Size>,
+            iv: Iv,
+        }
+
+        impl<'a, Size: KeySize> $Name<'a, Size> {
+            pub fn new(hashcrypt: &'a mut Hashcrypt<Enabled>, key: Key<'a, Size>, iv: &Iv) -> Self {
+                let aes = Aes {
+                    inner: hashcrypt,
+                    key,
+                };
+                let chaining: Option<Chaining> = $chaining;
+                match chaining {
+                    Some(chaining) => aes.configure_chained($mode, chaining, Some(iv)),
+                    None => aes.configure($mode),
+                }
+                Self {
+                    aes,
+                    iv: *iv,
+                }
+            }
+        }
+
+        impl<Size: KeySize> BlockSizeUser for $Name<'_, Size> {
+            type BlockSize = U16;
+        }
+
+        impl<Size: KeySize> IvSizeUser for $Name<'_, Size> {
+            type IvSize = U16;
+        }
+
+        impl<Size: KeySize> IvState for $Name<'_, Size> {
+            fn iv_state(&self) -> Iv {
+                self.iv
+            }
+        }
+    };
+}
+
+mode!(
+    /// AES-CBC encryption, chained in the peripheral
+    CbcEncrypt,
+    Some(Chaining::Cbc),
+    Mode::Encrypt
+);
+mode!(
+    /// AES-CBC decryption, chained in the peripheral
+    CbcDecrypt,
+    Some(Chaining::Cbc),
+    Mode::Decrypt
+);
+mode!(
+    /// AES-CFB (128-bit segments) encryption, on top of ECB
+    CfbEncrypt,
+    None,
+    Mode::Encrypt
+);
+mode!(
+    /// AES-CFB (128-bit segments) decryption, on top of ECB encryption
+    CfbDecrypt,
+    None,
+    Mode::Encrypt
+);
+mode!(
+    /// AES-CTR keystream, with the counter in the peripheral
+    ///
+    /// The whole IV is a big-endian counter, as with `ctr::Ctr128BE`.
+    CtrCore,
+    Some(Chaining::Ctr),
+    Mode::Encrypt
+);
+mode!(
+    /// AES-OFB keystream, on top of ECB
+    OfbCore,
+    None,
+    Mode::Encrypt
+);
+
+/// AES-CTR, as `StreamCipher`
+pub type Ctr<'a, Size> = StreamCipherCoreWrapper<CtrCore<'a, Size>>;
+/// AES-OFB, as `StreamCipher`
+pub type Ofb<'a, Size> = StreamCipherCoreWrapper<OfbCore<'a, Size>>;
+
+impl<Size: KeySize> BlockStep for CbcEncrypt<'_, Size> {
+    fn step(&mut self, block: InOut<'_, '_, Iv>) {
+        let aes = &self.aes;
+        cbc_encrypt(|iv| aes.one_block(iv), &mut self.iv, block);
+    }
+}
+
+impl<Size: KeySize> BlockStep for CbcDecrypt<'_, Size> {
+    fn step(&mut self, block: InOut<'_, '_, Iv>) {
+        let aes = &self.aes;
+        cbc_decrypt(|iv| aes.one_block(iv), &mut self.iv, block);
+    }
+}
+
+impl<Size: KeySize> BlockStep for CfbEncrypt<'_, Size> {
+    fn step(&mut self, block: InOut<'_, '_, Iv>) {
+        let aes = &self.aes;
+        cfb_encrypt(|iv| aes.one_block(iv), &mut self.iv, block);
+    }
+}
+
+impl<Size: KeySize> BlockStep for CfbDecrypt<'_, Size> {
+    fn step(&mut self, block: InOut<'_, '_, Iv>) {
+        let aes = &self.aes;
+        cfb_decrypt(|iv| aes.one_block(iv), &mut self.iv, block);
+    }
+}
+
+impl<Size: KeySize> KeystreamStep for CtrCore<'_, Size> {
+    fn step(&mut self, block: &mut Iv) {
+        let aes = &self.aes;
+        ctr(
+            |block| aes.one_block(block),
+            |counter| aes.configure_chained(Mode::Encrypt, Chaining::Ctr, Some(counter)),
+            &mut self.iv,
+            block,
+        );
+    }
+}
+
+impl<Size: KeySize> KeystreamStep for OfbCore<'_, Size> {
+    fn step(&mut self, block: &mut Iv) {
+        let aes = &self.aes;
+        ofb(|iv| aes.one_block(iv), &mut self.iv, block);
+    }
+}
+
+impl<Size: KeySize> BlockEncryptMut for CbcEncrypt<'_, Size> {
+    fn encrypt_with_backend_mut(&mut self, f: impl BlockClosure<BlockSize = U16>) {
+        f.call(&mut Backend(self));
+    }
+}
+
+impl<Size: KeySize> BlockDecryptMut for CbcDecrypt<'_, Size> {
+    fn decrypt_with_backend_mut(&mut self, f: impl BlockClosure<BlockSize = U16>) {
+        f.call(&mut Backend(self));
+    }
+}
+
+impl<Size: KeySize> BlockEncryptMut for CfbEncrypt<'_, Size> {
+    fn encrypt_with_backend_mut(&mut self, f: impl BlockClosure<BlockSize = U16>) {
+        f.call(&mut Backend(self));
+    }
+}
+
+impl<Size: KeySize> BlockDecryptMut for CfbDecrypt<'_, Size> {
+    fn decrypt_with_backend_mut(&mut self, f: impl BlockClosure<BlockSize = U16>) {
+        f.call(&mut Backend(self));
+    }
+}
+
+impl<Size: KeySize> AsyncStreamCipher for CfbEncrypt<'_, Size> {}
+impl<Size: KeySize> AsyncStreamCipher for CfbDecrypt<'_, Size> {}
+
+impl<Size: KeySize> StreamCipherCore for CtrCore<'_, Size> {
+    fn remaining_blocks(&self) -> Option<usize> {
+        None
+    }
+
+    fn process_with_backend(&mut self, f: impl StreamClosure<BlockSize = U16>) {
+        f.call(&mut Backend(self));
+    }
+}
+
+impl<Size: KeySize> StreamCipherCore for OfbCore<'_, Size> {
+    fn remaining_blocks(&self) -> Option<usize> {
+        None
+    }
+
+    fn process_with_backend(&mut self, f: impl StreamClosure<BlockSize = U16>) {
+        f.call(&mut Backend(self));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::hex;
+    use aes::{cipher::KeyInit, Aes128};
+    use cipher::{BlockDecrypt, BlockEncrypt};
+    use core::cell::Cell;
+
+    // NIST SP 800-38A, F.2.1-F.2.2, F.3.13-F.3.14, F.4.1-F.4.2 and F.5.1-F.5.2 (AES-128)
+    const KEY: &str = "2b7e151628aed2a6abf7158809cf4f3c";
+    const IV: &str = "000102030405060708090a0b0c0d0e0f";
+    const PLAINTEXT: [&str; 4] = [
+        "6bc1bee22e409f96e93d7e117393172a",
+        "ae2d8a571e03ac9c9eb76fac45af8e51",
+        "30c81c46a35ce411e5fbc1191a0a52ef",
+        "f69f2445df4f9b17ad2b417be66c3710",
+    ];
+    const CBC: [&str; 4] = [
+        "7649abac8119b246cee98e9b12e9197d",
+        "5086cb9b507219ee95db113a917678b2",
+        "73bed6b8e3c1743b7116e69e22229516",
+        "3ff1caa1681fac09120eca307586e1a7",
+    ];
+    const CFB: [&str; 4] = [
+        "3b3fd92eb72dad20333449f8e83cfb4a",
+        "c8a64537a0b3a93fcde3cdad9f1ce58b",
+        "26751f67a3cbb140b1808cf187a4f4df",
+        "c04b05357c5d1c0eeac4c66f9ff7f2e6",
+    ];
+    const OFB: [&str; 4] = [
+        "3b3fd92eb72dad20333449f8e83cfb4a",
+        "7789508d16918f03f53c52dac54ed825",
+        "9740051e9c5fecf64344f7a82260edcc",
+        "304c6528f659c77866a510d9c1d6ae5e",
+    ];
+
+    const COUNTER: &str = "f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff";
+    const CTR: [&str; 4] = [
+        "874d6191b620e3261bef6864990db6ce",
+        "9806f66b7970fdff8617187bb9fffdff",
+        "5ae4df3edbd5d35e5b4f09020db03eab",
+        "1e031dda2fbe03d1792170a0f3009cee",
+    ];
+
+    fn block(hex_block: &str) -> Iv {
+        Iv::from(hex::<16>(hex_block))
+    }
+
+    // Model of the peripheral in CTR mode: it encrypts its counter and
+    // increments only the last 16 bits, wrapping around.
+    struct CtrModel {
+        aes: Aes128,
+        counter: Cell<Iv>,
+    }
+
+    impl CtrModel {
+        fn new(counter: &Iv) -> Self {
+            Self {
+                aes: Aes128::new(&hex::<16>(KEY).into()),
+                counter: Cell::new(*counter),
+            }
+        }
+
+        fn keystream(&self, counter: &mut Iv) -> Iv {
+            let mut block = Iv::default();
+            ctr(
+                |block| {
+                    let mut keystream = self.counter.get();
+                    self.aes.encrypt_block(&mut keystream);
+                    xor(block, &keystream);
+                    let mut hw = self.counter.get();
+                    let low = u16::from_be_bytes([hw[14], hw[15]]).wrapping_add(1);
+                    hw[14..].copy_from_slice(&low.to_be_bytes());
+                    self.counter.set(hw);
+                },
+                |counter| self.counter.set(*counter),
+                counter,
+                &mut block,
+            );
+            block
+        }
+    }
+
+    #[test]
+    fn cbc() {
+        let aes = Aes128::new(&hex::<16>(KEY).into());
+
+        // the peripheral XORs in its chaining value, which is the previous ciphertext
+        let mut chain = block(IV);
+        let mut iv = block(IV);
+        for (plaintext, ciphertext) in PLAINTEXT.iter().zip(CBC.iter()) {
+            let mut data = block(plaintext);
+            let encrypt = |block: &mut Iv| {
+                xor(block, &chain);
+                aes.encrypt_block(block);
+                chain = *block;
+            };
+            cbc_encrypt(encrypt, &mut iv, InOut::from(&mut data));
+            assert_eq!(data, block(ciphertext));
+            assert_eq!(iv, block(ciphertext));
+        }
+
+        let mut chain = block(IV);
+        let mut iv = block(IV);
+        for (plaintext, ciphertext) in PLAINTEXT.iter().zip(CBC.iter()) {
+            let mut data = block(ciphertext);
+            let decrypt = |block: &mut Iv| {
+                let input = *block;
+                aes.decrypt_block(block);
+                xor(block, &chain);
+                chain = input;
+            };
+            cbc_decrypt(decrypt, &mut iv, InOut::from(&mut data));
+            assert_eq!(data, block(plaintext));
+            assert_eq!(iv, block(ciphertext));
+        }
+    }
+
+    #[test]
+    fn ctr_keystream() {
+        let mut counter = block(COUNTER);
+        let model = CtrModel::new(&counter);
+        for (plaintext, ciphertext) in PLAINTEXT.iter().zip(CTR.iter()) {
+            let mut data = block(plaintext);
+            xor(&mut data, &model.keystream(&mut counter));
+            assert_eq!(data, block(ciphertext));
+        }
+        assert_eq!(counter, block("f0f1f2f3f4f5f6f7f8f9fafbfcfdff03"));
+    }
+
+    #[test]
+    fn ctr_restarts_on_wrap() {
+        let aes = Aes128::new(&hex::<16>(KEY).into());
+        let start = u128::from_be_bytes(hex::<16>("0000000000000000000000fffffffffe"));
+
+        let mut counter = Iv::from(start.to_be_bytes());
+        let model = CtrModel::new(&counter);
+        for i in 0..4 {
+            let mut expected = Iv::from((start + i).to_be_bytes());
+            aes.encrypt_block(&mut expected);
+            assert_eq!(model.keystream(&mut counter), expected, "block {}", i);
+        }
+        assert_eq!(counter, Iv::from((start + 4).to_be_bytes()));
+    }
+
+    #[test]
+    fn cfb() {
+        let aes = Aes128::new(&hex::<16>(KEY).into());
+        let encrypt = |block: &mut Iv| aes.encrypt_block(block);
+
+        let mut iv = block(IV);
+        for (plaintext, ciphertext) in PLAINTEXT.iter().zip(CFB.iter()) {
+            let mut data = block(plaintext);
+            cfb_encrypt(encrypt, &mut iv, InOut::from(&mut data));
+            assert_eq!(data, block(ciphertext));
+        }
+
+        let mut iv = block(IV);
+        for (plaintext, ciphertext) in PLAINTEXT.iter().zip(CFB.iter()) {
+            let mut data = block(ciphertext);
+            cfb_decrypt(encrypt, &mut iv, InOut::from(&mut data));
+            assert_eq!(data, block(plaintext));
+        }
+    }
+
+    #[test]
+    fn ofb_keystream() {
+        let aes = Aes128::new(&hex::<16>(KEY).into());
+        let mut iv = block(IV);
+        for (plaintext, ciphertext) in PLAINTEXT.iter().zip(OFB.iter()) {
+            let mut keystream = Iv::default();
+            ofb(|block| aes.encrypt_block(block), &mut iv, &mut keystream);
+            let mut data = block(plaintext);
+            xor(&mut data, &keystream);
+            assert_eq!(data, block(ciphertext));
+        }
+    }
+
+    #[test]
+    fn counter_carries() {
+        let mut counter = block("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff");
+        assert!(!increment(&mut counter));
+        assert_eq!(counter, block("f0f1f2f3f4f5f6f7f8f9fafbfcfdff00"));
+
+        // the peripheral's 16-bit counter wraps, the carry goes on
+        let mut counter = block("000000000000000000000000ffffffff");
+        assert!(increment(&mut counter));
+        assert_eq!(counter, block("00000000000000000000000100000000"));
+
+        let mut counter = block("ffffffffffffffffffffffffffffffff");
+        assert!(increment(&mut counter));
+        assert_eq!(counter, Iv::default());
+    }
+}
@@ -0,0 +1,608 @@
+//! Multiprecision arithmetic on CASPER, the cryptographic accelerator of the LPC55.
+//!
+//! CASPER works on its own RAM window: SRAMX0 and SRAMX1 (4KB each, at 0x1400_0000 and
+//! 0x1400_4000, see `memory.x`), which the application must not use otherwise. The
+//! engine sees them as one 8KB memory of 64-bit words, the low halves in SRAMX0 and the
+//! high halves in SRAMX1.
+//!
+//! An operation walks `len` 64-bit words. Its operands are offsets into the RAM window:
+//! the walked operand `a` (`CTRL0.ABOFF`), the single word `b` multiplying it
+//! (`CTRL0.CDOFF`), and the result (`CTRL1.RESOFF`), which most operations also read.
+//! Writing `CTRL1` starts the operation, `STATUS.DONE` signals its completion, and
+//! `STATUS.CARRY` holds the final carry.
+//!
+//! The `Engine` trait runs operations, either on the peripheral or on the software
+//! `Model`, whose documentation lists the semantics of each operation. On top of it,
+//! `Bignum` allocates numbers ("slots") in the RAM window and schedules the operations
+//! for additions, multiplications, shifts and comparisons:
+//!
+//! ```ignore
+//! let mut casper = hal::Casper::from(dp.CASPER).enabled(&mut syscon);
+//! let mut bignum = Bignum::new(&mut casper);
+//! let (a, b, r) = (bignum.alloc(4), bignum.alloc(4), bignum.alloc(8));
+//! bignum.load(a, &A);
+//! bignum.load(b, &B);
+//! bignum.mul(r, a, b);
+//! ```
+//!
+//! Numbers are little-endian, in 64-bit words.
+
+use core::{cmp::Ordering, ptr};
+
+use crate::{
+    peripherals::casper::{Casper, Operations},
+    typestates::init_state::Enabled,
+};
+
+pub mod curve25519;
+
+pub mod ecc;
+
+pub mod model;
+pub use model::Model;
+
+pub mod montgomery;
+pub use montgomery::Modulus;
+
+/// Size of the RAM window, in 64-bit words
+pub const RAM_WORDS: usize = 1024;
+/// Maximal length of one operation (`CTRL1.ITER` + 1)
+pub const MAX_WORDS: usize = 256;
+
+// SRAMX0 and SRAMX1
+const RAM_LOW: usize = 0x1400_0000;
+const RAM_HIGH: usize = 0x1400_4000;
+
+/// One operation, with word offsets into the RAM window
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Operation {
+    pub opcode: Operations,
+    /// The walked operand
+    pub ab: usize,
+    /// The single word multiplying `ab`
+    pub cd: usize,
+    /// The result
+    pub res: usize,
+    /// Number of words walked, at most `MAX_WORDS`
+    pub len: usize,
+}
+
+impl Operation {
+    /// Panics if the operands do not lie within the RAM window.
+    pub fn check(&self) {
+        assert!(self.len >= 1 && self.len <= MAX_WORDS);
+        // multiplications also write the final carry word
+        let written = match self.opcode {
+            Operations::Mul6464NoSum
+            | Operations::Mul6464Sum
+            | Operations::Mul6464FullSum
+            | Operations::Mul6464Reduce => self.len + 1,
+            _ => self.len,
+        };
+        assert!(self.ab + self.len <= RAM_WORDS);
+        assert!(self.cd < RAM_WORDS);
+        assert!(self.res + written <= RAM_WORDS);
+    }
+}
+
+/// Runs CASPER operations, on the peripheral or on the software `Model`
+pub trait Engine {
+    /// Reads words from the RAM window, starting at `offset`.
+    fn read(&self, offset: usize, words: &mut [u64]);
+    /// Writes words to the RAM window, starting at `offset`.
+    fn write(&mut self, offset: usize, words: &[u64]);
+    /// Runs the operation to completion, returning the final carry (or borrow).
+    fn run(&mut self, operation: &Operation) -> bool;
+}
+
+impl<E: Engine + ?Sized> Engine for &mut E {
+    fn read(&self, offset: usize, words: &mut [u64]) {
+        (**self).read(offset, words)
+    }
+
+    fn write(&mut self, offset: usize, words: &[u64]) {
+        (**self).write(offset, words)
+    }
+
+    fn run(&mut self, operation: &Operation) -> bool {
+        (**self).run(operation)
+    }
+}
+
+impl Engine for Casper<Enabled> {
+    fn read(&self, offset: usize, words: &mut [u64]) {
+        assert!(offset + words.len() <= RAM_WORDS);
+        for (i, word) in words.iter_mut().enumerate() {
+            let at = 4 * (offset + i);
+            // the RAM window belongs to CASPER, which is idle between operations
+            let (low, high) = unsafe {
+                (
+                    ptr::read_volatile((RAM_LOW + at) as *const u32),
+                    ptr::read_volatile((RAM_HIGH + at) as *const u32),
+                )
+            };
+            *word = u64::from(low) | u64::from(high) << 32;
+        }
+    }
+
+    fn write(&mut self, offset: usize, words: &[u64]) {
+        assert!(offset + words.len() <= RAM_WORDS);
+        for (i, word) in words.iter().enumerate() {
+            let at = 4 * (offset + i);
+            unsafe {
+                ptr::write_volatile((RAM_LOW + at) as *mut u32, *word as u32);
+                ptr::write_volatile((RAM_HIGH + at) as *mut u32, (*word >> 32) as u32);
+            }
+        }
+    }
+
+    fn run(&mut self, operation: &Operation) -> bool {
+        operation.check();
+        // the offset fields hold byte offsets (the PAC has ABOFF one bit wide)
+        let ctrl0 = (8 * operation.ab) | ((8 * operation.cd) << 16);
+        let ctrl1 =
+            (operation.len - 1) | ((operation.opcode as usize) << 8) | ((8 * operation.res) << 16);
+        self.raw.ctrl0.write(|w| unsafe { w.bits(ctrl0 as u32) });
+        // starts the operation
+        self.raw.ctrl1.write(|w| unsafe { w.bits(ctrl1 as u32) });
+
+        while self.raw.status.read().done().is_busy() {
+            continue;
+        }
+        let carry = self.raw.status.read().carry().is_carry();
+        self.raw.status.write(|w| w.done().set_bit());
+        carry
+    }
+}
+
+/// A number in the RAM window, allocated by `Bignum::alloc`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Slot {
+    offset: usize,
+    len: usize,
+}
+
+impl Slot {
+    /// Length in 64-bit words
+    pub fn words(&self) -> usize {
+        self.len
+    }
+
+    /// The `len` words from `start` on. Panics if they are not within the slot.
+    pub fn sub(&self, start: usize, len: usize) -> Slot {
+        assert!(len >= 1 && start + len <= self.len);
+        Slot {
+            offset: self.offset + start,
+            len,
+        }
+    }
+
+    fn overlaps(&self, other: &Slot) -> bool {
+        self.offset < other.offset + other.len && other.offset < self.offset + self.len
+    }
+}
+
+/// Multiprecision arithmetic on slots in the RAM window, see the module documentation.
+///
+/// Slots are allocated stack-like: `scope` frees the slots allocated within it, `clear`
+/// frees all of them. Slots must not be used after they are freed, nor with another
+/// `Bignum`. All operations panic if the lengths of their operands do not match.
+pub struct Bignum<E: Engine> {
+    engine: E,
+    top: usize,
+}
+
+impl<E: Engine> Bignum<E> {
+    pub fn new(engine: E) -> Self {
+        Self { engine, top: 0 }
+    }
+
+    pub fn release(self) -> E {
+        self.engine
+    }
+
+    /// The engine, e.g. to run other operations.
+    pub fn engine(&mut self) -> &mut E {
+        &mut self.engine
+    }
+
+    /// Allocates a number, set to zero. Panics if the RAM window is full.
+    pub fn alloc(&mut self, words: usize) -> Slot {
+        assert!(words >= 1 && self.top + words <= RAM_WORDS);
+        let slot = Slot {
+            offset: self.top,
+            len: words,
+        };
+        self.top += words;
+        self.zero(slot);
+        slot
+    }
+
+    /// Runs `f`, then frees the slots it allocated.
+    pub fn scope<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let top = self.top;
+        let result = f(self);
+        self.top = top;
+        result
+    }
+
+    /// Frees all slots.
+    pub fn clear(&mut self) {
+        self.top = 0;
+    }
+
+    /// Zeroes the free part of the RAM window, e.g. after computations with secrets.
+    pub fn wipe(&mut self) {
+        for offset in self.top..RAM_WORDS {
+            self.engine.write(offset, &[0]);
+        }
+    }
+
+    pub fn zero(&mut self, r: Slot) {
+        for i in 0..r.len {
+            self.engine.write(r.offset + i, &[0]);
+        }
+    }
+
+    /// Sets `r` to `value`, zero-extended. Panics if `value` is longer.
+    pub fn load(&mut self, r: Slot, value: &[u64]) {
+        assert!(value.len() <= r.len);
+        self.engine.write(r.offset, value);
+        for i in value.len()..r.len {
+            self.engine.write(r.offset + i, &[0]);
+        }
+    }
+
+    /// Sets `r` to the big-endian `bytes`, zero-extended. Panics if they are longer.
+    pub fn load_be_bytes(&mut self, r: Slot, bytes: &[u8]) {
+        assert!(bytes.len() <= 8 * r.len);
+        for i in 0..r.len {
+            let end = bytes.len().saturating_sub(8 * i);
+            let start = end.saturating_sub(8);
+            let mut word = [0u8; 8];
+            word[8 - (end - start)..].copy_from_slice(&bytes[start..end]);
+            self.engine.write(r.offset + i, &[u64::from_be_bytes(word)]);
+        }
+    }
+
+    /// Reads `a`, into as many words.
+    pub fn store(&self, a: Slot, value: &mut [u64]) {
+        assert!(value.len() == a.len);
+        self.engine.read(a.offset, value);
+    }
+
+    /// Reads the low `bytes.len()` bytes of `a`, big-endian.
+    pub fn store_be_bytes(&self, a: Slot, bytes: &mut [u8]) {
+        assert!(bytes.len() <= 8 * a.len);
+        let len = bytes.len();
+        for i in 0..a.len {
+            let end = len.saturating_sub(8 * i);
+            let start = end.saturating_sub(8);
+            if start == end {
+                break;
+            }
+            let mut word = [0u64];
+            self.engine.read(a.offset + i, &mut word);
+            bytes[start..end].copy_from_slice(&word[0].to_be_bytes()[8 - (end - start)..]);
+        }
+    }
+
+    /// `r = a`
+    pub fn copy(&mut self, r: Slot, a: Slot) {
+        assert!(r.len == a.len);
+        self.run(Operations::Copy, a, 0, r);
+    }
+
+    /// `r += a`, returns the carry.
+    pub fn add(&mut self, r: Slot, a: Slot) -> bool {
+        assert!(r.len == a.len);
+        self.run(Operations::Add64, a, 0, r)
+    }
+
+    /// `r -= a`, returns the borrow.
+    pub fn sub(&mut self, r: Slot, a: Slot) -> bool {
+        assert!(r.len == a.len);
+        self.run(Operations::Sub64, a, 0, r)
+    }
+
+    /// `r ^= a`
+    pub fn xor(&mut self, r: Slot, a: Slot) {
+        assert!(r.len == a.len);
+        self.run(Operations::Xor64, a, 0, r);
+    }
+
+    /// Swaps `a` and `b` if `choice` is set, in time and with operations independent of
+    /// `choice`.
+    pub fn conditional_swap(&mut self, a: Slot, b: Slot, choice: bool) {
+        assert!(a.len == b.len && !a.overlaps(&b));
+        self.scope(|bignum| {
+            // mask = (a ^ b) * choice
+            let difference = bignum.alloc(a.len);
+            let mask = bignum.alloc(a.len + 1);
+            let factor = bignum.alloc(1);
+            bignum.load(factor, &[choice as u64]);
+            bignum.copy(difference, a);
+            bignum.xor(difference, b);
+            bignum.run(Operations::Mul6464NoSum, difference, factor.offset, mask);
+            bignum.xor(a, mask.sub(0, a.len));
+            bignum.xor(b, mask.sub(0, a.len));
+        })
+    }
+
+    /// `r = a` if `choice` is set, in time and with operations independent of `choice`.
+    pub fn conditional_copy(&mut self, r: Slot, a: Slot, choice: bool) {
+        assert!(r.len == a.len && !r.overlaps(&a));
+        self.scope(|bignum| {
+            // r ^= (r ^ a) * choice
+            let difference = bignum.alloc(a.len);
+            let mask = bignum.alloc(a.len + 1);
+            let factor = bignum.alloc(1);
+            bignum.load(factor, &[choice as u64]);
+            bignum.copy(difference, r);
+            bignum.xor(difference, a);
+            bignum.run(Operations::Mul6464NoSum, difference, factor.offset, mask);
+            bignum.xor(r, mask.sub(0, a.len));
+        })
+    }
+
+    /// `r += a * b` for a single word `b`, with `r` one word longer than `a`; returns
+    /// the carry.
+    pub fn mul_add_word(&mut self, r: Slot, a: Slot, b: Slot) -> bool {
+        assert!(r.len == a.len + 1 && b.len == 1);
+        assert!(!r.overlaps(&a) && !r.overlaps(&b));
+        self.run(Operations::Mul6464FullSum, a, b.offset, r)
+    }
+
+    /// `r = a * b`, with `r` as long as `a` and `b` together.
+    pub fn mul(&mut self, r: Slot, a: Slot, b: Slot) {
+        assert!(r.len == a.len + b.len);
+        assert!(!r.overlaps(&a) && !r.overlaps(&b));
+        // one row per word of `b`, each adding `a * b[i]` into `r[i..=i + a.len]`
+        self.run(Operations::Mul6464NoSum, a, b.offset, r);
+        for i in 1..b.len {
+            self.run(Operations::Mul6464Sum, a, b.offset + i, r.sub(i, a.len + 1));
+        }
+    }
+
+    /// `r = a << bits` for `bits < 64`, with `r` one word longer than `a`.
+    pub fn shl(&mut self, r: Slot, a: Slot, bits: u32) {
+        assert!(r.len == a.len + 1 && bits < 64);
+        assert!(!r.overlaps(&a));
+        self.scope(|bignum| {
+            let factor = bignum.alloc(1);
+            bignum.load(factor, &[1 << bits]);
+            bignum.run(Operations::Mul6464NoSum, a, factor.offset, r);
+        })
+    }
+
+    /// `r = a >> bits` for `bits < 64`.
+    pub fn shr(&mut self, r: Slot, a: Slot, bits: u32) {
+        assert!(r.len == a.len && bits < 64);
+        if bits == 0 {
+            return self.copy(r, a);
+        }
+        // the high words of `a << (64 - bits)`
+        self.scope(|bignum| {
+            let shifted = bignum.alloc(a.len + 1);
+            bignum.shl(shifted, a, 64 - bits);
+            bignum.copy(r, shifted.sub(1, a.len));
+        })
+    }
+
+    /// Compares `a` and `b`, in time independent of their values.
+    pub fn compare(&mut self, a: Slot, b: Slot) -> Ordering {
+        assert!(a.len == b.len);
+        self.scope(|bignum| {
+            let difference = bignum.alloc(a.len);
+            bignum.copy(difference, a);
+            let borrow = bignum.sub(difference, b);
+            let zero = bignum.is_zero(difference);
+            match (borrow, zero) {
+                (true, _) => Ordering::Less,
+                (false, true) => Ordering::Equal,
+                (false, false) => Ordering::Greater,
+            }
+        })
+    }
+
+    /// Reads all words of `a`, in time independent of its value.
+    pub fn is_zero(&self, a: Slot) -> bool {
+        let mut bits = 0;
+        for i in 0..a.len {
+            let mut word = [0u64];
+            self.engine.read(a.offset + i, &mut word);
+            bits |= word[0];
+        }
+        bits == 0
+    }
+
+    fn run(&mut self, opcode: Operations, a: Slot, cd: usize, r: Slot) -> bool {
+        self.engine.run(&Operation {
+            opcode,
+            ab: a.offset,
+            cd,
+            res: r.offset,
+            len: a.len,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // expected values from Python's integers
+    const A: [u64; 3] = [0x138dda71e3658966, 0x0a3aee4966660879, 0x963f389496afcff5];
+    const B: [u64; 2] = [0xe338e970dc1afab8, 0xa27056f73a818b9f];
+
+    fn load<const N: usize>(bignum: &mut Bignum<Model>, value: &[u64; N]) -> Slot {
+        let slot = bignum.alloc(N);
+        bignum.load(slot, value);
+        slot
+    }
+
+    fn store<const N: usize>(bignum: &Bignum<Model>, slot: Slot) -> [u64; N] {
+        let mut words = [0u64; N];
+        bignum.store(slot, &mut words);
+        words
+    }
+
+    #[test]
+    fn mul() {
+        let mut bignum = Bignum::new(Model::new());
+        let (a, b, r) = (
+            load(&mut bignum, &A),
+            load(&mut bignum, &B),
+            bignum.alloc(5),
+        );
+        bignum.mul(r, a, b);
+        assert_eq!(
+            store(&bignum, r),
+            [
+                0x0b0e86f035845d50,
+                0x470eefcf4c34f171,
+                0x56f4676de2082374,
+                0xd687c741a32eaadb,
+                0x5f55f0811eb50b1f,
+            ]
+        );
+
+        // carries through all rows
+        let max = load(&mut bignum, &[u64::MAX; 3]);
+        let r = bignum.alloc(6);
+        bignum.mul(r, max, max);
+        assert_eq!(
+            store(&bignum, r),
+            [1, 0, 0, u64::MAX - 1, u64::MAX, u64::MAX]
+        );
+    }
+
+    #[test]
+    fn shifts() {
+        let mut bignum = Bignum::new(Model::new());
+        let a = load(&mut bignum, &A);
+        let (left, right) = (bignum.alloc(4), bignum.alloc(3));
+
+        bignum.shl(left, a, 0);
+        assert_eq!(store(&bignum, left), [A[0], A[1], A[2], 0]);
+        bignum.shl(left, a, 1);
+        assert_eq!(
+            store(&bignum, left),
+            [
+                0x271bb4e3c6cb12cc,
+                0x1475dc92cccc10f2,
+                0x2c7e71292d5f9fea,
+                1
+            ]
+        );
+        bignum.shl(left, a, 63);
+        assert_eq!(
+            store(&bignum, left),
+            [
+                0,
+                0x89c6ed38f1b2c4b3,
+                0x851d7724b333043c,
+                0x4b1f9c4a4b57e7fa
+            ]
+        );
+
+        bignum.shr(right, a, 0);
+        assert_eq!(store(&bignum, right), A);
+        bignum.shr(right, a, 1);
+        assert_eq!(
+            store(&bignum, right),
+            [0x89c6ed38f1b2c4b3, 0x851d7724b333043c, 0x4b1f9c4a4b57e7fa]
+        );
+        bignum.shr(right, a, 63);
+        assert_eq!(
+            store(&bignum, right),
+            [0x1475dc92cccc10f2, 0x2c7e71292d5f9fea, 1]
+        );
+    }
+
+    #[test]
+    fn compare() {
+        let mut bignum = Bignum::new(Model::new());
+        let a = load(&mut bignum, &A);
+        let low = load(&mut bignum, &[A[0] + 1, A[1], A[2]]);
+        let high = load(&mut bignum, &[A[0], A[1], A[2] - 1]);
+
+        assert_eq!(bignum.compare(a, a), Ordering::Equal);
+        assert_eq!(bignum.compare(a, low), Ordering::Less);
+        assert_eq!(bignum.compare(low, a), Ordering::Greater);
+        assert_eq!(bignum.compare(a, high), Ordering::Greater);
+        assert_eq!(bignum.compare(high, low), Ordering::Less);
+        // the temporaries are freed again
+        assert_eq!(bignum.top, 9);
+    }
+
+    #[test]
+    fn conditional() {
+        let mut bignum = Bignum::new(Model::new());
+        let (a, b) = (load(&mut bignum, &A), load(&mut bignum, &[B[0], B[1], 0]));
+
+        bignum.conditional_swap(a, b, false);
+        assert_eq!(store(&bignum, a), A);
+        assert_eq!(store(&bignum, b), [B[0], B[1], 0]);
+        bignum.conditional_swap(a, b, true);
+        assert_eq!(store(&bignum, a), [B[0], B[1], 0]);
+        assert_eq!(store(&bignum, b), A);
+
+        bignum.conditional_copy(a, b, false);
+        assert_eq!(store(&bignum, a), [B[0], B[1], 0]);
+        bignum.conditional_copy(a, b, true);
+        assert_eq!(store(&bignum, a), A);
+        assert_eq!(store(&bignum, b), A);
+        assert_eq!(bignum.top, 6);
+    }
+
+    fn operation(opcode: Operations, ab: usize, cd: usize, res: usize, len: usize) -> Operation {
+        Operation {
+            opcode,
+            ab,
+            cd,
+            res,
+            len,
+        }
+    }
+
+    #[test]
+    fn operation_bounds() {
+        let last = RAM_WORDS - MAX_WORDS;
+        operation(Operations::Add64, 0, 0, 0, 1).check();
+        operation(Operations::Add64, last, RAM_WORDS - 1, last, MAX_WORDS).check();
+        // multiplications write one more word
+        operation(Operations::Add64, 0, 0, RAM_WORDS - 2, 2).check();
+        operation(Operations::Mul6464Sum, last, 0, last - 1, MAX_WORDS).check();
+    }
+
+    #[test]
+    #[should_panic]
+    fn operation_without_words() {
+        operation(Operations::Add64, 0, 0, 0, 0).check();
+    }
+
+    #[test]
+    #[should_panic]
+    fn operation_too_long() {
+        operation(Operations::Add64, 0, 0, 0, MAX_WORDS + 1).check();
+    }
+
+    #[test]
+    #[should_panic]
+    fn operand_past_the_window() {
+        operation(Operations::Add64, RAM_WORDS - 1, 0, 0, 2).check();
+    }
+
+    #[test]
+    #[should_panic]
+    fn word_past_the_window() {
+        operation(Operations::Mul6464NoSum, 0, RAM_WORDS, 8, 2).check();
+    }
+
+    #[test]
+    #[should_panic]
+    fn carry_past_the_window() {
+        operation(Operations::Mul6464NoSum, 0, 0, RAM_WORDS - 2, 2).check();
+    }
+}
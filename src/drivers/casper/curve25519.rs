@@ -0,0 +1,589 @@
+//! Curve25519 arithmetic on `Bignum`: the twisted Edwards curve edwards25519 (for
+//! Ed25519) and the Montgomery ladder of X25519.
+//!
+//! Field elements modulo `p = 2^255 - 19` are in Montgomery form, so CASPER does the
+//! multiplications and the CPU only the reduction factor of each row, as for the NIST
+//! curves in `ecc`. Points are in extended coordinates `(X : Y : Z : T)` with
+//! `x = X/Z`, `y = Y/Z`, `xy = T/Z`, and are added with the unified formulas of Hisil,
+//! Wong, Carter and Dawson ("Twisted Edwards curves revisited", 2008), which are
+//! complete on edwards25519.
+//!
+//! Encodings follow RFC 8032 and RFC 7748: little-endian, unlike the big-endian
+//! scalars of `Group::mul`.
+
+use super::{Bignum, Engine, Modulus, Slot};
+
+const WORDS: usize = 4;
+
+// 2^255 - 19
+const P: [u64; WORDS] = [
+    0xffff_ffff_ffff_ffed,
+    0xffff_ffff_ffff_ffff,
+    0xffff_ffff_ffff_ffff,
+    0x7fff_ffff_ffff_ffff,
+];
+// 2^252 + 27742317777372353535851937790883648493, the order of the base point
+const L: [u64; WORDS] = [
+    0x5812_631a_5cf5_d3ed,
+    0x14de_f9de_a2f7_9cd6,
+    0x0000_0000_0000_0000,
+    0x1000_0000_0000_0000,
+];
+// -121665 / 121666
+const D: [u64; WORDS] = [
+    0x75eb_4dca_1359_78a3,
+    0x0070_0a4d_4141_d8ab,
+    0x8cc7_4079_7779_e898,
+    0x5203_6cee_2b6f_fe73,
+];
+// 2^((p - 1) / 4)
+const SQRT_M1: [u64; WORDS] = [
+    0xc4ee_1b27_4a0e_a0b0,
+    0x2f43_1806_ad2f_e478,
+    0x2b4d_0099_3dfb_d7a7,
+    0x2b83_2480_4fc1_df0b,
+];
+const BASE_X: [u64; WORDS] = [
+    0xc956_2d60_8f25_d51a,
+    0x692c_c760_9525_a7b2,
+    0xc0a4_e231_fdd6_dc5c,
+    0x2169_36d3_cd6e_53fe,
+];
+// 4/5
+const BASE_Y: [u64; WORDS] = [
+    0x6666_6666_6666_6658,
+    0x6666_6666_6666_6666,
+    0x6666_6666_6666_6666,
+    0x6666_6666_6666_6666,
+];
+// (486662 - 2) / 4
+const A24: u64 = 121665;
+
+// big-endian exponents: p - 2 (inverses) and (p - 5) / 8 (square roots)
+const P_MINUS_2: [u8; 32] = exponent(0x7f, 0xeb);
+const P_MINUS_5_OVER_8: [u8; 32] = exponent(0x0f, 0xfd);
+
+const fn exponent(top: u8, bottom: u8) -> [u8; 32] {
+    let mut bytes = [0xff; 32];
+    bytes[0] = top;
+    bytes[31] = bottom;
+    bytes
+}
+
+/// The u-coordinate of the X25519 base point
+pub const X25519_BASEPOINT: [u8; 32] = [
+    9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+/// A point in extended coordinates, allocated by `Edwards25519::alloc`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Point {
+    x: Slot,
+    y: Slot,
+    z: Slot,
+    t: Slot,
+}
+
+/// The group of points of edwards25519, with its constants in the RAM window
+///
+/// Its slots are allocated by `new`, they must outlive the `Edwards25519`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Edwards25519 {
+    p: Modulus,
+    l: Modulus,
+    // d, 2d and the square root of -1, in Montgomery form
+    d: Slot,
+    d2: Slot,
+    sqrt_m1: Slot,
+}
+
+impl Edwards25519 {
+    pub fn new<E: Engine>(bignum: &mut Bignum<E>) -> Self {
+        let p = bignum.alloc(WORDS);
+        bignum.load(p, &P);
+        let p = Modulus::new(bignum, p);
+        let l = bignum.alloc(WORDS);
+        bignum.load(l, &L);
+        let l = Modulus::new(bignum, l);
+
+        let d = field_constant(bignum, &p, &D);
+        let d2 = bignum.alloc(WORDS);
+        p.add(bignum, d2, d, d);
+        let sqrt_m1 = field_constant(bignum, &p, &SQRT_M1);
+        Self {
+            p,
+            l,
+            d,
+            d2,
+            sqrt_m1,
+        }
+    }
+
+    /// The field modulus
+    pub fn field(&self) -> &Modulus {
+        &self.p
+    }
+
+    /// The order of the base point
+    pub fn order(&self) -> &Modulus {
+        &self.l
+    }
+
+    /// Allocates a point, set to the neutral element `(0, 1)`.
+    pub fn alloc<E: Engine>(&self, bignum: &mut Bignum<E>) -> Point {
+        let point = Point {
+            x: bignum.alloc(WORDS),
+            y: bignum.alloc(WORDS),
+            z: bignum.alloc(WORDS),
+            t: bignum.alloc(WORDS),
+        };
+        self.p.one(bignum, point.y);
+        self.p.one(bignum, point.z);
+        point
+    }
+
+    /// Sets `r` to the base point.
+    pub fn base<E: Engine>(&self, bignum: &mut Bignum<E>, r: Point) {
+        bignum.load(r.x, &BASE_X);
+        bignum.load(r.y, &BASE_Y);
+        self.p.to_montgomery(bignum, r.x, r.x);
+        self.p.to_montgomery(bignum, r.y, r.y);
+        self.p.one(bignum, r.z);
+        self.p.mul(bignum, r.t, r.x, r.y);
+    }
+
+    /// Sets `r` to the point of the 32-byte `encoding`, returns false (with `r`
+    /// undefined) unless it is the canonical encoding of a point on the curve.
+    pub fn decompress<E: Engine>(&self, bignum: &mut Bignum<E>, r: Point, encoding: &[u8]) -> bool {
+        assert!(encoding.len() == 32);
+        let mut y = [0u8; 32];
+        y.copy_from_slice(encoding);
+        let sign = y[31] >> 7;
+        y[31] &= 0x7f;
+        y.reverse();
+        bignum.load_be_bytes(r.y, &y);
+        if !self.p.contains(bignum, r.y) {
+            return false;
+        }
+        let p = &self.p;
+        p.to_montgomery(bignum, r.y, r.y);
+        p.one(bignum, r.z);
+
+        bignum.scope(|bignum| {
+            // x^2 = u / v with u = y^2 - 1, v = d y^2 + 1; x = u v^3 (u v^7)^((p - 5) / 8),
+            // up to a factor sqrt(-1)
+            let u = bignum.alloc(WORDS);
+            let v = bignum.alloc(WORDS);
+            let v3 = bignum.alloc(WORDS);
+            let check = bignum.alloc(WORDS);
+            p.mul(bignum, u, r.y, r.y);
+            p.mul(bignum, v, u, self.d);
+            p.sub(bignum, u, u, r.z);
+            p.add(bignum, v, v, r.z);
+
+            p.mul(bignum, v3, v, v);
+            p.mul(bignum, v3, v3, v);
+            p.mul(bignum, check, v3, v3);
+            p.mul(bignum, check, check, v);
+            p.mul(bignum, check, check, u);
+            p.pow_montgomery(bignum, check, check, &P_MINUS_5_OVER_8);
+            p.mul(bignum, r.x, u, v3);
+            p.mul(bignum, r.x, r.x, check);
+
+            // v x^2 is u or -u
+            p.mul(bignum, check, r.x, r.x);
+            p.mul(bignum, check, check, v);
+            if bignum.compare(check, u) != core::cmp::Ordering::Equal {
+                p.add(bignum, check, check, u);
+                if !bignum.is_zero(check) {
+                    return false;
+                }
+                p.mul(bignum, r.x, r.x, self.sqrt_m1);
+            }
+
+            // the sign is the parity of x
+            p.from_montgomery(bignum, check, r.x);
+            let mut low = [0u8];
+            bignum.store_be_bytes(check, &mut low);
+            if bignum.is_zero(check) && sign == 1 {
+                return false;
+            }
+            if low[0] & 1 != sign {
+                bignum.zero(check);
+                p.sub(bignum, r.x, check, r.x);
+            }
+            p.mul(bignum, r.t, r.x, r.y);
+            true
+        })
+    }
+
+    /// Writes the 32-byte encoding of `a`.
+    pub fn compress<E: Engine>(&self, bignum: &mut Bignum<E>, a: Point, encoding: &mut [u8]) {
+        assert!(encoding.len() == 32);
+        bignum.scope(|bignum| {
+            let inverse = bignum.alloc(WORDS);
+            let coordinate = bignum.alloc(WORDS);
+            self.p.pow_montgomery(bignum, inverse, a.z, &P_MINUS_2);
+
+            let mut x = [0u8];
+            self.p.mul(bignum, coordinate, a.x, inverse);
+            self.p.from_montgomery(bignum, coordinate, coordinate);
+            bignum.store_be_bytes(coordinate, &mut x);
+            self.p.mul(bignum, coordinate, a.y, inverse);
+            self.p.from_montgomery(bignum, coordinate, coordinate);
+            bignum.store_be_bytes(coordinate, encoding);
+            encoding.reverse();
+            encoding[31] |= x[0] << 7;
+        })
+    }
+
+    /// `r = a + b`, complete. `r` may be `a` or `b`.
+    pub fn add<E: Engine>(&self, bignum: &mut Bignum<E>, r: Point, a: Point, b: Point) {
+        let p = &self.p;
+        bignum.scope(|bignum| {
+            let t0 = bignum.alloc(WORDS);
+            let t1 = bignum.alloc(WORDS);
+            let e = bignum.alloc(WORDS);
+            let f = bignum.alloc(WORDS);
+            let g = bignum.alloc(WORDS);
+            let h = bignum.alloc(WORDS);
+
+            // A = (Y1 - X1)(Y2 - X2), B = (Y1 + X1)(Y2 + X2)
+            p.sub(bignum, t0, a.y, a.x);
+            p.sub(bignum, t1, b.y, b.x);
+            p.mul(bignum, e, t0, t1);
+            p.add(bignum, t0, a.y, a.x);
+            p.add(bignum, t1, b.y, b.x);
+            p.mul(bignum, h, t0, t1);
+            // E = B - A, H = B + A
+            p.sub(bignum, t0, h, e);
+            p.add(bignum, h, h, e);
+            bignum.copy(e, t0);
+
+            // C = 2d T1 T2, D = 2 Z1 Z2
+            p.mul(bignum, t0, a.t, b.t);
+            p.mul(bignum, t0, t0, self.d2);
+            p.mul(bignum, t1, a.z, b.z);
+            p.add(bignum, t1, t1, t1);
+            // F = D - C, G = D + C
+            p.sub(bignum, f, t1, t0);
+            p.add(bignum, g, t1, t0);
+
+            p.mul(bignum, r.x, e, f);
+            p.mul(bignum, r.y, g, h);
+            p.mul(bignum, r.t, e, h);
+            p.mul(bignum, r.z, f, g);
+        })
+    }
+
+    /// `r = -a`. `r` may be `a`.
+    pub fn neg<E: Engine>(&self, bignum: &mut Bignum<E>, r: Point, a: Point) {
+        bignum.scope(|bignum| {
+            let zero = bignum.alloc(WORDS);
+            self.p.sub(bignum, r.x, zero, a.x);
+            self.p.sub(bignum, r.t, zero, a.t);
+        });
+        bignum.copy(r.y, a.y);
+        bignum.copy(r.z, a.z);
+    }
+
+    /// `r = scalar * a` for a big-endian scalar of 32 bytes, in time independent of the
+    /// scalar. `r` may be `a`.
+    pub fn mul<E: Engine>(&self, bignum: &mut Bignum<E>, r: Point, scalar: &[u8], a: Point) {
+        assert!(scalar.len() == 32);
+        bignum.scope(|bignum| {
+            // r0 = k * a, r1 = (k + 1) * a for the bits k processed so far
+            let r0 = self.alloc(bignum);
+            let r1 = self.alloc(bignum);
+            self.copy(bignum, r1, a);
+            for byte in scalar {
+                for bit in (0..8).rev() {
+                    let choice = byte >> bit & 1 == 1;
+                    self.swap(bignum, r0, r1, choice);
+                    self.add(bignum, r1, r0, r1);
+                    self.add(bignum, r0, r0, r0);
+                    self.swap(bignum, r0, r1, choice);
+                }
+            }
+            self.copy(bignum, r, r0);
+        })
+    }
+
+    /// `r = u1 * B + u2 * a` for big-endian scalars of 32 bytes; the running time
+    /// depends on the scalars.
+    pub fn mul_add_public<E: Engine>(
+        &self,
+        bignum: &mut Bignum<E>,
+        r: Point,
+        u1: &[u8],
+        u2: &[u8],
+        a: Point,
+    ) {
+        assert!(u1.len() == 32 && u2.len() == 32);
+        bignum.scope(|bignum| {
+            let b = self.alloc(bignum);
+            self.base(bignum, b);
+            let sum = self.alloc(bignum);
+            self.add(bignum, sum, b, a);
+            let accumulator = self.alloc(bignum);
+
+            for (byte1, byte2) in u1.iter().zip(u2.iter()) {
+                for bit in (0..8).rev() {
+                    self.add(bignum, accumulator, accumulator, accumulator);
+                    match (byte1 >> bit & 1, byte2 >> bit & 1) {
+                        (1, 1) => self.add(bignum, accumulator, accumulator, sum),
+                        (1, 0) => self.add(bignum, accumulator, accumulator, b),
+                        (0, 1) => self.add(bignum, accumulator, accumulator, a),
+                        _ => {}
+                    }
+                }
+            }
+            self.copy(bignum, r, accumulator);
+        })
+    }
+
+    pub fn copy<E: Engine>(&self, bignum: &mut Bignum<E>, r: Point, a: Point) {
+        bignum.copy(r.x, a.x);
+        bignum.copy(r.y, a.y);
+        bignum.copy(r.z, a.z);
+        bignum.copy(r.t, a.t);
+    }
+
+    fn swap<E: Engine>(&self, bignum: &mut Bignum<E>, a: Point, b: Point, choice: bool) {
+        bignum.conditional_swap(a.x, b.x, choice);
+        bignum.conditional_swap(a.y, b.y, choice);
+        bignum.conditional_swap(a.z, b.z, choice);
+        bignum.conditional_swap(a.t, b.t, choice);
+    }
+}
+
+/// X25519 (RFC 7748): the u-coordinate of `scalar * u`, with the scalar clamped.
+///
+/// The ladder runs the same operations whatever the scalar, and the free part of the RAM
+/// window is wiped afterwards. An all-zero result means `u` has small order; callers of
+/// key agreement protocols may want to reject it.
+pub fn x25519<E: Engine>(bignum: &mut Bignum<E>, scalar: &[u8; 32], u: &[u8; 32]) -> [u8; 32] {
+    let mut k = *scalar;
+    k[0] &= 248;
+    k[31] &= 127;
+    k[31] |= 64;
+    k.reverse();
+    let mut encoding = *u;
+    encoding[31] &= 0x7f;
+    encoding.reverse();
+
+    let mut result = [0u8; 32];
+    bignum.scope(|bignum| {
+        let p = bignum.alloc(WORDS);
+        bignum.load(p, &P);
+        let p = Modulus::new(bignum, p);
+
+        // u may be non-canonical, up to 2^255 - 1
+        let x1 = bignum.alloc(WORDS);
+        bignum.scope(|bignum| {
+            let wide = bignum.alloc(WORDS + 1);
+            bignum.load_be_bytes(wide, &encoding);
+            p.reduce(bignum, x1, wide);
+        });
+        p.to_montgomery(bignum, x1, x1);
+        let a24 = bignum.alloc(WORDS);
+        bignum.load(a24, &[A24]);
+        p.to_montgomery(bignum, a24, a24);
+
+        let (x2, z2, x3, z3) = (
+            bignum.alloc(WORDS),
+            bignum.alloc(WORDS),
+            bignum.alloc(WORDS),
+            bignum.alloc(WORDS),
+        );
+        let (a, b, c, d) = (
+            bignum.alloc(WORDS),
+            bignum.alloc(WORDS),
+            bignum.alloc(WORDS),
+            bignum.alloc(WORDS),
+        );
+        p.one(bignum, x2);
+        bignum.copy(x3, x1);
+        p.one(bignum, z3);
+
+        let mut swap = false;
+        for t in (0..255).rev() {
+            let bit = k[31 - t / 8] >> (t % 8) & 1 == 1;
+            swap ^= bit;
+            bignum.conditional_swap(x2, x3, swap);
+            bignum.conditional_swap(z2, z3, swap);
+            swap = bit;
+
+            // a = x2 + z2, b = x2 - z2, c = x3 + z3, d = x3 - z3
+            p.add(bignum, a, x2, z2);
+            p.sub(bignum, b, x2, z2);
+            p.add(bignum, c, x3, z3);
+            p.sub(bignum, d, x3, z3);
+            // da = d * a, cb = c * b
+            p.mul(bignum, d, d, a);
+            p.mul(bignum, c, c, b);
+            // x3 = (da + cb)^2, z3 = x1 (da - cb)^2
+            p.add(bignum, x3, d, c);
+            p.mul(bignum, x3, x3, x3);
+            p.sub(bignum, z3, d, c);
+            p.mul(bignum, z3, z3, z3);
+            p.mul(bignum, z3, z3, x1);
+            // aa = a^2, bb = b^2, x2 = aa * bb, z2 = e (aa + a24 e) with e = aa - bb
+            p.mul(bignum, a, a, a);
+            p.mul(bignum, b, b, b);
+            p.mul(bignum, x2, a, b);
+            p.sub(bignum, b, a, b);
+            p.mul(bignum, z2, b, a24);
+            p.add(bignum, z2, z2, a);
+            p.mul(bignum, z2, z2, b);
+        }
+        bignum.conditional_swap(x2, x3, swap);
+        bignum.conditional_swap(z2, z3, swap);
+
+        p.pow_montgomery(bignum, z2, z2, &P_MINUS_2);
+        p.mul(bignum, x2, x2, z2);
+        p.from_montgomery(bignum, x2, x2);
+        bignum.store_be_bytes(x2, &mut result);
+    });
+    bignum.wipe();
+    for byte in k.iter_mut() {
+        unsafe { core::ptr::write_volatile(byte, 0) };
+    }
+    result.reverse();
+    result
+}
+
+// a constant of the field, in Montgomery form
+fn field_constant<E: Engine>(bignum: &mut Bignum<E>, p: &Modulus, value: &[u64]) -> Slot {
+    let slot = bignum.alloc(WORDS);
+    bignum.load(slot, value);
+    p.to_montgomery(bignum, slot, slot);
+    slot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::casper::Model;
+    use crate::testing::hex;
+
+    fn decompress(encoding: &str) -> bool {
+        let mut bignum = Bignum::new(Model::new());
+        let group = Edwards25519::new(&mut bignum);
+        let point = group.alloc(&mut bignum);
+        group.decompress(&mut bignum, point, &hex::<32>(encoding))
+    }
+
+    #[test]
+    fn decompress_canonical() {
+        let base: [u8; 32] =
+            hex("5866666666666666666666666666666666666666666666666666666666666666");
+        let mut bignum = Bignum::new(Model::new());
+        let group = Edwards25519::new(&mut bignum);
+        let point = group.alloc(&mut bignum);
+        assert!(group.decompress(&mut bignum, point, &base));
+        let mut encoding = [0u8; 32];
+        group.compress(&mut bignum, point, &mut encoding);
+        assert_eq!(encoding, base);
+
+        // y = 1 (the neutral element) and y = 0, x = sqrt(-1)
+        assert!(decompress(
+            "0100000000000000000000000000000000000000000000000000000000000000"
+        ));
+        assert!(decompress(
+            "0000000000000000000000000000000000000000000000000000000000000080"
+        ));
+        // y = 2 is not on the curve
+        assert!(!decompress(
+            "0200000000000000000000000000000000000000000000000000000000000000"
+        ));
+        // x = 0 with the sign bit set
+        assert!(!decompress(
+            "0100000000000000000000000000000000000000000000000000000000000080"
+        ));
+    }
+
+    // y = p + 1 and y = p: valid points, but their encodings are not canonical
+    #[test]
+    fn decompress_non_canonical() {
+        assert!(!decompress(
+            "eeffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff7f"
+        ));
+        assert!(!decompress(
+            "edffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff7f"
+        ));
+        assert!(!decompress(
+            "edffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff"
+        ));
+    }
+
+    // RFC 7748, section 5.2
+    #[test]
+    fn rfc7748() {
+        let mut bignum = Bignum::new(Model::new());
+        assert_eq!(
+            x25519(
+                &mut bignum,
+                &hex("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4"),
+                &hex("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c"),
+            ),
+            hex("c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552")
+        );
+        // the top bit of u is masked
+        assert_eq!(
+            x25519(
+                &mut bignum,
+                &hex("4b66e9d4d1b4673c5ad22691957d6af5c11b6421e0ea01d42ca4169e7918ba0d"),
+                &hex("e5210f12786811d3f4b7959d0538ae2c31dbe7106fc03c3efc4cd549c715a493"),
+            ),
+            hex("95cbde9476e8907d7aade45cb4b873f88b595a68799fa152e6f8f7647aac7957")
+        );
+    }
+
+    // RFC 7748, section 5.2: k = x25519(k, u), u = old k, from k = u = 9
+    #[test]
+    fn rfc7748_iterated() {
+        let mut bignum = Bignum::new(Model::new());
+        let (mut k, mut u) = (X25519_BASEPOINT, X25519_BASEPOINT);
+        for i in 1..=1000 {
+            let next = x25519(&mut bignum, &k, &u);
+            u = k;
+            k = next;
+            if i == 1 {
+                assert_eq!(
+                    k,
+                    hex("422c8e7a6227d7bca1350b3e2bb7279f7897b87bb6854b783c60e80311ae3079")
+                );
+            }
+        }
+        assert_eq!(
+            k,
+            hex("684cf59ba83309552800ef566f2f4d3c1c3887c49360e3875f2eb94d99532c51")
+        );
+    }
+
+    // RFC 7748, section 6.1
+    #[test]
+    fn rfc7748_diffie_hellman() {
+        let mut bignum = Bignum::new(Model::new());
+        let alice: [u8; 32] =
+            hex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob: [u8; 32] = hex("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+        let alice_public = x25519(&mut bignum, &alice, &X25519_BASEPOINT);
+        assert_eq!(
+            alice_public,
+            hex("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a")
+        );
+        let bob_public = x25519(&mut bignum, &bob, &X25519_BASEPOINT);
+        assert_eq!(
+            bob_public,
+            hex("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f")
+        );
+        let shared = x25519(&mut bignum, &alice, &bob_public);
+        assert_eq!(shared, x25519(&mut bignum, &bob, &alice_public));
+        assert_eq!(
+            shared,
+            hex("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742")
+        );
+    }
+}
@@ -0,0 +1,417 @@
+//! Elliptic curve arithmetic on the NIST curves P-256 and P-384, on `Bignum`.
+//!
+//! Points are in projective coordinates `(X : Y : Z)`, in Montgomery form modulo `p`.
+//! They are added with the complete formulas of Renes, Costello and Batina ("Complete
+//! addition formulas for prime order elliptic curves", 2015, algorithm 4 for `a = -3`),
+//! which need no special cases for doubling or the point at infinity.
+//!
+//! `Group::mul` is for secret scalars: a Montgomery ladder over all bits of the scalar,
+//! with swaps that run the same operations whatever the bits. `Group::mul_add_public`
+//! (for signature verification) depends on its scalars.
+
+use core::marker::PhantomData;
+
+use elliptic_curve::{
+    bigint::{U256, U384},
+    sec1::ModulusSize,
+    FieldBytesEncoding, PrimeCurve,
+};
+use generic_array::{
+    typenum::{U32, U48},
+    GenericArray,
+};
+
+use super::{Bignum, Engine, Modulus, Slot};
+
+/// A short Weierstrass curve `y^2 = x^3 - 3x + b` of prime order
+///
+/// It is an `elliptic_curve::PrimeCurve`, so keys and points convert to and from the
+/// `elliptic-curve` types (`SecretKey`, `sec1::EncodedPoint`).
+pub trait Curve:
+    PrimeCurve + elliptic_curve::Curve<FieldBytesSize = <Self as Curve>::Size>
+{
+    /// Length of field elements and scalars, in bytes
+    type Size: ModulusSize;
+    /// Field modulus, big-endian
+    const P: &'static [u8];
+    /// Group order, big-endian
+    const N: &'static [u8];
+    const B: &'static [u8];
+    /// Generator
+    const GX: &'static [u8];
+    const GY: &'static [u8];
+}
+
+/// Field elements and scalars, big-endian
+pub type FieldBytes<C> = GenericArray<u8, <C as Curve>::Size>;
+
+/// NIST P-256 (secp256r1)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct P256;
+
+impl elliptic_curve::Curve for P256 {
+    type FieldBytesSize = U32;
+    type Uint = U256;
+    const ORDER: U256 =
+        U256::from_be_hex("ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551");
+}
+
+impl PrimeCurve for P256 {}
+
+impl FieldBytesEncoding<P256> for U256 {}
+
+impl Curve for P256 {
+    type Size = U32;
+    const P: &'static [u8] = &[
+        0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff,
+    ];
+    const N: &'static [u8] = &[
+        0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xbc, 0xe6, 0xfa, 0xad, 0xa7, 0x17, 0x9e, 0x84, 0xf3, 0xb9, 0xca, 0xc2, 0xfc, 0x63,
+        0x25, 0x51,
+    ];
+    const B: &'static [u8] = &[
+        0x5a, 0xc6, 0x35, 0xd8, 0xaa, 0x3a, 0x93, 0xe7, 0xb3, 0xeb, 0xbd, 0x55, 0x76, 0x98, 0x86,
+        0xbc, 0x65, 0x1d, 0x06, 0xb0, 0xcc, 0x53, 0xb0, 0xf6, 0x3b, 0xce, 0x3c, 0x3e, 0x27, 0xd2,
+        0x60, 0x4b,
+    ];
+    const GX: &'static [u8] = &[
+        0x6b, 0x17, 0xd1, 0xf2, 0xe1, 0x2c, 0x42, 0x47, 0xf8, 0xbc, 0xe6, 0xe5, 0x63, 0xa4, 0x40,
+        0xf2, 0x77, 0x03, 0x7d, 0x81, 0x2d, 0xeb, 0x33, 0xa0, 0xf4, 0xa1, 0x39, 0x45, 0xd8, 0x98,
+        0xc2, 0x96,
+    ];
+    const GY: &'static [u8] = &[
+        0x4f, 0xe3, 0x42, 0xe2, 0xfe, 0x1a, 0x7f, 0x9b, 0x8e, 0xe7, 0xeb, 0x4a, 0x7c, 0x0f, 0x9e,
+        0x16, 0x2b, 0xce, 0x33, 0x57, 0x6b, 0x31, 0x5e, 0xce, 0xcb, 0xb6, 0x40, 0x68, 0x37, 0xbf,
+        0x51, 0xf5,
+    ];
+}
+
+/// NIST P-384 (secp384r1)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct P384;
+
+impl elliptic_curve::Curve for P384 {
+    type FieldBytesSize = U48;
+    type Uint = U384;
+    const ORDER: U384 = U384::from_be_hex(concat!(
+        "ffffffffffffffffffffffffffffffffffffffffffffffffc7634d81f4372ddf",
+        "581a0db248b0a77aecec196accc52973"
+    ));
+}
+
+impl PrimeCurve for P384 {}
+
+impl FieldBytesEncoding<P384> for U384 {}
+
+impl Curve for P384 {
+    type Size = U48;
+    const P: &'static [u8] = &[
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xfe, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff,
+        0xff, 0xff, 0xff,
+    ];
+    const N: &'static [u8] = &[
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xc7, 0x63, 0x4d, 0x81, 0xf4, 0x37,
+        0x2d, 0xdf, 0x58, 0x1a, 0x0d, 0xb2, 0x48, 0xb0, 0xa7, 0x7a, 0xec, 0xec, 0x19, 0x6a, 0xcc,
+        0xc5, 0x29, 0x73,
+    ];
+    const B: &'static [u8] = &[
+        0xb3, 0x31, 0x2f, 0xa7, 0xe2, 0x3e, 0xe7, 0xe4, 0x98, 0x8e, 0x05, 0x6b, 0xe3, 0xf8, 0x2d,
+        0x19, 0x18, 0x1d, 0x9c, 0x6e, 0xfe, 0x81, 0x41, 0x12, 0x03, 0x14, 0x08, 0x8f, 0x50, 0x13,
+        0x87, 0x5a, 0xc6, 0x56, 0x39, 0x8d, 0x8a, 0x2e, 0xd1, 0x9d, 0x2a, 0x85, 0xc8, 0xed, 0xd3,
+        0xec, 0x2a, 0xef,
+    ];
+    const GX: &'static [u8] = &[
+        0xaa, 0x87, 0xca, 0x22, 0xbe, 0x8b, 0x05, 0x37, 0x8e, 0xb1, 0xc7, 0x1e, 0xf3, 0x20, 0xad,
+        0x74, 0x6e, 0x1d, 0x3b, 0x62, 0x8b, 0xa7, 0x9b, 0x98, 0x59, 0xf7, 0x41, 0xe0, 0x82, 0x54,
+        0x2a, 0x38, 0x55, 0x02, 0xf2, 0x5d, 0xbf, 0x55, 0x29, 0x6c, 0x3a, 0x54, 0x5e, 0x38, 0x72,
+        0x76, 0x0a, 0xb7,
+    ];
+    const GY: &'static [u8] = &[
+        0x36, 0x17, 0xde, 0x4a, 0x96, 0x26, 0x2c, 0x6f, 0x5d, 0x9e, 0x98, 0xbf, 0x92, 0x92, 0xdc,
+        0x29, 0xf8, 0xf4, 0x1d, 0xbd, 0x28, 0x9a, 0x14, 0x7c, 0xe9, 0xda, 0x31, 0x13, 0xb5, 0xf0,
+        0xb8, 0xc0, 0x0a, 0x60, 0xb1, 0xce, 0x1d, 0x7e, 0x81, 0x9d, 0x7a, 0x43, 0x1d, 0x7c, 0x90,
+        0xea, 0x0e, 0x5f,
+    ];
+}
+
+/// A point in projective coordinates, allocated by `Group::alloc`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Point {
+    x: Slot,
+    y: Slot,
+    z: Slot,
+}
+
+/// The group of points of a curve, with its constants in the RAM window
+///
+/// Its slots are allocated by `new`, they must outlive the `Group`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Group<C> {
+    p: Modulus,
+    n: Modulus,
+    // b in Montgomery form
+    b: Slot,
+    words: usize,
+    _curve: PhantomData<C>,
+}
+
+impl<C: Curve> Group<C> {
+    pub fn new<E: Engine>(bignum: &mut Bignum<E>) -> Self {
+        let words = C::P.len() / 8;
+        let p = bignum.alloc(words);
+        bignum.load_be_bytes(p, C::P);
+        let p = Modulus::new(bignum, p);
+        let n = bignum.alloc(words);
+        bignum.load_be_bytes(n, C::N);
+        let n = Modulus::new(bignum, n);
+
+        let b = bignum.alloc(words);
+        bignum.load_be_bytes(b, C::B);
+        p.to_montgomery(bignum, b, b);
+        Self {
+            p,
+            n,
+            b,
+            words,
+            _curve: PhantomData,
+        }
+    }
+
+    /// The field modulus
+    pub fn field(&self) -> &Modulus {
+        &self.p
+    }
+
+    /// The group order
+    pub fn order(&self) -> &Modulus {
+        &self.n
+    }
+
+    /// Allocates a point, set to the point at infinity.
+    pub fn alloc<E: Engine>(&self, bignum: &mut Bignum<E>) -> Point {
+        let point = Point {
+            x: bignum.alloc(self.words),
+            y: bignum.alloc(self.words),
+            z: bignum.alloc(self.words),
+        };
+        self.p.one(bignum, point.y);
+        point
+    }
+
+    /// Sets `r` to the generator.
+    pub fn generator<E: Engine>(&self, bignum: &mut Bignum<E>, r: Point) {
+        let loaded = self.load(bignum, r, C::GX, C::GY);
+        debug_assert!(loaded);
+    }
+
+    /// Sets `r` to the affine point `(x, y)`, returns false (with `r` undefined) unless
+    /// it is on the curve.
+    pub fn load<E: Engine>(&self, bignum: &mut Bignum<E>, r: Point, x: &[u8], y: &[u8]) -> bool {
+        assert!(x.len() == C::P.len() && y.len() == C::P.len());
+        bignum.load_be_bytes(r.x, x);
+        bignum.load_be_bytes(r.y, y);
+        if !self.p.contains(bignum, r.x) || !self.p.contains(bignum, r.y) {
+            return false;
+        }
+        self.p.to_montgomery(bignum, r.x, r.x);
+        self.p.to_montgomery(bignum, r.y, r.y);
+        self.p.one(bignum, r.z);
+
+        // y^2 = x^3 - 3x + b
+        bignum.scope(|bignum| {
+            let left = bignum.alloc(self.words);
+            let right = bignum.alloc(self.words);
+            let p = &self.p;
+            p.mul(bignum, left, r.y, r.y);
+            p.mul(bignum, right, r.x, r.x);
+            p.mul(bignum, right, right, r.x);
+            for _ in 0..3 {
+                p.sub(bignum, right, right, r.x);
+            }
+            p.add(bignum, right, right, self.b);
+            bignum.compare(left, right) == core::cmp::Ordering::Equal
+        })
+    }
+
+    /// Writes the affine coordinates of `a`, returns false for the point at infinity.
+    pub fn to_affine<E: Engine>(
+        &self,
+        bignum: &mut Bignum<E>,
+        a: Point,
+        x: &mut [u8],
+        y: Option<&mut [u8]>,
+    ) -> bool {
+        if bignum.is_zero(a.z) {
+            return false;
+        }
+        bignum.scope(|bignum| {
+            // 1/z = z^(p - 2)
+            let inverse = bignum.alloc(self.words);
+            let coordinate = bignum.alloc(self.words);
+            let mut exponent = GenericArray::<u8, C::Size>::default();
+            minus_two(C::P, &mut exponent);
+            self.p
+                .pow_montgomery(bignum, inverse, a.z, exponent.as_slice());
+
+            self.p.mul(bignum, coordinate, a.x, inverse);
+            self.p.from_montgomery(bignum, coordinate, coordinate);
+            bignum.store_be_bytes(coordinate, x);
+            if let Some(y) = y {
+                self.p.mul(bignum, coordinate, a.y, inverse);
+                self.p.from_montgomery(bignum, coordinate, coordinate);
+                bignum.store_be_bytes(coordinate, y);
+            }
+        });
+        true
+    }
+
+    /// `r = a + b`, complete. `r` may be `a` or `b`.
+    pub fn add<E: Engine>(&self, bignum: &mut Bignum<E>, r: Point, a: Point, b: Point) {
+        let p = &self.p;
+        let (x1, y1, z1) = (a.x, a.y, a.z);
+        let (x2, y2, z2) = (b.x, b.y, b.z);
+        bignum.scope(|bignum| {
+            let t0 = bignum.alloc(self.words);
+            let t1 = bignum.alloc(self.words);
+            let t2 = bignum.alloc(self.words);
+            let t3 = bignum.alloc(self.words);
+            let t4 = bignum.alloc(self.words);
+            let x3 = bignum.alloc(self.words);
+            let y3 = bignum.alloc(self.words);
+            let z3 = bignum.alloc(self.words);
+
+            p.mul(bignum, t0, x1, x2);
+            p.mul(bignum, t1, y1, y2);
+            p.mul(bignum, t2, z1, z2);
+            p.add(bignum, t3, x1, y1);
+            p.add(bignum, t4, x2, y2);
+            p.mul(bignum, t3, t3, t4);
+            p.add(bignum, t4, t0, t1);
+            p.sub(bignum, t3, t3, t4);
+            p.add(bignum, t4, y1, z1);
+            p.add(bignum, x3, y2, z2);
+            p.mul(bignum, t4, t4, x3);
+            p.add(bignum, x3, t1, t2);
+            p.sub(bignum, t4, t4, x3);
+            p.add(bignum, x3, x1, z1);
+            p.add(bignum, y3, x2, z2);
+            p.mul(bignum, x3, x3, y3);
+            p.add(bignum, y3, t0, t2);
+            p.sub(bignum, y3, x3, y3);
+            p.mul(bignum, z3, self.b, t2);
+            p.sub(bignum, x3, y3, z3);
+            p.add(bignum, z3, x3, x3);
+            p.add(bignum, x3, x3, z3);
+            p.sub(bignum, z3, t1, x3);
+            p.add(bignum, x3, t1, x3);
+            p.mul(bignum, y3, self.b, y3);
+            p.add(bignum, t1, t2, t2);
+            p.add(bignum, t2, t1, t2);
+            p.sub(bignum, y3, y3, t2);
+            p.sub(bignum, y3, y3, t0);
+            p.add(bignum, t1, y3, y3);
+            p.add(bignum, y3, t1, y3);
+            p.add(bignum, t1, t0, t0);
+            p.add(bignum, t0, t1, t0);
+            p.sub(bignum, t0, t0, t2);
+            p.mul(bignum, t1, t4, y3);
+            p.mul(bignum, t2, t0, y3);
+            p.mul(bignum, y3, x3, z3);
+            p.add(bignum, y3, y3, t2);
+            p.mul(bignum, x3, t3, x3);
+            p.sub(bignum, x3, x3, t1);
+            p.mul(bignum, z3, t4, z3);
+            p.mul(bignum, t1, t3, t0);
+            p.add(bignum, z3, z3, t1);
+
+            bignum.copy(r.x, x3);
+            bignum.copy(r.y, y3);
+            bignum.copy(r.z, z3);
+        })
+    }
+
+    /// `r = scalar * a` for a big-endian scalar of `Size` bytes, in time independent of
+    /// the scalar. `r` may be `a`.
+    pub fn mul<E: Engine>(&self, bignum: &mut Bignum<E>, r: Point, scalar: &[u8], a: Point) {
+        assert!(scalar.len() == C::P.len());
+        bignum.scope(|bignum| {
+            // r0 = k * a, r1 = (k + 1) * a for the bits k processed so far
+            let r0 = self.alloc(bignum);
+            let r1 = self.alloc(bignum);
+            self.copy(bignum, r1, a);
+            for byte in scalar {
+                for bit in (0..8).rev() {
+                    let choice = byte >> bit & 1 == 1;
+                    self.swap(bignum, r0, r1, choice);
+                    self.add(bignum, r1, r0, r1);
+                    self.add(bignum, r0, r0, r0);
+                    self.swap(bignum, r0, r1, choice);
+                }
+            }
+            self.copy(bignum, r, r0);
+        })
+    }
+
+    /// `r = u1 * G + u2 * q` for big-endian scalars of `Size` bytes; the running time
+    /// depends on the scalars.
+    pub fn mul_add_public<E: Engine>(
+        &self,
+        bignum: &mut Bignum<E>,
+        r: Point,
+        u1: &[u8],
+        u2: &[u8],
+        q: Point,
+    ) {
+        assert!(u1.len() == C::P.len() && u2.len() == C::P.len());
+        bignum.scope(|bignum| {
+            let g = self.alloc(bignum);
+            self.generator(bignum, g);
+            let sum = self.alloc(bignum);
+            self.add(bignum, sum, g, q);
+            let accumulator = self.alloc(bignum);
+
+            for (byte1, byte2) in u1.iter().zip(u2.iter()) {
+                for bit in (0..8).rev() {
+                    self.add(bignum, accumulator, accumulator, accumulator);
+                    match (byte1 >> bit & 1, byte2 >> bit & 1) {
+                        (1, 1) => self.add(bignum, accumulator, accumulator, sum),
+                        (1, 0) => self.add(bignum, accumulator, accumulator, g),
+                        (0, 1) => self.add(bignum, accumulator, accumulator, q),
+                        _ => {}
+                    }
+                }
+            }
+            self.copy(bignum, r, accumulator);
+        })
+    }
+
+    pub fn copy<E: Engine>(&self, bignum: &mut Bignum<E>, r: Point, a: Point) {
+        bignum.copy(r.x, a.x);
+        bignum.copy(r.y, a.y);
+        bignum.copy(r.z, a.z);
+    }
+
+    fn swap<E: Engine>(&self, bignum: &mut Bignum<E>, a: Point, b: Point, choice: bool) {
+        bignum.conditional_swap(a.x, b.x, choice);
+        bignum.conditional_swap(a.y, b.y, choice);
+        bignum.conditional_swap(a.z, b.z, choice);
+    }
+}
+
+/// `value - 2`, for big-endian values of at least 2, e.g. the exponent of inverses modulo
+/// a prime.
+pub fn minus_two(value: &[u8], out: &mut [u8]) {
+    out.copy_from_slice(value);
+    let mut borrow = 2u16;
+    for byte in out.iter_mut().rev() {
+        let difference = u16::from(*byte).wrapping_sub(borrow);
+        *byte = difference as u8;
+        borrow = (difference >> 8) & 1;
+    }
+}
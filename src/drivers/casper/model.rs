@@ -0,0 +1,138 @@
+//! Software model of the CASPER operations.
+//!
+//! Runs anywhere, so code built on `Bignum` can be tested on the host. With `a` the
+//! walked operand (`ab`), `b` the single word at `cd`, `r` the result and `n` the length:
+//!
+//! - `Mul6464NoSum`: `r[..=n] = a * b`
+//! - `Mul6464Sum`: `r[..=n] = r[..n] + a * b`, the last word of `r` is overwritten
+//! - `Mul6464FullSum`: `r[..=n] += a * b`, with carry
+//! - `Mul6464Reduce`: `r[..=n] = (r[..=n] + a * b) >> 64`, with the carry in the last
+//!   word; the first word of the sum is not written (it is zero in Montgomery reduction)
+//! - `Add64`: `r += a`, with carry
+//! - `Sub64`: `r -= a`, with borrow
+//! - `Double64`: `r += r`, with carry
+//! - `Xor64`: `r ^= a`
+//! - `Copy`: `r = a`
+//!
+//! The shifts, `Remask` and the comparisons are not modelled; `Bignum` does not use them.
+//!
+//! These semantics follow how NXP's SDK (`fsl_casper.c`) uses the operations, they are
+//! not taken from the user manual. Host tests only check `Bignum` against the model;
+//! `examples/casper.rs` compares the model with the peripheral.
+
+use crate::peripherals::casper::Operations;
+
+use super::{Engine, Operation, MAX_WORDS, RAM_WORDS};
+
+/// The RAM window and the operations on it, in software
+#[derive(Clone)]
+pub struct Model {
+    ram: [u64; RAM_WORDS],
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Model {
+    pub fn new() -> Self {
+        Self {
+            ram: [0; RAM_WORDS],
+        }
+    }
+
+    // r[..=n] + a * b with `full_sum`, r[..n] + a * b with `sum`, else a * b;
+    // returns the words and the carry out of the last one
+    fn multiply(
+        &self,
+        operation: &Operation,
+        full_sum: bool,
+        sum: bool,
+    ) -> ([u64; MAX_WORDS + 1], bool) {
+        let n = operation.len;
+        let b = u128::from(self.ram[operation.cd]);
+        let mut words = [0u64; 257];
+        let mut carry = 0u128;
+        for (j, word) in words.iter_mut().enumerate().take(n) {
+            let r = if sum { self.ram[operation.res + j] } else { 0 };
+            let product = u128::from(self.ram[operation.ab + j]) * b + u128::from(r) + carry;
+            *word = product as u64;
+            carry = product >> 64;
+        }
+        let (last, overflow) = if full_sum {
+            self.ram[operation.res + n].overflowing_add(carry as u64)
+        } else {
+            (carry as u64, false)
+        };
+        words[n] = last;
+        (words, overflow)
+    }
+}
+
+impl Engine for Model {
+    fn read(&self, offset: usize, words: &mut [u64]) {
+        words.copy_from_slice(&self.ram[offset..][..words.len()]);
+    }
+
+    fn write(&mut self, offset: usize, words: &[u64]) {
+        self.ram[offset..][..words.len()].copy_from_slice(words);
+    }
+
+    fn run(&mut self, operation: &Operation) -> bool {
+        operation.check();
+        let (n, ab, res) = (operation.len, operation.ab, operation.res);
+        match operation.opcode {
+            Operations::Mul6464NoSum | Operations::Mul6464Sum | Operations::Mul6464FullSum => {
+                let full_sum = operation.opcode == Operations::Mul6464FullSum;
+                let sum = operation.opcode != Operations::Mul6464NoSum;
+                let (words, carry) = self.multiply(operation, full_sum, sum);
+                self.ram[res..=res + n].copy_from_slice(&words[..=n]);
+                carry
+            }
+            Operations::Mul6464Reduce => {
+                let (words, carry) = self.multiply(operation, true, true);
+                self.ram[res..res + n].copy_from_slice(&words[1..=n]);
+                self.ram[res + n] = carry as u64;
+                false
+            }
+            Operations::Add64 | Operations::Sub64 | Operations::Double64 => {
+                let mut carry = false;
+                for j in 0..n {
+                    let r = self.ram[res + j];
+                    let a = match operation.opcode {
+                        Operations::Double64 => r,
+                        _ => self.ram[ab + j],
+                    };
+                    let (word, c1, c2) = match operation.opcode {
+                        Operations::Sub64 => {
+                            let (word, c1) = r.overflowing_sub(a);
+                            let (word, c2) = word.overflowing_sub(carry as u64);
+                            (word, c1, c2)
+                        }
+                        _ => {
+                            let (word, c1) = r.overflowing_add(a);
+                            let (word, c2) = word.overflowing_add(carry as u64);
+                            (word, c1, c2)
+                        }
+                    };
+                    self.ram[res + j] = word;
+                    carry = c1 | c2;
+                }
+                carry
+            }
+            Operations::Xor64 => {
+                for j in 0..n {
+                    self.ram[res + j] ^= self.ram[ab + j];
+                }
+                false
+            }
+            Operations::Copy => {
+                self.ram.copy_within(ab..ab + n, res);
+                false
+            }
+            opcode => panic!("{:?} is not modelled", opcode),
+        }
+    }
+}
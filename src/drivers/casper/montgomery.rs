@@ -0,0 +1,452 @@
+//! Modular arithmetic in Montgomery form, on `Bignum`.
+//!
+//! For an odd modulus `n` of `k` words and `R = 2^(64k)`, the Montgomery product of `a`
+//! and `b` is `a * b / R mod n`. Numbers are converted to Montgomery form (`a * R mod n`)
+//! once, after which products need no division. The reduction runs on CASPER as one
+//! multiply-accumulate per word of `n`; only the reduction factor of each row is
+//! computed by the CPU.
+
+use core::cmp::Ordering;
+
+use super::{Bignum, Engine, Slot};
+
+/// An odd modulus, with the constants for Montgomery products
+///
+/// Its slots are allocated by `new`, they must outlive the `Modulus`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Modulus {
+    // one word longer than the modulus, with the top word zero
+    n: Slot,
+    // -1/n mod 2^64
+    n0: u64,
+    // R^2 mod n
+    r2: Slot,
+    words: usize,
+}
+
+impl Modulus {
+    /// Panics unless `n` is odd and larger than one.
+    pub fn new<E: Engine>(bignum: &mut Bignum<E>, n: Slot) -> Self {
+        let words = n.words();
+        let extended = bignum.alloc(words + 1);
+        bignum.copy(extended.sub(0, words), n);
+
+        let mut low = [0u64];
+        bignum.engine.read(n.offset, &mut low);
+        assert!(low[0] & 1 == 1);
+        // Newton iteration, each step doubles the correct low bits
+        let mut inverse = 1u64;
+        for _ in 0..6 {
+            inverse = inverse.wrapping_mul(2u64.wrapping_sub(low[0].wrapping_mul(inverse)));
+        }
+
+        let r2 = bignum.alloc(words);
+        let modulus = Modulus {
+            n: extended,
+            n0: inverse.wrapping_neg(),
+            r2,
+            words,
+        };
+        modulus.compute_r2(bignum);
+        modulus
+    }
+
+    /// Length of the modulus and the numbers modulo it, in words
+    pub fn words(&self) -> usize {
+        self.words
+    }
+
+    /// The modulus
+    pub fn n(&self) -> Slot {
+        self.n.sub(0, self.words)
+    }
+
+    /// `r = a * b / R mod n`, for `a`, `b` less than `n`. `r` may be `a` or `b`.
+    pub fn mul<E: Engine>(&self, bignum: &mut Bignum<E>, r: Slot, a: Slot, b: Slot) {
+        let k = self.words;
+        assert!(r.words() == k && a.words() == k && b.words() == k);
+        bignum.scope(|bignum| {
+            let t = bignum.alloc(2 * k + 1);
+            let carries = bignum.alloc(k);
+            let factor = bignum.alloc(1);
+            bignum.mul(t.sub(0, 2 * k), a, b);
+
+            // add multiples of n, zeroing t word by word; the carry out of each row
+            // belongs `k + 1` words further, collected in `carries`
+            for i in 0..k {
+                let mut word = [0u64];
+                bignum.engine.read(t.offset + i, &mut word);
+                bignum.load(factor, &[word[0].wrapping_mul(self.n0)]);
+                let carry = bignum.mul_add_word(t.sub(i, k + 1), self.n(), factor);
+                bignum.load(carries.sub(i, 1), &[carry as u64]);
+            }
+            bignum.add(t.sub(k + 1, k), carries);
+
+            // t / R < 2n
+            self.reduce_once(bignum, r, t.sub(k, k + 1));
+        })
+    }
+
+    /// `r = a * R mod n`, for `a` less than `n`.
+    pub fn to_montgomery<E: Engine>(&self, bignum: &mut Bignum<E>, r: Slot, a: Slot) {
+        self.mul(bignum, r, a, self.r2);
+    }
+
+    /// `r = a / R mod n`
+    pub fn from_montgomery<E: Engine>(&self, bignum: &mut Bignum<E>, r: Slot, a: Slot) {
+        bignum.scope(|bignum| {
+            let one = bignum.alloc(self.words);
+            bignum.load(one, &[1]);
+            self.mul(bignum, r, a, one);
+        })
+    }
+
+    /// `r = base^exponent mod n`, for `base` less than `n` and a big-endian exponent.
+    ///
+    /// Square and multiply, so the running time depends on the exponent (but not on
+    /// `base`): use it with public exponents only.
+    pub fn pow<E: Engine>(&self, bignum: &mut Bignum<E>, r: Slot, base: Slot, exponent: &[u8]) {
+        bignum.scope(|bignum| {
+            let base_m = bignum.alloc(self.words);
+            self.to_montgomery(bignum, base_m, base);
+            self.pow_montgomery(bignum, base_m, base_m, exponent);
+            self.from_montgomery(bignum, r, base_m);
+        })
+    }
+
+    /// `pow`, with `base` and `r` in Montgomery form.
+    pub fn pow_montgomery<E: Engine>(
+        &self,
+        bignum: &mut Bignum<E>,
+        r: Slot,
+        base: Slot,
+        exponent: &[u8],
+    ) {
+        bignum.scope(|bignum| {
+            let accumulator = bignum.alloc(self.words);
+            self.one(bignum, accumulator);
+            for byte in exponent {
+                for bit in (0..8).rev() {
+                    self.mul(bignum, accumulator, accumulator, accumulator);
+                    if byte >> bit & 1 == 1 {
+                        self.mul(bignum, accumulator, accumulator, base);
+                    }
+                }
+            }
+            bignum.copy(r, accumulator);
+        })
+    }
+
+    /// `r = 1` in Montgomery form, i.e. `R mod n`.
+    pub fn one<E: Engine>(&self, bignum: &mut Bignum<E>, r: Slot) {
+        bignum.load(r, &[1]);
+        self.to_montgomery(bignum, r, r);
+    }
+
+    /// `r = a + b mod n`, for `a`, `b` less than `n`. `r` may be `a` or `b`.
+    pub fn add<E: Engine>(&self, bignum: &mut Bignum<E>, r: Slot, a: Slot, b: Slot) {
+        bignum.scope(|bignum| {
+            let sum = bignum.alloc(self.words + 1);
+            bignum.copy(sum.sub(0, self.words), a);
+            let carry = bignum.add(sum.sub(0, self.words), b);
+            bignum.load(sum.sub(self.words, 1), &[carry as u64]);
+            self.reduce_once(bignum, r, sum);
+        })
+    }
+
+    /// `r = a - b mod n`, for `a`, `b` less than `n`. `r` may be `a` or `b`.
+    pub fn sub<E: Engine>(&self, bignum: &mut Bignum<E>, r: Slot, a: Slot, b: Slot) {
+        bignum.scope(|bignum| {
+            let difference = bignum.alloc(self.words);
+            let wrapped = bignum.alloc(self.words);
+            bignum.copy(difference, a);
+            let borrow = bignum.sub(difference, b);
+            bignum.copy(wrapped, difference);
+            bignum.add(wrapped, self.n());
+            bignum.conditional_copy(difference, wrapped, borrow);
+            bignum.copy(r, difference);
+        })
+    }
+
+    /// `r = a mod n`, for `a` one word longer than `n` and less than `2n`.
+    pub fn reduce<E: Engine>(&self, bignum: &mut Bignum<E>, r: Slot, a: Slot) {
+        assert!(a.words() == self.words + 1 && r.words() == self.words);
+        self.reduce_once(bignum, r, a);
+    }
+
+    /// `r = a mod n`, for any `a` twice as long as `n`, e.g. a hash reduced to a scalar.
+    pub fn reduce_wide<E: Engine>(&self, bignum: &mut Bignum<E>, r: Slot, a: Slot) {
+        let k = self.words;
+        assert!(a.words() == 2 * k && r.words() == k);
+        // a = high * R + low; products with r2 (less than n) reduce any k-word factor,
+        // so (high * R^2 + low * R) / R is computed without a division
+        bignum.scope(|bignum| {
+            let high = bignum.alloc(k);
+            let low = bignum.alloc(k);
+            self.mul(bignum, high, a.sub(k, k), self.r2);
+            self.mul(bignum, high, high, self.r2);
+            self.mul(bignum, low, a.sub(0, k), self.r2);
+            self.add(bignum, r, high, low);
+            self.from_montgomery(bignum, r, r);
+        })
+    }
+
+    /// Whether `a` is less than `n`.
+    pub fn contains<E: Engine>(&self, bignum: &mut Bignum<E>, a: Slot) -> bool {
+        bignum.compare(a, self.n()) == Ordering::Less
+    }
+
+    // r = a mod n, for `a` (one word longer) less than 2n; the borrow may depend on
+    // secrets, so it selects with a mask rather than a branch
+    fn reduce_once<E: Engine>(&self, bignum: &mut Bignum<E>, r: Slot, a: Slot) {
+        bignum.scope(|bignum| {
+            let difference = bignum.alloc(self.words + 1);
+            bignum.copy(difference, a);
+            let borrow = bignum.sub(difference, self.n);
+            bignum.conditional_copy(difference, a, borrow);
+            bignum.copy(r, difference.sub(0, self.words));
+        })
+    }
+
+    // r2 = 2^(2 * 64k) mod n: R mod n by doubling from the top bit of n, then doublings
+    // and Montgomery squarings, each doubling the exponent of 2 above R
+    fn compute_r2<E: Engine>(&self, bignum: &mut Bignum<E>) {
+        let k = self.words;
+        let mut words = [0u64; 1];
+        let mut bits = 0;
+        for i in (0..k).rev() {
+            bignum.engine.read(self.n.offset + i, &mut words);
+            if words[0] != 0 {
+                bits = 64 * i + 64 - words[0].leading_zeros() as usize;
+                break;
+            }
+        }
+
+        assert!(bits > 1);
+        let x = self.r2;
+        let top = bits - 1;
+        bignum.zero(x);
+        bignum.load(x.sub(top / 64, 1), &[1 << (top % 64)]);
+
+        let squarings = (64 * k).trailing_zeros();
+        let doublings = (64 * k - top) + ((64 * k) >> squarings);
+        for _ in 0..doublings {
+            self.double(bignum, x);
+        }
+        for _ in 0..squarings {
+            self.mul(bignum, x, x, x);
+        }
+    }
+
+    // x = 2x mod n, for `x` less than `n`
+    fn double<E: Engine>(&self, bignum: &mut Bignum<E>, x: Slot) {
+        bignum.scope(|bignum| {
+            let doubled = bignum.alloc(self.words + 1);
+            bignum.copy(doubled.sub(0, self.words), x);
+            let carry = bignum.add(doubled.sub(0, self.words), x);
+            bignum.load(doubled.sub(self.words, 1), &[carry as u64]);
+            self.reduce_once(bignum, x, doubled);
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::casper::Model;
+    use crate::testing::hex;
+
+    // expected values from Python's integers
+    const N: u128 = 0x8dbb5b2a6e20af8e1001a6625a1298a1;
+    const A: u128 = 0x41e6cb0657eda214921f54d17423c60d;
+    const B: u128 = 0x00290f121a3a8beef515f5fb060e9290;
+
+    fn load(bignum: &mut Bignum<Model>, value: u128) -> Slot {
+        let slot = bignum.alloc(2);
+        bignum.load(slot, &[value as u64, (value >> 64) as u64]);
+        slot
+    }
+
+    fn store(bignum: &Bignum<Model>, slot: Slot) -> u128 {
+        let mut words = [0u64; 2];
+        bignum.store(slot, &mut words);
+        u128::from(words[0]) | u128::from(words[1]) << 64
+    }
+
+    fn new_modulus(bignum: &mut Bignum<Model>, n: u128) -> Modulus {
+        let n = load(bignum, n);
+        Modulus::new(bignum, n)
+    }
+
+    #[test]
+    fn arithmetic() {
+        let mut bignum = Bignum::new(Model::new());
+        let modulus = new_modulus(&mut bignum, N);
+        let (a, b, r) = (load(&mut bignum, A), load(&mut bignum, B), bignum.alloc(2));
+
+        modulus.mul(&mut bignum, r, a, b);
+        assert_eq!(store(&bignum, r), 0x0f8203addc861e93fbbd56c88ba1ad2b);
+        modulus.add(&mut bignum, r, a, b);
+        assert_eq!(store(&bignum, r), 0x420fda1872282e0387354acc7a32589d);
+        // no reduction, then with one
+        modulus.add(&mut bignum, r, a, r);
+        assert_eq!(store(&bignum, r), A + 0x420fda1872282e0387354acc7a32589d);
+        modulus.add(&mut bignum, r, r, a);
+        assert_eq!(
+            store(&bignum, r),
+            2 * A + 0x420fda1872282e0387354acc7a32589d - N
+        );
+        // with a carry out of the top word
+        let top = load(&mut bignum, N - 1);
+        modulus.add(&mut bignum, r, top, top);
+        assert_eq!(store(&bignum, r), N - 2);
+        modulus.sub(&mut bignum, r, a, b);
+        assert_eq!(store(&bignum, r), 0x41bdbbf43db316259d095ed66e15337d);
+        modulus.sub(&mut bignum, r, b, a);
+        assert_eq!(store(&bignum, r), 0x4bfd9f36306d996872f8478bebfd6524);
+        modulus.sub(&mut bignum, r, a, a);
+        assert_eq!(store(&bignum, r), 0);
+
+        modulus.to_montgomery(&mut bignum, r, a);
+        modulus.from_montgomery(&mut bignum, r, r);
+        assert_eq!(store(&bignum, r), A);
+    }
+
+    #[test]
+    fn pow_u128() {
+        let mut bignum = Bignum::new(Model::new());
+        let modulus = new_modulus(&mut bignum, N);
+        let (a, r) = (load(&mut bignum, A), bignum.alloc(2));
+
+        modulus.pow(&mut bignum, r, a, &0xf15179064388d4ebu64.to_be_bytes());
+        assert_eq!(store(&bignum, r), 0x70ae115af1e226c769faf532acd41ce9);
+        modulus.pow(&mut bignum, r, a, &[0]);
+        assert_eq!(store(&bignum, r), 1);
+        modulus.pow(&mut bignum, r, a, &[1]);
+        assert_eq!(store(&bignum, r), A);
+
+        // Fermat's little theorem, for the prime 2^127 - 1
+        let mut bignum = Bignum::new(Model::new());
+        let modulus = new_modulus(&mut bignum, (1 << 127) - 1);
+        let (a, r) = (load(&mut bignum, A >> 1), bignum.alloc(2));
+        modulus.pow(&mut bignum, r, a, &((1u128 << 127) - 2).to_be_bytes());
+        assert_eq!(store(&bignum, r), 1);
+    }
+
+    #[test]
+    fn reduce_wide() {
+        let mut bignum = Bignum::new(Model::new());
+        let modulus = new_modulus(&mut bignum, N);
+        let (wide, r) = (bignum.alloc(4), bignum.alloc(2));
+        let value: [u8; 32] =
+            hex("d0d27379eadaf9430256b88b8bf3baebb94eba887b470af1cad08becf72fcc47");
+        bignum.load_be_bytes(wide, &value);
+        modulus.reduce_wide(&mut bignum, r, wide);
+        assert_eq!(store(&bignum, r), 0x63a073682c763ce1800d981c0d139772);
+    }
+
+    // base^65537 mod n, with moduli of RSA keys
+    fn pow_65537<const K: usize>(n: &str, base: &str, expected: &str) {
+        let mut bignum = Bignum::new(Model::new());
+        let (n_slot, base_slot) = (bignum.alloc(K / 8), bignum.alloc(K / 8));
+        bignum.load_be_bytes(n_slot, &hex::<K>(n));
+        bignum.load_be_bytes(base_slot, &hex::<K>(base));
+        let modulus = Modulus::new(&mut bignum, n_slot);
+        modulus.pow(&mut bignum, base_slot, base_slot, &[1, 0, 1]);
+
+        let mut result = [0u8; K];
+        bignum.store_be_bytes(base_slot, &mut result);
+        assert_eq!(result, hex::<K>(expected));
+    }
+
+    #[test]
+    fn pow_rsa2048() {
+        pow_65537::<256>(N2048, BASE2048, POW2048);
+    }
+
+    #[test]
+    fn pow_rsa4096() {
+        pow_65537::<512>(N4096, BASE4096, POW4096);
+    }
+
+    // RSA-2048
+    const N2048: &str = "
+        9184c06704fa5ddb613861edf50eb02c164ac1181652de6f8a0b6c79ed692059
+        3c285e3e53a85f95a20c6d0d2fd6100a707108c2d3c9c58633ea1f0c7f3efc10
+        a31f57554b417d67d7c0cabec5ca35de2c9a300491171e0fc7c415c870f34d72
+        a5e00df38eaf94bcfe50bbf3a328ae76f402e7415b73821d907b4f4e6be2b51b
+        9b5b403cf70f99e2b6daeb17d5c6e373d072c173b755e49eb53a0ba06623960e
+        f7093da52360e86833bbb4e311854463e498835688683112d8442b6e1b8d0af7
+        2b60fd8b57f2033ce438747cd368f2322b41d498d9ef6efc4046246e3e387861
+        40ea52c51d756c61d4bcf99dd8c60bc4e421838cf073a52e33512a775001de5b";
+    const BASE2048: &str = "
+        7a318c739952a1bc970a0004b585084c85574930e11316e51be9b2e5c9370d66
+        b8f0a8fe1186fd58623a62af7b911d898ad117f043651c86c594fbac0a77844f
+        497c6a88adfa4180d2f860a1307501d78f692f11f68ad3360616b53ceb30f13a
+        b06dc7eeb1fdd2deaf9eca1b8b7fe81ba3560e9446db8184fa2c8f8e75e531bd
+        f0900287759fd591abc27d201922b491967504fa05c8b93c766a675a2abb1d29
+        54c39a26cbd722f15a290f703ec02a05c144896b1d3786f2809ced737d7de53f
+        b46aeb0bc8a3814d49a20e04ca0aa3bac817fe6710f1ed2ff760a712c36f1de4
+        85a9fdc8905db44fcf46caab6f40d3900493a30dd2dbb830742a08f7c96f7859";
+    const POW2048: &str = "
+        8c132211f0b05f9db88a78f086969273a211be59df08ec559b5c24862dff461d
+        c5af1eab1cf7211dcccea700fe2d6866198c29cd9e39a579cf19d59c6248565e
+        2b564f05c3d6ca5f8d4e68008f6299fc60807c468be90b4b2ff3f5a6826defe5
+        9df8990d4827d8489490c5b3628538a2fefddd63eeed8d5f4ce1c8cf7a9804fb
+        72248692c0536847bee58c12e3254f89f8d1e810227215e15a9fa74be3da11f2
+        ef73afb2aa5fdb402eb26b60de84d5a8ba140537ac96846ed37d904b9413778d
+        07b98ac2f20b4a8ec65ece540e0690e5cff9afa2700defd938b7e6b8f93a118e
+        b4611adaaa0ef2ef2e721db5c9cf167f5e6f0d8251b8fa545832e1a8b3e9164b";
+    // RSA-4096
+    const N4096: &str = "
+        a0ca6b4dbfb7e24d103ee1bd2bc471d921a69fd36b4c265789c6d40e3daece8d
+        86d2b0f76e7c5cc1e4b1ccefcd34bca2b1c542e7b9c9b69faa63b87d35970890
+        40b0749503e15b17e568dc92e8fe971f5936fa1ef7ffddd7d816fc9dc93542bf
+        a59af890c64cc1ae1a293eefc0620c1a493852b81b101c9d577fcfa4f67c7996
+        828415fbb54a6afca3aeecaebe5636f22bdbf8b5f06e24e9d3cc9c7b86e067bb
+        78e501185fa4696229c31f5745f0ad34bba76a65801409468a1d6fc8ad83d682
+        d1d82b63a87e20159d35e1cb88c679212bdc5e2fdb2a6f15edc48d84f81a479e
+        3bc744a5bdcf76620fa67ec746c67978fd903af06bb280b1257ab905f65fb9dd
+        a810e5a935b41d327d7c691def08567e441c058f6898c435d8b8127852eaef29
+        8d1e077a287cad74693afa87f349ba53291bc772a56da6196ba7e4a6f1998a1d
+        9d4ef05e202ed2a7e793196cf3764df25d67a693b731dcf12661f77fcdef1b5b
+        bc2ff3aeacf4137b0324ec4bc85a8e8a7fc99d6bd9fa8b3a278883799967894e
+        3677c8e1fd9d06f90253fa196a3b684031186a07cb511402af32ad79c26b9dfa
+        a4902734c916370c5e50c3972fd6b4e07de02474d0a786fb51a5e93f19418a5b
+        8d22af35c4574c7b38c84dceaa6021028dadd5af1197298e8aea41c8197aace1
+        33b9875796ff5bfa4880e051814df9c274282d1b40dd23532cd7f6df2a313c19";
+    const BASE4096: &str = "
+        9fb6ab872e744807edda29e99ceca18f2e7f940e0af4b17ad0827c1b608402d0
+        b3afa089aeba4f782df3bad68eeda3269df27e7d763a3bf5eae0c1e49fbf1346
+        b09a07c09847836db260705396dc8a0298385a9de9ff8493f7e611dadb17ebef
+        611b28f4df9714f5ac4e54729be02debeb21de7405e7bf498e06c5ff50201792
+        435a9af88363615591fd13c080dbe9833c08b5c0f495d7786d0e21537f5e0df2
+        562f1b2a6c4f79992b7498f8eef324861fb485204d9653741a3deb6c4b411343
+        41fa137fdc21a54bd08c0fb39d32fc687018c580852d5eab7518573bddbd7cce
+        6c38e0db070bcaa10028542a7acd9cb8948da8ad27c035b80e057a6dacaba24b
+        dd63b3c851448a3770c1759cc6947689ef5f150471fb95aa4cacf20ef4ff06a0
+        d1591198d22b5923a94c7ca6d9de1b59770a5599358e9cf64a2b77e335ea97b8
+        411c20554495fc3fce9e430fa249bcf76450c492ecfb7e240e753a22dd7a39c1
+        95349570598b42d10cdcdb0a5c4ba5a56d16a36fbe7526b89f574580dad64c03
+        17daac42eee124a8d0f265c51bda40a5d9e13f465c30fcb1d613fc2f89fb5b5e
+        552f79fcf40a910567b34afc4e144e4ccf22b504832c1bcb1a49472af06fa7e6
+        fa244b3a1768f93ea1ecaf71bb7c233334725978e5f72a7ee5680a12aecc6777
+        c51df46a697f1c244fcb603294160e2c5ca994bfca70fb81be8c9510898319a4";
+    const POW4096: &str = "
+        5571830d9bd5e5b3113a1739ae2762407676dc885cf27e28a97d0f9b974f8b7e
+        e0073170e52c56f010981a2465f339b98d956c4ae622ca3874f11e8e4725e9b3
+        b6aaf9dc00f129ac0870729210276f36dd468ad6fbe30557eb76e3ac1083edc9
+        406a5121e7a693b0a68bfdaaaacede55929c304a359b7b2bbe299b1531327532
+        b8edd823d3df135d9e93a8ef54c6a57fc4685d2e05b0ed4da0c6673be387c36b
+        f5768e644a3f2cca129ce60ec158cb405d7b86af9aed4fde2a8706dc96620fde
+        6c3cccce4ddeb183ca0779e2305e75171ffc307b90bb1946b061864cc2cab198
+        32dc6c98c160ad5c6e022bbe197d7f61ecc4f04286accc2615d329416e636834
+        55c7a95a7e9784fa532a4a54d7bb4d5fdff220a528d8934f994d6ef55eb2c451
+        255cf0f70f88a2ebf6f5bdc0897f7eaadfedd79df1930c6eccd6f30e53320322
+        6d5c48156afbb3a549b67652ea202d9f761c83d2ef50b659b9d7b9d08c2b604b
+        50a91e1dcdc4097a2a4ac45e2c69e5a12379316753074fd0b84f44912326c1dc
+        c998b359e87a9c4868d38f2b9431662e86c0fb0927745f0678e0070bd06986d7
+        37944f3af234c6a80d890a57a809d561998adc8aa8b8744d1263ac5eab589413
+        4c21393e577be33e0c80e6292a3dc4f59d73de9e9919d960a25b83b470a95b13
+        7d275596b74a8ab6fdc347b6dec218101fe8962c5e3583d8ff4819d1313e7041";
+}
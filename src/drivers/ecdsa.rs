@@ -0,0 +1,642 @@
+//! ECDSA (FIPS 186-4) and ECDH on P-256 and P-384, on CASPER.
+//!
+//! Keys borrow a `RefCell<Bignum>`, so they can implement the `signature` traits, which
+//! take `&self`: `PrehashVerifier` and `RandomizedPrehashSigner`. Hashing is up to the
+//! caller, e.g. `Hashcrypt::sha256` for P-256. Points are exchanged as affine
+//! coordinates or SEC1 uncompressed encodings. `P256` and `P384` are `elliptic-curve`
+//! curves, so keys also convert from and to its `SecretKey` and `sec1::EncodedPoint`,
+//! which the `p256` and `p384` crates use too.
+//!
+//! Operations with the private key (signing, `verifying_key`, `diffie_hellman`) run the
+//! same CASPER operations whatever the key and the nonce, and wipe the free part of the
+//! RAM window afterwards.
+//!
+//! ```ignore
+//! let bignum = RefCell::new(Bignum::new(&mut casper));
+//! let key = SigningKey::<P256, _>::from_bytes(&bignum, &secret)?;
+//! let signature = key.sign_prehash_with_rng(&mut rng, &digest)?;
+//! key.verifying_key().verify_prehash(&digest, &signature)?;
+//! ```
+
+use core::{cell::RefCell, ptr};
+
+use elliptic_curve::{
+    sec1::{Coordinates, EncodedPoint, ToEncodedPoint},
+    SecretKey,
+};
+use generic_array::GenericArray;
+
+use crate::{
+    drivers::casper::{
+        ecc::{minus_two, Group},
+        Bignum, Engine, Slot,
+    },
+    traits::signature::{
+        self,
+        hazmat::{PrehashVerifier, RandomizedPrehashSigner},
+        rand_core::CryptoRngCore,
+    },
+};
+
+pub use crate::drivers::casper::ecc::{Curve, FieldBytes, P256, P384};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// Scalar out of range, or point not on the curve
+    InvalidKey,
+    /// Scalars out of range, or not matching
+    InvalidSignature,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl From<Error> for signature::Error {
+    fn from(_: Error) -> Self {
+        signature::Error::new()
+    }
+}
+
+/// An ECDSA signature `(r, s)`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature<C: Curve> {
+    r: FieldBytes<C>,
+    s: FieldBytes<C>,
+}
+
+impl<C: Curve> Signature<C> {
+    pub fn from_scalars(r: FieldBytes<C>, s: FieldBytes<C>) -> Self {
+        Self { r, s }
+    }
+
+    /// From the concatenation of `r` and `s`, big-endian.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self> {
+        let size = C::P.len();
+        if bytes.len() != 2 * size {
+            return Err(Error::InvalidSignature);
+        }
+        Ok(Self {
+            r: GenericArray::clone_from_slice(&bytes[..size]),
+            s: GenericArray::clone_from_slice(&bytes[size..]),
+        })
+    }
+
+    pub fn r(&self) -> &FieldBytes<C> {
+        &self.r
+    }
+
+    pub fn s(&self) -> &FieldBytes<C> {
+        &self.s
+    }
+
+    /// Writes the concatenation of `r` and `s`.
+    pub fn write_bytes(&self, bytes: &mut [u8]) {
+        let size = C::P.len();
+        bytes[..size].copy_from_slice(&self.r);
+        bytes[size..2 * size].copy_from_slice(&self.s);
+    }
+}
+
+/// A private key, see the module documentation
+pub struct SigningKey<'b, C: Curve, E: Engine> {
+    secret: FieldBytes<C>,
+    bignum: &'b RefCell<Bignum<E>>,
+}
+
+impl<'b, C: Curve, E: Engine> SigningKey<'b, C, E> {
+    /// From a big-endian scalar in `[1, n - 1]`.
+    pub fn from_bytes(bignum: &'b RefCell<Bignum<E>>, secret: &[u8]) -> Result<Self> {
+        if secret.len() != C::P.len() || !is_scalar(secret, C::N) {
+            return Err(Error::InvalidKey);
+        }
+        Ok(Self {
+            secret: GenericArray::clone_from_slice(secret),
+            bignum,
+        })
+    }
+
+    pub fn random(bignum: &'b RefCell<Bignum<E>>, rng: &mut impl CryptoRngCore) -> Self {
+        Self {
+            secret: random_scalar::<C>(rng),
+            bignum,
+        }
+    }
+
+    pub fn from_secret_key(bignum: &'b RefCell<Bignum<E>>, secret: &SecretKey<C>) -> Self {
+        Self {
+            secret: secret.to_bytes(),
+            bignum,
+        }
+    }
+
+    pub fn to_bytes(&self) -> FieldBytes<C> {
+        self.secret.clone()
+    }
+
+    pub fn to_secret_key(&self) -> SecretKey<C> {
+        // checked in `from_bytes` and `random`
+        SecretKey::from_bytes(&self.secret).unwrap()
+    }
+
+    /// The public key, `d * G`.
+    pub fn verifying_key(&self) -> VerifyingKey<'b, C, E> {
+        let mut x = FieldBytes::<C>::default();
+        let mut y = FieldBytes::<C>::default();
+        let mut bignum = self.bignum.borrow_mut();
+        bignum.scope(|bignum| {
+            let group = Group::<C>::new(bignum);
+            let point = group.alloc(bignum);
+            group.generator(bignum, point);
+            group.mul(bignum, point, &self.secret, point);
+            group.to_affine(bignum, point, &mut x, Some(&mut y));
+        });
+        bignum.wipe();
+        VerifyingKey {
+            x,
+            y,
+            bignum: self.bignum,
+        }
+    }
+
+    /// ECDH: the x-coordinate of `d * Q`.
+    pub fn diffie_hellman(&self, public: &VerifyingKey<'_, C, E>) -> Result<FieldBytes<C>> {
+        let mut shared = FieldBytes::<C>::default();
+        let mut bignum = self.bignum.borrow_mut();
+        let finite = bignum.scope(|bignum| {
+            let group = Group::<C>::new(bignum);
+            let point = group.alloc(bignum);
+            group.load(bignum, point, &public.x, &public.y);
+            group.mul(bignum, point, &self.secret, point);
+            group.to_affine(bignum, point, &mut shared, None)
+        });
+        bignum.wipe();
+        if finite {
+            Ok(shared)
+        } else {
+            Err(Error::InvalidKey)
+        }
+    }
+
+    /// Signs with the given nonce `k`, fails if `r` or `s` is zero (retry with another
+    /// nonce). The nonce must be secret, and never reused.
+    pub fn sign_prehash_with_nonce(&self, prehash: &[u8], k: &[u8]) -> Result<Signature<C>> {
+        if k.len() != C::P.len() || !is_scalar(k, C::N) {
+            return Err(Error::InvalidKey);
+        }
+        let mut r = FieldBytes::<C>::default();
+        let mut s = FieldBytes::<C>::default();
+        let mut bignum = self.bignum.borrow_mut();
+        let valid = bignum.scope(|bignum| {
+            let group = Group::<C>::new(bignum);
+            let n = *group.order();
+            let words = n.words();
+
+            // r = (k * G).x mod n
+            let point = group.alloc(bignum);
+            group.generator(bignum, point);
+            group.mul(bignum, point, k, point);
+            group.to_affine(bignum, point, &mut r, None);
+            let r_slot = bignum.alloc(words);
+            reduce::<C, E>(bignum, &group, r_slot, &r);
+            bignum.store_be_bytes(r_slot, &mut r);
+
+            // s = (e + r * d) / k mod n
+            let e = bignum.alloc(words);
+            reduce::<C, E>(bignum, &group, e, &truncate::<C>(prehash));
+            let d = bignum.alloc(words);
+            bignum.load_be_bytes(d, &self.secret);
+            let inverse = bignum.alloc(words);
+            bignum.load_be_bytes(inverse, k);
+            invert::<C, E>(bignum, &group, inverse);
+
+            let s_slot = bignum.alloc(words);
+            n.to_montgomery(bignum, s_slot, r_slot);
+            n.mul(bignum, s_slot, s_slot, d);
+            n.add(bignum, s_slot, s_slot, e);
+            n.mul(bignum, s_slot, s_slot, inverse);
+            bignum.store_be_bytes(s_slot, &mut s);
+
+            !bignum.is_zero(r_slot) & !bignum.is_zero(s_slot)
+        });
+        bignum.wipe();
+        if valid {
+            Ok(Signature { r, s })
+        } else {
+            Err(Error::InvalidSignature)
+        }
+    }
+}
+
+impl<C: Curve, E: Engine> Drop for SigningKey<'_, C, E> {
+    fn drop(&mut self) {
+        for byte in self.secret.iter_mut() {
+            unsafe { ptr::write_volatile(byte, 0) };
+        }
+    }
+}
+
+impl<C: Curve, E: Engine> RandomizedPrehashSigner<Signature<C>> for SigningKey<'_, C, E> {
+    fn sign_prehash_with_rng(
+        &self,
+        rng: &mut impl CryptoRngCore,
+        prehash: &[u8],
+    ) -> core::result::Result<Signature<C>, signature::Error> {
+        loop {
+            let mut k = random_scalar::<C>(rng);
+            let signature = self.sign_prehash_with_nonce(prehash, &k);
+            for byte in k.iter_mut() {
+                unsafe { ptr::write_volatile(byte, 0) };
+            }
+            if let Ok(signature) = signature {
+                return Ok(signature);
+            }
+        }
+    }
+}
+
+/// A public key, a point on the curve other than the point at infinity
+pub struct VerifyingKey<'b, C: Curve, E: Engine> {
+    x: FieldBytes<C>,
+    y: FieldBytes<C>,
+    bignum: &'b RefCell<Bignum<E>>,
+}
+
+impl<'b, C: Curve, E: Engine> VerifyingKey<'b, C, E> {
+    /// From big-endian affine coordinates, checking the point is on the curve.
+    pub fn from_affine(bignum: &'b RefCell<Bignum<E>>, x: &[u8], y: &[u8]) -> Result<Self> {
+        let size = C::P.len();
+        if x.len() != size || y.len() != size {
+            return Err(Error::InvalidKey);
+        }
+        let on_curve = bignum.borrow_mut().scope(|bignum| {
+            let group = Group::<C>::new(bignum);
+            let point = group.alloc(bignum);
+            group.load(bignum, point, x, y)
+        });
+        if !on_curve {
+            return Err(Error::InvalidKey);
+        }
+        Ok(Self {
+            x: GenericArray::clone_from_slice(x),
+            y: GenericArray::clone_from_slice(y),
+            bignum,
+        })
+    }
+
+    /// From the SEC1 uncompressed encoding, `04 || x || y`.
+    pub fn from_sec1_bytes(bignum: &'b RefCell<Bignum<E>>, bytes: &[u8]) -> Result<Self> {
+        let size = C::P.len();
+        if bytes.len() != 1 + 2 * size || bytes[0] != 4 {
+            return Err(Error::InvalidKey);
+        }
+        Self::from_affine(bignum, &bytes[1..1 + size], &bytes[1 + size..])
+    }
+
+    /// From a SEC1 encoded point, which must be uncompressed (there is no square root
+    /// to recover `y`).
+    pub fn from_encoded_point(
+        bignum: &'b RefCell<Bignum<E>>,
+        point: &EncodedPoint<C>,
+    ) -> Result<Self> {
+        match point.coordinates() {
+            Coordinates::Uncompressed { x, y } => Self::from_affine(bignum, x, y),
+            _ => Err(Error::InvalidKey),
+        }
+    }
+
+    /// Writes the SEC1 uncompressed encoding, `04 || x || y`.
+    pub fn write_sec1_bytes(&self, bytes: &mut [u8]) {
+        let size = C::P.len();
+        bytes[0] = 4;
+        bytes[1..1 + size].copy_from_slice(&self.x);
+        bytes[1 + size..1 + 2 * size].copy_from_slice(&self.y);
+    }
+
+    pub fn x(&self) -> &FieldBytes<C> {
+        &self.x
+    }
+
+    pub fn y(&self) -> &FieldBytes<C> {
+        &self.y
+    }
+
+    /// Verifies a signature of a hash, e.g. SHA-256 for P-256 and SHA-384 for P-384.
+    pub fn verify(&self, prehash: &[u8], signature: &Signature<C>) -> Result<()> {
+        let mut bignum = self.bignum.borrow_mut();
+        let valid = bignum.scope(|bignum| {
+            let group = Group::<C>::new(bignum);
+            let n = *group.order();
+            let words = n.words();
+
+            let r = bignum.alloc(words);
+            let s = bignum.alloc(words);
+            bignum.load_be_bytes(r, &signature.r);
+            bignum.load_be_bytes(s, &signature.s);
+            if bignum.is_zero(r)
+                || bignum.is_zero(s)
+                || !n.contains(bignum, r)
+                || !n.contains(bignum, s)
+            {
+                return false;
+            }
+
+            // u1 = e / s, u2 = r / s
+            let e = bignum.alloc(words);
+            reduce::<C, E>(bignum, &group, e, &truncate::<C>(prehash));
+            invert::<C, E>(bignum, &group, s);
+            let mut u1 = FieldBytes::<C>::default();
+            let mut u2 = FieldBytes::<C>::default();
+            n.mul(bignum, e, e, s);
+            bignum.store_be_bytes(e, &mut u1);
+            n.mul(bignum, e, r, s);
+            bignum.store_be_bytes(e, &mut u2);
+
+            // (u1 * G + u2 * Q).x mod n = r
+            let q = group.alloc(bignum);
+            group.load(bignum, q, &self.x, &self.y);
+            group.mul_add_public(bignum, q, &u1, &u2, q);
+            let mut x = FieldBytes::<C>::default();
+            if !group.to_affine(bignum, q, &mut x, None) {
+                return false;
+            }
+            reduce::<C, E>(bignum, &group, e, &x);
+            bignum.compare(e, r) == core::cmp::Ordering::Equal
+        });
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::InvalidSignature)
+        }
+    }
+}
+
+impl<C: Curve, E: Engine> ToEncodedPoint<C> for VerifyingKey<'_, C, E> {
+    fn to_encoded_point(&self, compress: bool) -> EncodedPoint<C> {
+        EncodedPoint::<C>::from_affine_coordinates(&self.x, &self.y, compress)
+    }
+}
+
+impl<C: Curve, E: Engine> PrehashVerifier<Signature<C>> for VerifyingKey<'_, C, E> {
+    fn verify_prehash(
+        &self,
+        prehash: &[u8],
+        signature: &Signature<C>,
+    ) -> core::result::Result<(), signature::Error> {
+        Ok(self.verify(prehash, signature)?)
+    }
+}
+
+// the leftmost bytes of the hash, as many as the order has (the orders of P-256 and
+// P-384 have whole bytes)
+fn truncate<C: Curve>(prehash: &[u8]) -> FieldBytes<C> {
+    let mut e = FieldBytes::<C>::default();
+    let size = e.len();
+    if prehash.len() >= size {
+        e.copy_from_slice(&prehash[..size]);
+    } else {
+        e[size - prehash.len()..].copy_from_slice(prehash);
+    }
+    e
+}
+
+// r = value mod n, for values less than 2n
+fn reduce<C: Curve, E: Engine>(bignum: &mut Bignum<E>, group: &Group<C>, r: Slot, value: &[u8]) {
+    bignum.scope(|bignum| {
+        let extended = bignum.alloc(r.words() + 1);
+        bignum.load_be_bytes(extended, value);
+        group.order().reduce(bignum, r, extended);
+    })
+}
+
+// a = 1 / a mod n in Montgomery form, so that Montgomery products with it divide by a
+fn invert<C: Curve, E: Engine>(bignum: &mut Bignum<E>, group: &Group<C>, a: Slot) {
+    let n = group.order();
+    let mut exponent = FieldBytes::<C>::default();
+    minus_two(C::N, &mut exponent);
+    n.to_montgomery(bignum, a, a);
+    n.pow_montgomery(bignum, a, a, &exponent);
+}
+
+// whether 0 < value < n, in constant time
+fn is_scalar(value: &[u8], n: &[u8]) -> bool {
+    let mut borrow = 0u16;
+    let mut bits = 0u8;
+    for (byte, n) in value.iter().zip(n.iter()).rev() {
+        let difference = u16::from(*byte)
+            .wrapping_sub(u16::from(*n))
+            .wrapping_sub(borrow);
+        borrow = (difference >> 8) & 1;
+        bits |= byte;
+    }
+    (borrow == 1) & (bits != 0)
+}
+
+fn random_scalar<C: Curve>(rng: &mut impl CryptoRngCore) -> FieldBytes<C> {
+    let mut scalar = FieldBytes::<C>::default();
+    loop {
+        rng.fill_bytes(&mut scalar);
+        if is_scalar(&scalar, C::N) {
+            return scalar;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::casper::Model;
+    use crate::testing::hex;
+    use elliptic_curve::{bigint::ArrayEncoding, Curve as _};
+    use sha2::{Digest, Sha256, Sha384};
+    use wycheproof::{ecdh, ecdsa, HashFunction, TestResult};
+
+    fn bignum() -> RefCell<Bignum<Model>> {
+        RefCell::new(Bignum::new(Model::new()))
+    }
+
+    // the digest of `message`, in `out`
+    fn prehash<'a>(function: HashFunction, message: &[u8], out: &'a mut [u8; 48]) -> &'a [u8] {
+        match function {
+            HashFunction::Sha2_256 => {
+                out[..32].copy_from_slice(&Sha256::digest(message));
+                &out[..32]
+            }
+            HashFunction::Sha2_384 => {
+                out.copy_from_slice(&Sha384::digest(message));
+                out
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn wycheproof_ecdsa<C: Curve>(name: ecdsa::TestName) {
+        let bignum = bignum();
+        let set = ecdsa::TestSet::load(name).unwrap();
+        for group in set.test_groups {
+            let point = EncodedPoint::<C>::from_bytes(&group.key.key).unwrap();
+            let key = VerifyingKey::<C, _>::from_encoded_point(&bignum, &point).unwrap();
+            for test in group.tests {
+                let mut digest = [0u8; 48];
+                let digest = prehash(group.hash, &test.msg, &mut digest);
+                let valid = Signature::<C>::from_slice(&test.sig)
+                    .and_then(|signature| key.verify(digest, &signature))
+                    .is_ok();
+                match test.result {
+                    TestResult::Valid => assert!(valid, "test {}: {}", test.tc_id, test.comment),
+                    TestResult::Invalid => assert!(!valid, "test {}: {}", test.tc_id, test.comment),
+                    TestResult::Acceptable => {}
+                }
+            }
+        }
+    }
+
+    fn wycheproof_ecdh<C: Curve>(name: ecdh::TestName) {
+        let bignum = bignum();
+        let set = ecdh::TestSet::load(name).unwrap();
+        for group in set.test_groups {
+            for test in group.tests {
+                // the private keys are ASN.1 integers, with a leading zero byte or shorter
+                let private = &test.private_key[..];
+                let mut secret = FieldBytes::<C>::default();
+                let start = private.len().saturating_sub(secret.len());
+                assert!(private[..start].iter().all(|byte| *byte == 0));
+                let offset = secret.len() - (private.len() - start);
+                secret[offset..].copy_from_slice(&private[start..]);
+
+                let shared = VerifyingKey::<C, _>::from_sec1_bytes(&bignum, &test.public_key)
+                    .and_then(|public| {
+                        SigningKey::<C, _>::from_bytes(&bignum, &secret)?.diffie_hellman(&public)
+                    });
+                match test.result {
+                    TestResult::Valid => assert_eq!(
+                        shared.as_deref(),
+                        Ok(&test.shared_secret[..]),
+                        "test {}: {}",
+                        test.tc_id,
+                        test.comment
+                    ),
+                    TestResult::Invalid => {
+                        assert!(shared.is_err(), "test {}: {}", test.tc_id, test.comment)
+                    }
+                    TestResult::Acceptable => {}
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wycheproof_p256() {
+        wycheproof_ecdsa::<P256>(ecdsa::TestName::EcdsaSecp256r1Sha256P1363);
+        wycheproof_ecdh::<P256>(ecdh::TestName::EcdhSecp256r1Ecpoint);
+    }
+
+    #[test]
+    fn wycheproof_p384() {
+        wycheproof_ecdsa::<P384>(ecdsa::TestName::EcdsaSecp384r1Sha384P1363);
+        wycheproof_ecdh::<P384>(ecdh::TestName::EcdhSecp384r1Ecpoint);
+    }
+
+    // RFC 6979, A.2.5 and A.2.6: the keys, and the signatures of "sample" with the
+    // deterministic nonces for SHA-256 and SHA-384
+    #[test]
+    fn rfc6979_p256() {
+        let bignum = bignum();
+        let secret: [u8; 32] =
+            hex("c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721");
+        let key = SigningKey::<P256, _>::from_bytes(&bignum, &secret).unwrap();
+        let public = key.verifying_key();
+        assert_eq!(
+            public.x()[..],
+            hex::<32>("60fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb6")
+        );
+        assert_eq!(
+            public.y()[..],
+            hex::<32>("7903fe1008b8bc99a41ae9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299")
+        );
+
+        let digest = Sha256::digest(b"sample");
+        let k: [u8; 32] = hex("a6e3c57dd01abe90086538398355dd4c3b17aa873382b0f24d6129493d8aad60");
+        let signature = key.sign_prehash_with_nonce(&digest, &k).unwrap();
+        assert_eq!(
+            signature.r()[..],
+            hex::<32>("efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716")
+        );
+        assert_eq!(
+            signature.s()[..],
+            hex::<32>("f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8")
+        );
+        assert!(public.verify(&digest, &signature).is_ok());
+        assert!(public.verify(&Sha256::digest(b"test"), &signature).is_err());
+    }
+
+    #[test]
+    fn rfc6979_p384() {
+        let bignum = bignum();
+        let secret: [u8; 48] = hex(
+            "6b9d3dad2e1b8c1c05b19875b6659f4de23c3b667bf297ba9aa47740787137d8
+             96d5724e4c70a825f872c9ea60d2edf5",
+        );
+        let key = SigningKey::<P384, _>::from_bytes(&bignum, &secret).unwrap();
+        let public = key.verifying_key();
+        assert_eq!(
+            public.x()[..],
+            hex::<48>(
+                "ec3a4e415b4e19a4568618029f427fa5da9a8bc4ae92e02e06aae5286b300c64
+                 def8f0ea9055866064a254515480bc13"
+            )
+        );
+
+        let digest = Sha384::digest(b"sample");
+        let k: [u8; 48] = hex(
+            "94ed910d1a099dad3254e9242ae85abde4ba15168eaf0ca87a555fd56d10fbca
+             2907e3e83ba95368623b8c4686915cf9",
+        );
+        let signature = key.sign_prehash_with_nonce(&digest, &k).unwrap();
+        assert_eq!(
+            signature.r()[..],
+            hex::<48>(
+                "94edbb92a5ecb8aad4736e56c691916b3f88140666ce9fa73d64c4ea95ad133c
+                 81a648152e44acf96e36dd1e80fabe46"
+            )
+        );
+        assert_eq!(
+            signature.s()[..],
+            hex::<48>(
+                "99ef4aeb15f178cea1fe40db2603138f130e740a19624526203b6351d0a3a94f
+                 a329c145786e679e7b82c71a38628ac8"
+            )
+        );
+        assert!(public.verify(&digest, &signature).is_ok());
+    }
+
+    #[test]
+    fn elliptic_curve_types() {
+        assert_eq!(P256::ORDER.to_be_byte_array()[..], *P256::N);
+        assert_eq!(P384::ORDER.to_be_byte_array()[..], *P384::N);
+
+        let bignum = bignum();
+        let secret: [u8; 32] =
+            hex("c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721");
+        let secret = SecretKey::<P256>::from_slice(&secret).unwrap();
+        let key = SigningKey::from_secret_key(&bignum, &secret);
+        assert_eq!(key.to_secret_key().to_bytes(), secret.to_bytes());
+
+        let public = key.verifying_key();
+        let point = public.to_encoded_point(false);
+        let decoded = VerifyingKey::<P256, _>::from_encoded_point(&bignum, &point).unwrap();
+        assert_eq!((decoded.x(), decoded.y()), (public.x(), public.y()));
+        let mut sec1 = [0u8; 65];
+        public.write_sec1_bytes(&mut sec1);
+        assert_eq!(point.as_bytes(), &sec1[..]);
+
+        // y is odd
+        let compressed = public.to_encoded_point(true);
+        assert_eq!(compressed.as_bytes()[0], 3);
+        assert_eq!(compressed.as_bytes()[1..], public.x()[..]);
+        assert!(VerifyingKey::<P256, _>::from_encoded_point(&bignum, &compressed).is_err());
+        assert!(VerifyingKey::<P256, _>::from_encoded_point(
+            &bignum,
+            &EncodedPoint::<P256>::identity()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,507 @@
+//! Ed25519 signatures (RFC 8032) and X25519 key agreement (RFC 7748), on CASPER.
+//!
+//! The field and group arithmetic runs on `Bignum` (see `casper::curve25519`), hashing on
+//! any SHA-512 implementing `FixedOutputReset`, such as the `sha2` crate (HASHCRYPT has
+//! no SHA-512). Both Ed25519 and Ed25519ph (prehashed, with an optional context) are
+//! supported. Signing runs the same CASPER operations whatever the key, and wipes the
+//! free part of the RAM window afterwards.
+//!
+//! ```ignore
+//! let mut bignum = Bignum::new(&mut casper);
+//! let key = SigningKey::from_seed(&mut bignum, &mut sha512, &seed);
+//! let signature = key.sign(&mut bignum, &mut sha512, message);
+//! key.verifying_key().verify(&mut bignum, &mut sha512, message, &signature)?;
+//!
+//! let shared = x25519(&mut bignum, &secret, &their_public);
+//! ```
+
+use core::ptr;
+
+use crate::{
+    drivers::casper::{curve25519::Edwards25519, Bignum, Engine, Slot},
+    traits::digest::{consts::U64, FixedOutputReset, Update},
+};
+
+pub use crate::drivers::casper::curve25519::{x25519, X25519_BASEPOINT};
+
+// dom2 prefix of Ed25519ph and Ed25519ctx
+const DOM2: &[u8] = b"SigEd25519 no Ed25519 collisions";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// Not the encoding of a point on the curve
+    InvalidKey,
+    /// `S` out of range, or not matching
+    InvalidSignature,
+    /// Contexts have at most 255 bytes
+    ContextTooLong,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// An Ed25519 signature `(R, S)`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Signature {
+    r: [u8; 32],
+    s: [u8; 32],
+}
+
+impl Signature {
+    /// From the concatenation of `R` and `S`.
+    pub fn from_bytes(bytes: &[u8; 64]) -> Self {
+        let mut r = [0u8; 32];
+        let mut s = [0u8; 32];
+        r.copy_from_slice(&bytes[..32]);
+        s.copy_from_slice(&bytes[32..]);
+        Self { r, s }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 64] {
+        let mut bytes = [0u8; 64];
+        bytes[..32].copy_from_slice(&self.r);
+        bytes[32..].copy_from_slice(&self.s);
+        bytes
+    }
+
+    /// The encoded point `R`
+    pub fn r(&self) -> &[u8; 32] {
+        &self.r
+    }
+
+    /// The scalar `S`, little-endian
+    pub fn s(&self) -> &[u8; 32] {
+        &self.s
+    }
+}
+
+/// A private key, derived from a 32-byte seed
+pub struct SigningKey {
+    seed: [u8; 32],
+    // clamped, big-endian
+    scalar: [u8; 32],
+    prefix: [u8; 32],
+    public: VerifyingKey,
+}
+
+impl SigningKey {
+    pub fn from_seed<E, D>(bignum: &mut Bignum<E>, sha: &mut D, seed: &[u8; 32]) -> Self
+    where
+        E: Engine,
+        D: Update + FixedOutputReset<OutputSize = U64>,
+    {
+        sha.update(seed);
+        let mut h = sha.finalize_fixed_reset();
+        let mut scalar = [0u8; 32];
+        let mut prefix = [0u8; 32];
+        scalar.copy_from_slice(&h[..32]);
+        prefix.copy_from_slice(&h[32..]);
+        for byte in h.iter_mut() {
+            unsafe { ptr::write_volatile(byte, 0) };
+        }
+        scalar[0] &= 248;
+        scalar[31] &= 127;
+        scalar[31] |= 64;
+        scalar.reverse();
+
+        let mut public = [0u8; 32];
+        bignum.scope(|bignum| {
+            let group = Edwards25519::new(bignum);
+            let point = group.alloc(bignum);
+            group.base(bignum, point);
+            group.mul(bignum, point, &scalar, point);
+            group.compress(bignum, point, &mut public);
+        });
+        bignum.wipe();
+        Self {
+            seed: *seed,
+            scalar,
+            prefix,
+            public: VerifyingKey { bytes: public },
+        }
+    }
+
+    pub fn seed(&self) -> &[u8; 32] {
+        &self.seed
+    }
+
+    pub fn verifying_key(&self) -> &VerifyingKey {
+        &self.public
+    }
+
+    /// Signs `message` with Ed25519.
+    pub fn sign<E, D>(&self, bignum: &mut Bignum<E>, sha: &mut D, message: &[u8]) -> Signature
+    where
+        E: Engine,
+        D: Update + FixedOutputReset<OutputSize = U64>,
+    {
+        self.sign_with(bignum, sha, None, message)
+    }
+
+    /// Signs the SHA-512 digest of a message with Ed25519ph.
+    pub fn sign_prehashed<E, D>(
+        &self,
+        bignum: &mut Bignum<E>,
+        sha: &mut D,
+        prehash: &[u8; 64],
+        context: Option<&[u8]>,
+    ) -> Result<Signature>
+    where
+        E: Engine,
+        D: Update + FixedOutputReset<OutputSize = U64>,
+    {
+        let context = context.unwrap_or(&[]);
+        if context.len() > 255 {
+            return Err(Error::ContextTooLong);
+        }
+        Ok(self.sign_with(bignum, sha, Some(context), prehash))
+    }
+
+    fn sign_with<E, D>(
+        &self,
+        bignum: &mut Bignum<E>,
+        sha: &mut D,
+        context: Option<&[u8]>,
+        message: &[u8],
+    ) -> Signature
+    where
+        E: Engine,
+        D: Update + FixedOutputReset<OutputSize = U64>,
+    {
+        let mut r = [0u8; 32];
+        let mut s = [0u8; 32];
+        bignum.scope(|bignum| {
+            let group = Edwards25519::new(bignum);
+            let l = *group.order();
+
+            // r = H(prefix || M), R = r * B
+            dom2(sha, context);
+            sha.update(&self.prefix);
+            sha.update(message);
+            let nonce = bignum.alloc(4);
+            hash_to_scalar(bignum, &group, sha, nonce);
+            let mut nonce_bytes = [0u8; 32];
+            bignum.store_be_bytes(nonce, &mut nonce_bytes);
+            let point = group.alloc(bignum);
+            group.base(bignum, point);
+            group.mul(bignum, point, &nonce_bytes, point);
+            group.compress(bignum, point, &mut r);
+            for byte in nonce_bytes.iter_mut() {
+                unsafe { ptr::write_volatile(byte, 0) };
+            }
+
+            // S = r + H(R || A || M) * a mod l
+            dom2(sha, context);
+            sha.update(&r);
+            sha.update(&self.public.bytes);
+            sha.update(message);
+            let k = bignum.alloc(4);
+            hash_to_scalar(bignum, &group, sha, k);
+            let a = bignum.alloc(4);
+            bignum.load_be_bytes(a, &self.scalar);
+            l.to_montgomery(bignum, k, k);
+            l.mul(bignum, k, a, k);
+            l.add(bignum, k, k, nonce);
+            bignum.store_be_bytes(k, &mut s);
+        });
+        bignum.wipe();
+        s.reverse();
+        Signature { r, s }
+    }
+}
+
+impl Drop for SigningKey {
+    fn drop(&mut self) {
+        for byte in self
+            .seed
+            .iter_mut()
+            .chain(self.scalar.iter_mut())
+            .chain(self.prefix.iter_mut())
+        {
+            unsafe { ptr::write_volatile(byte, 0) };
+        }
+    }
+}
+
+/// A public key, the encoding of a point on the curve
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct VerifyingKey {
+    bytes: [u8; 32],
+}
+
+impl VerifyingKey {
+    /// Checks the encoding is canonical and the point is on the curve.
+    pub fn from_bytes<E: Engine>(bignum: &mut Bignum<E>, bytes: &[u8; 32]) -> Result<Self> {
+        let valid = bignum.scope(|bignum| {
+            let group = Edwards25519::new(bignum);
+            let point = group.alloc(bignum);
+            group.decompress(bignum, point, bytes)
+        });
+        if valid {
+            Ok(Self { bytes: *bytes })
+        } else {
+            Err(Error::InvalidKey)
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.bytes
+    }
+
+    /// Verifies an Ed25519 signature of `message`.
+    pub fn verify<E, D>(
+        &self,
+        bignum: &mut Bignum<E>,
+        sha: &mut D,
+        message: &[u8],
+        signature: &Signature,
+    ) -> Result<()>
+    where
+        E: Engine,
+        D: Update + FixedOutputReset<OutputSize = U64>,
+    {
+        self.verify_with(bignum, sha, None, message, signature)
+    }
+
+    /// Verifies an Ed25519ph signature of the SHA-512 digest of a message.
+    pub fn verify_prehashed<E, D>(
+        &self,
+        bignum: &mut Bignum<E>,
+        sha: &mut D,
+        prehash: &[u8; 64],
+        context: Option<&[u8]>,
+        signature: &Signature,
+    ) -> Result<()>
+    where
+        E: Engine,
+        D: Update + FixedOutputReset<OutputSize = U64>,
+    {
+        let context = context.unwrap_or(&[]);
+        if context.len() > 255 {
+            return Err(Error::ContextTooLong);
+        }
+        self.verify_with(bignum, sha, Some(context), prehash, signature)
+    }
+
+    // S * B - H(R || A || M) * A encodes to R
+    fn verify_with<E, D>(
+        &self,
+        bignum: &mut Bignum<E>,
+        sha: &mut D,
+        context: Option<&[u8]>,
+        message: &[u8],
+        signature: &Signature,
+    ) -> Result<()>
+    where
+        E: Engine,
+        D: Update + FixedOutputReset<OutputSize = U64>,
+    {
+        let valid = bignum.scope(|bignum| {
+            let group = Edwards25519::new(bignum);
+            let mut s = signature.s;
+            s.reverse();
+            let s_slot = bignum.alloc(4);
+            bignum.load_be_bytes(s_slot, &s);
+            if !group.order().contains(bignum, s_slot) {
+                return false;
+            }
+
+            dom2(sha, context);
+            sha.update(&signature.r);
+            sha.update(&self.bytes);
+            sha.update(message);
+            let k = bignum.alloc(4);
+            hash_to_scalar(bignum, &group, sha, k);
+            let mut k_bytes = [0u8; 32];
+            bignum.store_be_bytes(k, &mut k_bytes);
+
+            let point = group.alloc(bignum);
+            if !group.decompress(bignum, point, &self.bytes) {
+                return false;
+            }
+            group.neg(bignum, point, point);
+            group.mul_add_public(bignum, point, &s, &k_bytes, point);
+            let mut r = [0u8; 32];
+            group.compress(bignum, point, &mut r);
+            r == signature.r
+        });
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::InvalidSignature)
+        }
+    }
+}
+
+// dom2(1, context) for Ed25519ph, nothing for Ed25519
+fn dom2<D: Update>(sha: &mut D, context: Option<&[u8]>) {
+    if let Some(context) = context {
+        sha.update(DOM2);
+        sha.update(&[1, context.len() as u8]);
+        sha.update(context);
+    }
+}
+
+// r = the little-endian hash mod l
+fn hash_to_scalar<E, D>(bignum: &mut Bignum<E>, group: &Edwards25519, sha: &mut D, r: Slot)
+where
+    E: Engine,
+    D: FixedOutputReset<OutputSize = U64>,
+{
+    let mut h = sha.finalize_fixed_reset();
+    h.reverse();
+    bignum.scope(|bignum| {
+        let wide = bignum.alloc(8);
+        bignum.load_be_bytes(wide, &h);
+        group.order().reduce_wide(bignum, r, wide);
+    });
+    for byte in h.iter_mut() {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::casper::Model;
+    use crate::testing::hex;
+    use sha2::{Digest, Sha512};
+
+    // RFC 8032, section 7.1: the secret key (seed), public key, message and signature
+    fn ed25519(secret: &str, public: &str, message: &[u8], signature: &str) {
+        let mut bignum = Bignum::new(Model::new());
+        let mut sha = Sha512::new();
+        let key = SigningKey::from_seed(&mut bignum, &mut sha, &hex(secret));
+        assert_eq!(*key.verifying_key().as_bytes(), hex::<32>(public));
+
+        let expected = Signature::from_bytes(&hex(signature));
+        assert_eq!(key.sign(&mut bignum, &mut sha, message), expected);
+        let public = VerifyingKey::from_bytes(&mut bignum, &hex(public)).unwrap();
+        assert!(public
+            .verify(&mut bignum, &mut sha, message, &expected)
+            .is_ok());
+        assert!(public
+            .verify(&mut bignum, &mut sha, b"another message", &expected)
+            .is_err());
+    }
+
+    #[test]
+    fn rfc8032() {
+        ed25519(
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+            b"",
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155
+             5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        );
+        ed25519(
+            "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+            "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+            &[0x72],
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da
+             085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+        );
+        ed25519(
+            "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+            "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+            &[0xaf, 0x82],
+            "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac
+             18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+        );
+        // TEST SHA(abc)
+        ed25519(
+            "833fe62409237b9d62ec77587520911e9a759cec1d19755b7da901b96dca3d42",
+            "ec172b93ad5e563bf4932c70e1245034c35467ef2efd4d64ebf819683467e2bf",
+            &Sha512::digest(b"abc"),
+            "dc2a4459e7369633a52b1bf277839a00201009a3efbf3ecb69bea2186c26b589
+             09351fc9ac90b3ecfdfbc7c66431e0303dca179c138ac17ad9bef1177331a704",
+        );
+    }
+
+    // RFC 8032, section 7.3: Ed25519ph of "abc", without context
+    #[test]
+    fn rfc8032_prehashed() {
+        let mut bignum = Bignum::new(Model::new());
+        let mut sha = Sha512::new();
+        let key = SigningKey::from_seed(
+            &mut bignum,
+            &mut sha,
+            &hex("833fe62409237b9d62ec77587520911e9a759cec1d19755b7da901b96dca3d42"),
+        );
+        let prehash: [u8; 64] = Sha512::digest(b"abc").into();
+        let signature = key
+            .sign_prehashed(&mut bignum, &mut sha, &prehash, None)
+            .unwrap();
+        assert_eq!(
+            signature.to_bytes(),
+            hex(
+                "98a70222f0b8121aa9d30f813d683f809e462b469c7ff87639499bb94e6dae41
+                 31f85042463c2a355a2003d062adf5aaa10b8c61e636062aaad11c2a26083406"
+            )
+        );
+
+        let public = key.verifying_key();
+        assert!(public
+            .verify_prehashed(&mut bignum, &mut sha, &prehash, None, &signature)
+            .is_ok());
+        // an empty context is the same as none, but Ed25519ph differs from Ed25519
+        assert!(public
+            .verify_prehashed(&mut bignum, &mut sha, &prehash, Some(&[]), &signature)
+            .is_ok());
+        assert!(public
+            .verify_prehashed(
+                &mut bignum,
+                &mut sha,
+                &prehash,
+                Some(b"context"),
+                &signature
+            )
+            .is_err());
+        assert!(public
+            .verify(&mut bignum, &mut sha, &prehash, &signature)
+            .is_err());
+        assert_eq!(
+            key.sign_prehashed(&mut bignum, &mut sha, &prehash, Some(&[0; 256])),
+            Err(Error::ContextTooLong)
+        );
+    }
+
+    // `S` must be less than l: S + l, which reduces to the valid `S`, and l are rejected
+    #[test]
+    fn scalar_out_of_range() {
+        let mut bignum = Bignum::new(Model::new());
+        let mut sha = Sha512::new();
+        let public = VerifyingKey::from_bytes(
+            &mut bignum,
+            &hex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"),
+        )
+        .unwrap();
+        let signature: [u8; 64] = hex(
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155
+             5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        );
+        assert!(public
+            .verify(
+                &mut bignum,
+                &mut sha,
+                b"",
+                &Signature::from_bytes(&signature)
+            )
+            .is_ok());
+
+        let mut bytes = signature;
+        bytes[32..].copy_from_slice(&hex::<32>(
+            "4c8c7872aa064e049dbb3013fbf29380d25bf5f0595bbe24655141438e7a101b",
+        ));
+        assert_eq!(
+            public.verify(&mut bignum, &mut sha, b"", &Signature::from_bytes(&bytes)),
+            Err(Error::InvalidSignature)
+        );
+        bytes[32..].copy_from_slice(&hex::<32>(
+            "edd3f55c1a631258d69cf7a2def9de1400000000000000000000000000000010",
+        ));
+        assert_eq!(
+            public.verify(&mut bignum, &mut sha, b"", &Signature::from_bytes(&bytes)),
+            Err(Error::InvalidSignature)
+        );
+    }
+}
@@ -523,6 +523,8 @@ macro_rules! littlefs2_prince_filesystem {
             type LOOKAHEAD_SIZE = $crate::drivers::flash::littlefs_params::LOOKAHEAD_SIZE;
 
 
+            // the ROM loads the region 2 key at boot, there is no token for it
+            #[allow(deprecated)]
             fn read(&mut self, off: usize, buf: &mut [u8]) -> LfsResult<usize> {
                 self.prince.enable_region_2_for(||{
                     let flash: *const u8 = (Self::BASE_OFFSET + off) as *const u8;
@@ -533,6 +535,8 @@ macro_rules! littlefs2_prince_filesystem {
                 Ok(buf.len())
             }
 
+            // the ROM loads the region 2 key at boot, there is no token for it
+            #[allow(deprecated)]
             fn write(&mut self, off: usize, data: &[u8]) -> LfsResult<usize> {
                 let prince = &mut self.prince;
                 let flash_gordon = &mut self.flash_gordon;
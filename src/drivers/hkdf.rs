@@ -0,0 +1,185 @@
+use crate::{
+    drivers::{
+        hmac::{Engine, Hmac},
+        sha::Sha256,
+    },
+    peripherals::hashcrypt::Hashcrypt,
+    traits::digest::{FixedOutputReset, InvalidLength, Output, Update},
+    typestates::init_state::Enabled,
+};
+
+const HASH_LEN: usize = 32;
+
+/// HKDF-SHA256 (RFC 5869) on top of `Hmac`.
+///
+/// Holds the HMAC keyed with the pseudorandom key, so repeated `expand` calls
+/// do not hash the key again.
+pub struct Hkdf<S: Engine> {
+    hmac: Hmac<S>,
+}
+
+/// HKDF-SHA256 on the HASHCRYPT peripheral.
+pub type HkdfSha256<'a> = Hkdf<Sha256<'a>>;
+
+impl<'a> HkdfSha256<'a> {
+    /// Extract step, keeping only the HKDF. Without salt, uses 32 zero bytes.
+    pub fn new(hashcrypt: &'a mut Hashcrypt<Enabled>, salt: Option<&[u8]>, ikm: &[u8]) -> Self {
+        Self::extract(hashcrypt, salt, ikm).1
+    }
+
+    /// Extract step, returning the pseudorandom key as well.
+    pub fn extract(
+        hashcrypt: &'a mut Hashcrypt<Enabled>,
+        salt: Option<&[u8]>,
+        ikm: &[u8],
+    ) -> (Output<Hmac<Sha256<'a>>>, Self) {
+        Self::extract_with(Sha256::new(hashcrypt), salt, ikm)
+    }
+
+    /// Skips the extract step, for a pseudorandom key of at least 32 bytes.
+    pub fn from_prk(
+        hashcrypt: &'a mut Hashcrypt<Enabled>,
+        prk: &[u8],
+    ) -> Result<Self, InvalidLength> {
+        Self::from_prk_with(Sha256::new(hashcrypt), prk)
+    }
+
+    pub fn into_inner(self) -> &'a mut Hashcrypt<Enabled> {
+        self.hmac.into_inner()
+    }
+}
+
+impl<S: Engine> Hkdf<S> {
+    /// Extract step on the given hasher, as `HkdfSha256::extract`.
+    pub fn extract_with(sha: S, salt: Option<&[u8]>, ikm: &[u8]) -> (Output<Hmac<S>>, Self) {
+        let mut hmac = Hmac::from_sha(sha, salt.unwrap_or(&[0; HASH_LEN]));
+        hmac.update(ikm);
+        let prk = hmac.finalize_fixed_reset();
+
+        let hmac = Hmac::from_sha(hmac.into_sha(), &prk);
+        (prk, Self { hmac })
+    }
+
+    /// Skips the extract step on the given hasher, as `HkdfSha256::from_prk`.
+    pub fn from_prk_with(sha: S, prk: &[u8]) -> Result<Self, InvalidLength> {
+        if prk.len() < HASH_LEN {
+            return Err(InvalidLength);
+        }
+        Ok(Self {
+            hmac: Hmac::from_sha(sha, prk),
+        })
+    }
+
+    /// Expand step, filling `okm`, which may be at most 255 * 32 bytes long.
+    pub fn expand(&mut self, info: &[u8], okm: &mut [u8]) -> Result<(), InvalidLength> {
+        if okm.len() > 255 * HASH_LEN {
+            return Err(InvalidLength);
+        }
+
+        let mut previous: Option<Output<Hmac<S>>> = None;
+        for (i, chunk) in okm.chunks_mut(HASH_LEN).enumerate() {
+            if let Some(previous) = &previous {
+                self.hmac.update(previous);
+            }
+            self.hmac.update(info);
+            self.hmac.update(&[i as u8 + 1]);
+            let block = self.hmac.finalize_fixed_reset();
+            chunk.copy_from_slice(&block[..chunk.len()]);
+            previous = Some(block);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::hex;
+
+    type SoftHkdf = Hkdf<sha2::Sha256>;
+
+    // RFC 5869, appendix A.1
+    #[test]
+    fn rfc5869_basic() {
+        let salt: [u8; 13] = hex("000102030405060708090a0b0c");
+        let info: [u8; 10] = hex("f0f1f2f3f4f5f6f7f8f9");
+        let (prk, mut hkdf) = SoftHkdf::extract_with(Default::default(), Some(&salt), &[0x0b; 22]);
+        assert_eq!(
+            prk[..],
+            hex::<32>("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5")
+        );
+
+        let mut okm = [0u8; 42];
+        hkdf.expand(&info, &mut okm).unwrap();
+        assert_eq!(
+            okm,
+            hex(
+                "3cb25f25faacd57a90434f64d0362f2a 2d2d0a90cf1a5a4c5db02d56ecc4c5bf
+                 34007208d5b887185865"
+            )
+        );
+    }
+
+    // RFC 5869, appendix A.2: several blocks, chained through the previous one
+    #[test]
+    fn rfc5869_long_inputs() {
+        let mut ikm = [0u8; 80];
+        let mut salt = [0u8; 80];
+        let mut info = [0u8; 80];
+        for i in 0..80 {
+            ikm[i] = i as u8;
+            salt[i] = 0x60 + i as u8;
+            info[i] = 0xb0 + i as u8;
+        }
+        let (prk, mut hkdf) = SoftHkdf::extract_with(Default::default(), Some(&salt), &ikm);
+        assert_eq!(
+            prk[..],
+            hex::<32>("06a6b88c5853361a06104c9ceb35b45cef760014904671014a193f40c15fc244")
+        );
+
+        let mut okm = [0u8; 82];
+        hkdf.expand(&info, &mut okm).unwrap();
+        assert_eq!(
+            okm,
+            hex(
+                "b11e398dc80327a1c8e7f78c596a4934 4f012eda2d4efad8a050cc4c19afa97c
+                 59045a99cac7827271cb41c65e590e09 da3275600c2f09b8367793a9aca3db71
+                 cc30c58179ec3e87c14c01d5c1f3434f 1d87"
+            )
+        );
+    }
+
+    // RFC 5869, appendix A.3: no salt is the same as an empty one
+    #[test]
+    fn rfc5869_no_salt() {
+        let expected_prk: [u8; 32] =
+            hex("19ef24a32c717b167f33a91d6f648bdf96596776afdb6377ac434c1c293ccb04");
+        let expected_okm: [u8; 42] = hex(
+            "8da4e775a563c18f715f802a063c5a31 b8a11f5c5ee1879ec3454e5f3c738d2d
+             9d201395faa4b61a96c8",
+        );
+
+        for salt in [None, Some(&[][..])].iter() {
+            let (prk, mut hkdf) = SoftHkdf::extract_with(Default::default(), *salt, &[0x0b; 22]);
+            assert_eq!(prk[..], expected_prk);
+            let mut okm = [0u8; 42];
+            hkdf.expand(&[], &mut okm).unwrap();
+            assert_eq!(okm, expected_okm);
+        }
+
+        let mut hkdf = SoftHkdf::from_prk_with(Default::default(), &expected_prk).unwrap();
+        let mut okm = [0u8; 42];
+        hkdf.expand(&[], &mut okm).unwrap();
+        assert_eq!(okm, expected_okm);
+    }
+
+    #[test]
+    fn invalid_lengths() {
+        assert!(SoftHkdf::from_prk_with(Default::default(), &[0; 31]).is_err());
+
+        let mut hkdf = SoftHkdf::from_prk_with(Default::default(), &[0; 32]).unwrap();
+        let mut okm = [0u8; 255 * HASH_LEN + 1];
+        assert!(hkdf.expand(&[], &mut okm).is_err());
+        assert!(hkdf.expand(&[], &mut okm[..255 * HASH_LEN]).is_ok());
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{
+    drivers::sha::Sha256,
+    peripherals::hashcrypt::Hashcrypt,
+    traits::digest::{
+        generic_array::typenum::U32, FixedOutput, FixedOutputReset, MacMarker, Output,
+        OutputSizeUser, Reset, Update,
+    },
+    typestates::init_state::Enabled,
+};
+
+const BLOCK_SIZE: usize = 64;
+const IPAD: u8 = 0x36;
+const OPAD: u8 = 0x5c;
+
+// intention of this module is to prevent users from implementing `Engine`
+// for anything but the hardware SHA-256; the tests implement it for `sha2`.
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::Sha256<'_> {}
+}
+
+/// A SHA-256 that can start hashing from a prepared padded key block.
+///
+/// The hardware SHA-256 cannot resume from a saved state, so its `Pad` is the padded
+/// key block itself, hashed again by `start` at the start of every message.
+pub trait Engine: sealed::Sealed + Update + FixedOutputReset<OutputSize = U32> {
+    /// The state after hashing a padded key block.
+    type Pad: Clone;
+
+    /// Hashes `block` from scratch, returning the state.
+    fn prepare(&mut self, block: &[u8; BLOCK_SIZE]) -> Self::Pad;
+
+    /// Continues from a prepared state, discarding the current hash.
+    fn start(&mut self, pad: &Self::Pad);
+}
+
+// keeps the padded key blocks, to hash one of them at the start of each hash
+impl Engine for Sha256<'_> {
+    type Pad = [u8; BLOCK_SIZE];
+
+    fn prepare(&mut self, block: &[u8; BLOCK_SIZE]) -> [u8; BLOCK_SIZE] {
+        *block
+    }
+
+    fn start(&mut self, pad: &[u8; BLOCK_SIZE]) {
+        self.reset();
+        Update::update(self, pad);
+    }
+}
+
+/// The key of an HMAC, as its padded inner and outer key blocks, prepared by the `Engine`.
+///
+/// Keeping it around avoids hashing a key longer than a block again, see `HmacSha256::with_key`.
+/// It does not save the hashes of the padded blocks: each message, as well as `reset`,
+/// hashes the inner block again, and finalizing hashes the outer block, so an HMAC
+/// costs two blocks more than hashing the message.
+#[derive(Clone)]
+pub struct Key<Pad> {
+    inner: Pad,
+    outer: Pad,
+}
+
+pub type HmacKey = Key<<Sha256<'static> as Engine>::Pad>;
+
+/// HMAC-SHA256 (RFC 2104) over an `Engine`, as in RustCrypto `Mac` trait.
+///
+/// Since `HmacSha256` borrows the peripheral, there is no `KeyInit`, so instead
+/// of `Mac::new` use `Hashcrypt::hmac_sha256` or `HmacSha256::with_key`:
+///
+/// ```ignore
+/// let mut mac = hashcrypt.hmac_sha256(b"key");
+/// mac.update(b"message");
+/// let tag = mac.finalize().into_bytes();
+/// ```
+pub struct Hmac<S: Engine> {
+    sha: S,
+    key: Key<S::Pad>,
+}
+
+/// HMAC-SHA256 on the HASHCRYPT peripheral.
+///
+/// Short messages are dominated by hashing the padded key blocks, which happens
+/// for every message, see `Key`.
+pub type HmacSha256<'a> = Hmac<Sha256<'a>>;
+
+impl<'a> HmacSha256<'a> {
+    pub fn new(hashcrypt: &'a mut Hashcrypt<Enabled>, key: &[u8]) -> Self {
+        Self::from_sha(Sha256::new(hashcrypt), key)
+    }
+
+    /// Starts a MAC with a key prepared by an earlier `HmacSha256`.
+    pub fn with_key(hashcrypt: &'a mut Hashcrypt<Enabled>, key: &HmacKey) -> Self {
+        Self::from_sha_with_key(Sha256::new(hashcrypt), key)
+    }
+
+    pub fn into_inner(self) -> &'a mut Hashcrypt<Enabled> {
+        self.sha.into_inner()
+    }
+}
+
+impl<S: Engine> Hmac<S> {
+    pub fn from_sha(mut sha: S, key: &[u8]) -> Self {
+        let mut block = [0u8; BLOCK_SIZE];
+        if key.len() > BLOCK_SIZE {
+            Update::update(&mut sha, key);
+            let digest = sha.finalize_fixed_reset();
+            block[..digest.len()].copy_from_slice(&digest);
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+
+        let key = Key {
+            inner: Self::padded_state(&mut sha, &block, IPAD),
+            outer: Self::padded_state(&mut sha, &block, OPAD),
+        };
+        sha.start(&key.inner);
+        Self { sha, key }
+    }
+
+    pub fn from_sha_with_key(mut sha: S, key: &Key<S::Pad>) -> Self {
+        sha.start(&key.inner);
+        Self {
+            sha,
+            key: key.clone(),
+        }
+    }
+
+    pub fn key(&self) -> Key<S::Pad> {
+        self.key.clone()
+    }
+
+    pub(crate) fn into_sha(self) -> S {
+        self.sha
+    }
+
+    fn padded_state(sha: &mut S, key: &[u8; BLOCK_SIZE], pad: u8) -> S::Pad {
+        let mut block = *key;
+        for byte in block.iter_mut() {
+            *byte ^= pad;
+        }
+        sha.prepare(&block)
+    }
+
+    fn finalize_into_dirty(&mut self, out: &mut Output<Self>) {
+        let inner = self.sha.finalize_fixed_reset();
+        self.sha.start(&self.key.outer);
+        Update::update(&mut self.sha, &inner);
+        self.sha.finalize_into_reset(out);
+    }
+}
+
+// the `digest` traits
+
+impl<S: Engine> OutputSizeUser for Hmac<S> {
+    type OutputSize = U32;
+}
+
+impl<S: Engine> MacMarker for Hmac<S> {}
+
+impl<S: Engine> Update for Hmac<S> {
+    fn update(&mut self, data: &[u8]) {
+        Update::update(&mut self.sha, data);
+    }
+}
+
+impl<S: Engine> FixedOutput for Hmac<S> {
+    fn finalize_into(mut self, out: &mut Output<Self>) {
+        self.finalize_into_dirty(out);
+    }
+}
+
+impl<S: Engine> FixedOutputReset for Hmac<S> {
+    fn finalize_into_reset(&mut self, out: &mut Output<Self>) {
+        self.finalize_into_dirty(out);
+        Reset::reset(self);
+    }
+}
+
+impl<S: Engine> Reset for Hmac<S> {
+    fn reset(&mut self) {
+        self.sha.start(&self.key.inner);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::hex;
+    use crate::traits::digest::Mac;
+
+    // the software SHA-256 stands in for the peripheral
+    impl sealed::Sealed for sha2::Sha256 {}
+
+    impl Engine for sha2::Sha256 {
+        type Pad = sha2::Sha256;
+
+        fn prepare(&mut self, block: &[u8; BLOCK_SIZE]) -> sha2::Sha256 {
+            let mut sha = sha2::Sha256::default();
+            Update::update(&mut sha, block);
+            sha
+        }
+
+        fn start(&mut self, pad: &sha2::Sha256) {
+            *self = pad.clone();
+        }
+    }
+
+    type SoftHmac = Hmac<sha2::Sha256>;
+
+    fn mac(key: &[u8], data: &[u8]) -> [u8; 32] {
+        let mut mac = SoftHmac::from_sha(Default::default(), key);
+        Update::update(&mut mac, data);
+        mac.finalize().into_bytes().into()
+    }
+
+    // RFC 4231, section 4
+    #[test]
+    fn rfc4231() {
+        assert_eq!(
+            mac(&[0x0b; 20], b"Hi There"),
+            hex("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7")
+        );
+        assert_eq!(
+            mac(b"Jefe", b"what do ya want for nothing?"),
+            hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")
+        );
+        assert_eq!(
+            mac(&[0xaa; 20], &[0xdd; 50]),
+            hex("773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe")
+        );
+        let key: [u8; 25] = hex("0102030405060708090a0b0c0d0e0f10111213141516171819");
+        assert_eq!(
+            mac(&key, &[0xcd; 50]),
+            hex("82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b")
+        );
+        // test case 5 is truncated to 128 bits
+        assert_eq!(
+            mac(&[0x0c; 20], b"Test With Truncation")[..16],
+            hex::<16>("a3b6167473100ee06e0c796c2955552b")
+        );
+    }
+
+    // RFC 4231, test cases 6 and 7: keys longer than a block are hashed first
+    #[test]
+    fn rfc4231_long_keys() {
+        assert_eq!(
+            mac(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            ),
+            hex("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54")
+        );
+        assert_eq!(
+            mac(
+                &[0xaa; 131],
+                b"This is a test using a larger than block-size key and a larger than \
+                  block-size data. The key needs to be hashed before being used by the \
+                  HMAC algorithm."
+            ),
+            hex("9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2")
+        );
+    }
+
+    #[test]
+    fn key_reuse_and_reset() {
+        let expected = mac(b"Jefe", b"what do ya want for nothing?");
+
+        let mut mac = SoftHmac::from_sha(Default::default(), b"Jefe");
+        Update::update(&mut mac, b"discarded");
+        Reset::reset(&mut mac);
+        Update::update(&mut mac, b"what do ya want for nothing?");
+        assert_eq!(mac.finalize_reset().into_bytes(), expected.into());
+        Update::update(&mut mac, b"what do ya want for nothing?");
+        assert_eq!(mac.finalize_reset().into_bytes(), expected.into());
+
+        let mut mac = SoftHmac::from_sha_with_key(Default::default(), &mac.key());
+        Update::update(&mut mac, b"what do ya want ");
+        Update::update(&mut mac, b"for nothing?");
+        assert!(mac.verify_slice(&expected).is_ok());
+    }
+}
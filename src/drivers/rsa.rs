@@ -0,0 +1,421 @@
+//! RSA signature verification with SHA-256 (RFC 8017), on CASPER.
+//!
+//! The modular exponentiation runs on `Bignum`, hashing on any SHA-256 implementing
+//! `FixedOutputReset`, such as `Hashcrypt::sha256`. Both the peripherals and software
+//! (`casper::Model` and e.g. the `sha2` crate) work, so verification can be tested on
+//! the host:
+//!
+//! ```ignore
+//! let key = RsaPublicKey::new(&MODULUS, 65537)?;
+//! let mut bignum = Bignum::new(&mut casper);
+//! key.verify_pkcs1v15_sha256(&mut bignum, &mut hashcrypt.sha256(), message, &signature)?;
+//! ```
+
+use crate::{
+    drivers::casper::{Bignum, Engine, Modulus},
+    traits::digest::{consts::U32, FixedOutputReset, Update},
+};
+
+/// Maximal length of the modulus, in bytes (RSA-4096)
+pub const MAX_BYTES: usize = 512;
+/// Minimal length of the modulus, in bytes (RSA-512)
+pub const MIN_BYTES: usize = 64;
+
+const HASH_LEN: usize = 32;
+// DER encoding of the SHA-256 `DigestInfo`, without the digest
+const DIGEST_INFO_SHA256: [u8; 19] = [
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// Modulus even or of unsupported length, or exponent even
+    InvalidKey,
+    /// Signature of the wrong length, not less than the modulus, or not matching
+    InvalidSignature,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// An RSA public key, with the modulus as big-endian bytes
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RsaPublicKey<'a> {
+    modulus: &'a [u8],
+    exponent: u32,
+}
+
+impl<'a> RsaPublicKey<'a> {
+    /// Leading zero bytes of the modulus are ignored.
+    pub fn new(modulus: &'a [u8], exponent: u32) -> Result<Self> {
+        let start = modulus
+            .iter()
+            .position(|byte| *byte != 0)
+            .unwrap_or(modulus.len());
+        let modulus = &modulus[start..];
+        if modulus.len() < MIN_BYTES
+            || modulus.len() > MAX_BYTES
+            || modulus[modulus.len() - 1] & 1 == 0
+            || exponent & 1 == 0
+            || exponent < 3
+        {
+            return Err(Error::InvalidKey);
+        }
+        Ok(Self { modulus, exponent })
+    }
+
+    /// Length of the modulus, and of signatures, in bytes
+    pub fn size(&self) -> usize {
+        self.modulus.len()
+    }
+
+    /// Verifies a PKCS #1 v1.5 signature of `message`.
+    pub fn verify_pkcs1v15_sha256<E, D>(
+        &self,
+        bignum: &mut Bignum<E>,
+        sha: &mut D,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<()>
+    where
+        E: Engine,
+        D: Update + FixedOutputReset<OutputSize = U32>,
+    {
+        sha.update(message);
+        let digest = sha.finalize_fixed_reset();
+        self.verify_pkcs1v15_sha256_prehash(bignum, digest.as_ref(), signature)
+    }
+
+    /// Verifies a PKCS #1 v1.5 signature of a SHA-256 digest.
+    pub fn verify_pkcs1v15_sha256_prehash<E: Engine>(
+        &self,
+        bignum: &mut Bignum<E>,
+        digest: &[u8; HASH_LEN],
+        signature: &[u8],
+    ) -> Result<()> {
+        let k = self.size();
+        let mut buffer = [0u8; MAX_BYTES];
+        let em = &mut buffer[..k];
+        self.public_operation(bignum, signature, em)?;
+
+        // 00 01 ff .. ff 00 || DigestInfo || digest
+        let mut expected = [0xffu8; MAX_BYTES];
+        let expected = &mut expected[..k];
+        expected[0] = 0;
+        expected[1] = 1;
+        let t = k - HASH_LEN - DIGEST_INFO_SHA256.len();
+        expected[t - 1] = 0;
+        expected[t..k - HASH_LEN].copy_from_slice(&DIGEST_INFO_SHA256);
+        expected[k - HASH_LEN..].copy_from_slice(digest);
+
+        if em == expected {
+            Ok(())
+        } else {
+            Err(Error::InvalidSignature)
+        }
+    }
+
+    /// Verifies a PSS signature of `message`, with MGF1-SHA256.
+    ///
+    /// Without `salt_len`, the salt length is taken from the signature.
+    pub fn verify_pss_sha256<E, D>(
+        &self,
+        bignum: &mut Bignum<E>,
+        sha: &mut D,
+        message: &[u8],
+        salt_len: Option<usize>,
+        signature: &[u8],
+    ) -> Result<()>
+    where
+        E: Engine,
+        D: Update + FixedOutputReset<OutputSize = U32>,
+    {
+        sha.update(message);
+        let digest = sha.finalize_fixed_reset();
+        self.verify_pss_sha256_prehash(bignum, sha, digest.as_ref(), salt_len, signature)
+    }
+
+    /// Verifies a PSS signature of a SHA-256 digest, with MGF1-SHA256.
+    ///
+    /// `sha` is needed for the mask generation and the final hash.
+    pub fn verify_pss_sha256_prehash<E, D>(
+        &self,
+        bignum: &mut Bignum<E>,
+        sha: &mut D,
+        digest: &[u8; HASH_LEN],
+        salt_len: Option<usize>,
+        signature: &[u8],
+    ) -> Result<()>
+    where
+        E: Engine,
+        D: Update + FixedOutputReset<OutputSize = U32>,
+    {
+        let k = self.size();
+        let mut buffer = [0u8; MAX_BYTES];
+        self.public_operation(bignum, signature, &mut buffer[..k])?;
+
+        // EMSA-PSS-VERIFY, with emBits = modBits - 1
+        let em_bits = 8 * k - self.modulus[0].leading_zeros() as usize - 1;
+        let em_len = (em_bits - 1) / 8 + 1;
+        if buffer[..k - em_len].iter().any(|byte| *byte != 0) {
+            return Err(Error::InvalidSignature);
+        }
+        let em = &mut buffer[k - em_len..k];
+        if em_len < HASH_LEN + 2 || em[em_len - 1] != 0xbc {
+            return Err(Error::InvalidSignature);
+        }
+
+        let (db, rest) = em.split_at_mut(em_len - HASH_LEN - 1);
+        let h = &rest[..HASH_LEN];
+        let top_mask = 0xffu8 >> (8 * em_len - em_bits);
+        if db[0] & !top_mask != 0 {
+            return Err(Error::InvalidSignature);
+        }
+
+        // MGF1
+        for (counter, chunk) in db.chunks_mut(HASH_LEN).enumerate() {
+            sha.update(h);
+            sha.update(&(counter as u32).to_be_bytes());
+            let mask = sha.finalize_fixed_reset();
+            chunk
+                .iter_mut()
+                .zip(mask.iter())
+                .for_each(|(byte, mask)| *byte ^= mask);
+        }
+        db[0] &= top_mask;
+
+        // DB = 00 .. 00 01 || salt
+        let separator = match salt_len {
+            Some(salt_len) if salt_len < db.len() => db.len() - salt_len - 1,
+            Some(_) => return Err(Error::InvalidSignature),
+            None => db
+                .iter()
+                .position(|byte| *byte != 0)
+                .ok_or(Error::InvalidSignature)?,
+        };
+        if db[..separator].iter().any(|byte| *byte != 0) || db[separator] != 1 {
+            return Err(Error::InvalidSignature);
+        }
+        let salt = &db[separator + 1..];
+
+        sha.update(&[0; 8]);
+        sha.update(digest);
+        sha.update(salt);
+        if sha.finalize_fixed_reset().as_slice() == h {
+            Ok(())
+        } else {
+            Err(Error::InvalidSignature)
+        }
+    }
+
+    /// `signature^e mod n`, into `em`, which is as long as the modulus.
+    pub fn public_operation<E: Engine>(
+        &self,
+        bignum: &mut Bignum<E>,
+        signature: &[u8],
+        em: &mut [u8],
+    ) -> Result<()> {
+        let k = self.size();
+        if signature.len() != k {
+            return Err(Error::InvalidSignature);
+        }
+        assert!(em.len() == k);
+        let words = (k - 1) / 8 + 1;
+        let exponent = self.exponent.to_be_bytes();
+        let exponent = &exponent[self.exponent.leading_zeros() as usize / 8..];
+
+        bignum.scope(|bignum| {
+            let n = bignum.alloc(words);
+            bignum.load_be_bytes(n, self.modulus);
+            let modulus = Modulus::new(bignum, n);
+
+            let s = bignum.alloc(words);
+            bignum.load_be_bytes(s, signature);
+            if !modulus.contains(bignum, s) {
+                return Err(Error::InvalidSignature);
+            }
+            modulus.pow(bignum, s, s, exponent);
+            bignum.store_be_bytes(s, em);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::casper::Model;
+    use crate::testing::hex;
+
+    const MESSAGE: &[u8] = b"Be that word our sign of parting, bird or fiend!";
+
+    fn verify_pkcs1v15(signature: &[u8]) -> Result<()> {
+        let modulus = hex::<256>(MODULUS);
+        let key = RsaPublicKey::new(&modulus, 65537)?;
+        let mut bignum = Bignum::new(Model::new());
+        key.verify_pkcs1v15_sha256(
+            &mut bignum,
+            &mut sha2::Sha256::default(),
+            MESSAGE,
+            signature,
+        )
+    }
+
+    fn verify_pss(message: &[u8], salt_len: Option<usize>, signature: &[u8]) -> Result<()> {
+        let modulus = hex::<256>(MODULUS);
+        let key = RsaPublicKey::new(&modulus, 65537)?;
+        let mut bignum = Bignum::new(Model::new());
+        key.verify_pss_sha256(
+            &mut bignum,
+            &mut sha2::Sha256::default(),
+            message,
+            salt_len,
+            signature,
+        )
+    }
+
+    #[test]
+    fn invalid_keys() {
+        let mut modulus = hex::<256>(MODULUS);
+        assert!(RsaPublicKey::new(&modulus, 65537).is_ok());
+        assert_eq!(RsaPublicKey::new(&modulus, 65536), Err(Error::InvalidKey));
+        assert_eq!(RsaPublicKey::new(&modulus, 1), Err(Error::InvalidKey));
+        assert_eq!(RsaPublicKey::new(&modulus[..63], 3), Err(Error::InvalidKey));
+        modulus[255] &= !1;
+        assert_eq!(RsaPublicKey::new(&modulus, 65537), Err(Error::InvalidKey));
+
+        // leading zeros are ignored
+        let mut padded = [0u8; 258];
+        padded[2..].copy_from_slice(&hex::<256>(MODULUS));
+        assert_eq!(RsaPublicKey::new(&padded, 65537).unwrap().size(), 256);
+    }
+
+    // signatures made with Python's `cryptography`
+    #[test]
+    fn pkcs1v15() {
+        let signature = hex::<256>(PKCS1V15);
+        assert_eq!(verify_pkcs1v15(&signature), Ok(()));
+
+        let mut tampered = signature;
+        tampered[100] ^= 1;
+        assert_eq!(verify_pkcs1v15(&tampered), Err(Error::InvalidSignature));
+        assert_eq!(
+            verify_pkcs1v15(&signature[..255]),
+            Err(Error::InvalidSignature)
+        );
+        // not less than the modulus
+        assert_eq!(
+            verify_pkcs1v15(&hex::<256>(MODULUS)),
+            Err(Error::InvalidSignature)
+        );
+    }
+
+    // a valid RSA signature of the right digest, but with block type 02
+    #[test]
+    fn pkcs1v15_bad_padding() {
+        let signature = hex::<256>(PKCS1V15_BAD_PADDING);
+        assert_eq!(verify_pkcs1v15(&signature), Err(Error::InvalidSignature));
+    }
+
+    #[test]
+    fn pss() {
+        let signature = hex::<256>(PSS_SALT32);
+        assert_eq!(verify_pss(MESSAGE, Some(32), &signature), Ok(()));
+        assert_eq!(verify_pss(MESSAGE, None, &signature), Ok(()));
+        assert_eq!(
+            verify_pss(b"Be that word our sign of parting!", None, &signature),
+            Err(Error::InvalidSignature)
+        );
+
+        let mut tampered = signature;
+        tampered[0] ^= 1;
+        assert_eq!(
+            verify_pss(MESSAGE, None, &tampered),
+            Err(Error::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn pss_wrong_salt_length() {
+        let signature = hex::<256>(PSS_SALT32);
+        assert_eq!(
+            verify_pss(MESSAGE, Some(20), &signature),
+            Err(Error::InvalidSignature)
+        );
+        assert_eq!(
+            verify_pss(MESSAGE, Some(33), &signature),
+            Err(Error::InvalidSignature)
+        );
+        assert_eq!(
+            verify_pss(MESSAGE, Some(1000), &signature),
+            Err(Error::InvalidSignature)
+        );
+
+        let signature = hex::<256>(PSS_SALT0);
+        assert_eq!(verify_pss(MESSAGE, Some(0), &signature), Ok(()));
+        assert_eq!(verify_pss(MESSAGE, None, &signature), Ok(()));
+        assert_eq!(
+            verify_pss(MESSAGE, Some(32), &signature),
+            Err(Error::InvalidSignature)
+        );
+    }
+
+    // the PKCS #1 v1.5 encoding is no PSS encoding, and vice versa
+    #[test]
+    fn schemes_are_distinct() {
+        assert_eq!(
+            verify_pss(MESSAGE, None, &hex::<256>(PKCS1V15)),
+            Err(Error::InvalidSignature)
+        );
+        assert_eq!(
+            verify_pkcs1v15(&hex::<256>(PSS_SALT32)),
+            Err(Error::InvalidSignature)
+        );
+    }
+
+    const MODULUS: &str = "
+        b5174bfb8e4ee0608462e4f89c3fcd0113bd687e5d6961c0c7e0d35c5bb17edc
+        091b43360558e6c5368ab4fea0d045e2076b633d18efa50541e511faec9f26ce
+        4ac20c61073377342e51fbfa8873f94b1af41127809df207fcabc55d4632ce4e
+        96f8060977db1aade06d28c39bd71b8f2dd1c52f1a083784311a8a9944213519
+        695f67ad6919cacf03023ae1ce06e5f89231ec8c21c68b86d6d47a72b1f4da96
+        3e20992c3cd6654e7208105060f1d55c6bf03e5752bb74a395a5a3eaf37825d5
+        c479922a117b0e69023a61639cd40f91c1a283378092bc63ee40a457ebfd362d
+        88d81760c8db41234a11b9b3104b10484384f4a5878f99a9de1b872efb801573";
+    const PKCS1V15: &str = "
+        1855c9e266dc690ed314b35f5c1f5323b5173742d715b7b8c8c7d197b820d5a8
+        056f9101ec93b3dc4395b659fcac6df26ad059f492e483eae5d2a5649ca514e9
+        9204e5689372ad728f466ce6ea695124ff14c133e02e1def47b4abaac9a59b87
+        226cd9ef41d5275a85860b36382aea51e9a55994ef8d0068ffc7778a3e1bf54c
+        9bcbebf4ca11e219e49f7cfbe65a4c010373a4302199206d1303abd65ec9e84d
+        ac65acbbafbbceb9282992f5f491b9c306b05a8783d3c332ae81223f63dc8727
+        f0fc9da458a971d2800c3ba5a257d79f48e7088fb4a24ed87d7bee89f6d6088e
+        cf4987ccd20542402bace82fc7673117a2a2a97e8960dd0c6cb54e22966da5db";
+    const PKCS1V15_BAD_PADDING: &str = "
+        2be404cbd50e3d1d2cbd5a37b7c5bc7b2061191503f4c922aedb4c2bfc64b5a9
+        92179ce7032f9d20a19f04427eb40efb86dae775e64a8710b3a30696a448ae54
+        144a3710dcd58585a0af0bfc992301069e328a38b1c6df3b52949f90a0782ecf
+        657d6a3cef4512e1837654bae66d90360dae749c9514228c53d6c822e010ed1c
+        818b1c1afe34b028f3f32cdac72d68718b766f2c89585069ff8aff558e75fd4d
+        c3b8ed692f498121525d750cdfff1d06c990a76d9e857c5f5f9ed60943e8f55a
+        97f1a020da46d431675f5703441b80fa12c6958a4791a03511a50d882894fa64
+        11f742c62b38047dd02527af4afdfda23a056a482923d1712c16266659f481ab";
+    const PSS_SALT32: &str = "
+        a80932c7ac352428d677f9e77c642fb649cd6824808b1c9159097ed353a168fd
+        5d68d622d1641c3755a3c03b7d1b624a303dc05b32016caac3b4abbdbee3d4bf
+        504000c7165de30d919b37378d8fc44dfb6d8e7e7dd1bee89afc71a8a6630d9e
+        9128df6d5253feaf8776c4bd5f62af2738fd0336ed9e388f2a46beaac9ed8328
+        7c63369f7565d07bc769278212882b3325262c558fc368ea15a5452c3d0dd070
+        3d94b7f2a77577862d8299d12de9e52636951e7ec925cde4796480d4d2e4d19d
+        ae26e96f35891bc2b7a13bcb248f30cffd698e1deb0bc38838aea69d0257918a
+        013470ecd8f7ed0f5a5fe1a3c25acb3f2c687f7e1ec3cf509258c9b686c4c5f5";
+    const PSS_SALT0: &str = "
+        2f5c94d58de9cdf3bfd396c31b2cfda0e9f2f7d8dd483b77c79a658b2389f9ac
+        0419b3680c1f7a50748b5f9258480395177a04cadca9b5a3ab6609200c2f790d
+        1c8e8898027daae682a0490605d3f3a7e60ab6c8e2b28d3888d32e80fbcc1b91
+        e3d7dbedfbbe6d986d4e428ed8252b2d64e52e8d4f093e4e9824404b90926cc9
+        1c57617a0f7a2135538841499228a33cc90a1f668547c88b3997bcace909a345
+        1560ed43f765888c5e00f7d3fde1c18b8b27ce2df4f8bdba1830d4aaf796e4d9
+        1875df73f9b75ed81520b0824954ba9443a38ec3ef2010fa901f443a50fceb33
+        227d70582887842f81cae4e03c4922650ed9e70297d0c2ff4f71c767016c639e";
+}
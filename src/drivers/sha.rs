@@ -1,17 +1,17 @@
-use core::marker::PhantomData;
+use core::{convert::TryInto, marker::PhantomData};
 
 use crate::traits::aligned::{Aligned, A4};
-use block_buffer::{BlockBuffer, Eager};
+use block_buffer::{Block, BlockBuffer, Eager};
 use cipher::BlockSizeUser;
 
 use crate::{
-    peripherals::hashcrypt::Hashcrypt,
+    peripherals::hashcrypt::{Arbiter, ArbiterGuard, Hashcrypt},
     traits::{
         digest::generic_array::{
             typenum::{U20, U32, U64},
             GenericArray,
         },
-        digest::Update,
+        digest::{FixedOutput, FixedOutputReset, HashMarker, Output, OutputSizeUser, Update},
     },
     typestates::init_state::Enabled,
 };
@@ -33,10 +33,14 @@ mod sealed {
 
 use sealed::OutputSize;
 
+/// SHA-1 or SHA-256 on the HASHCRYPT peripheral, via the `digest` traits.
+///
+/// As the hasher borrows the peripheral, it cannot implement `Default` (nor `Clone`),
+/// so the blanket `Digest` implementation does not apply: generic code should bound
+/// on `Update + FixedOutput` (or `FixedOutputReset`) instead, or use `SharedSha`.
 pub struct Sha<'a, Size: OutputSize> {
-    buffer: Aligned<A4, BlockBuffer<BlockSize, Eager>>,
+    buffer: Buffer,
     inner: &'a mut Hashcrypt<Enabled>,
-    len: u64,
     size: PhantomData<Size>,
 }
 
@@ -46,9 +50,8 @@ pub type Sha256<'a> = Sha<'a, U32>;
 impl<'a, Size: OutputSize> Sha<'a, Size> {
     pub fn new(hashcrypt: &'a mut Hashcrypt<Enabled>) -> Self {
         let mut sha = Self {
-            buffer: Aligned(Default::default()),
+            buffer: Buffer::new(),
             inner: hashcrypt,
-            len: 0,
             size: PhantomData,
         };
         sha.reset();
@@ -60,8 +63,7 @@ impl<'a, Size: OutputSize> Sha<'a, Size> {
     }
 
     pub fn reset(&mut self) {
-        self.buffer = Aligned(Default::default());
-        self.len = 0;
+        self.buffer = Buffer::new();
 
         // SDK says:
         // /* NEW bit must be set before we switch from previous mode otherwise
@@ -97,18 +99,24 @@ impl<Size: OutputSize> BlockSizeUser for Sha<'_, Size> {
     type BlockSize = BlockSize;
 }
 
-// impl<Size: OutputSize> FixedOutputDirty for Sha<'_, Size> {
-//     type OutputSize = Size;
+impl<Size: OutputSize> OutputSizeUser for Sha<'_, Size> {
+    type OutputSize = Size;
+}
 
-//     fn finalize_into_dirty(&mut self, out: &mut GenericArray<u8, Self::OutputSize>) {
-//         self.finish();
-//         // cf `hashcrypt_get_data` ~line 315 of `fsl_hashcrypt.c`
-//         for i in 0..Size::to_usize() / 4 {
-//             out.as_mut_slice()[4 * i..4 * i + 4]
-//                 .copy_from_slice(&self.inner.raw.digest0[i].read().bits().to_be_bytes());
-//         }
-//     }
-// }
+impl<Size: OutputSize> HashMarker for Sha<'_, Size> {}
+
+impl<Size: OutputSize> FixedOutput for Sha<'_, Size> {
+    fn finalize_into(mut self, out: &mut Output<Self>) {
+        self.finalize_into_dirty(out);
+    }
+}
+
+impl<Size: OutputSize> FixedOutputReset for Sha<'_, Size> {
+    fn finalize_into_reset(&mut self, out: &mut Output<Self>) {
+        self.finalize_into_dirty(out);
+        self.reset();
+    }
+}
 
 impl<Size: OutputSize> Update for Sha<'_, Size> {
     fn update(&mut self, data: &[u8]) {
@@ -116,27 +124,181 @@ impl<Size: OutputSize> Update for Sha<'_, Size> {
     }
 }
 
-// the actual implementation
+/// A `Sha` that holds the peripheral of the shared `Arbiter` while it lives.
+///
+/// It implements `Default`, so it is a `digest::Digest`, for code that creates its own
+/// hashers (e.g. `hmac::Hmac`, signature crates):
+///
+/// ```ignore
+/// let arbiter = cortex_m::singleton!(: Arbiter = Arbiter::new(hashcrypt)).unwrap();
+/// arbiter.share();
+/// let digest = SharedSha256::digest(b"message");
+/// ```
+///
+/// # Panics
+///
+/// `Default::default()`, and so `Digest::new()`, panics if no arbiter is shared, or if
+/// another task holds the peripheral. Code that may run while the peripheral is in use,
+/// e.g. in an interrupt handler, must call `try_new` instead.
+pub struct SharedSha<Size: OutputSize> {
+    // borrows from the arbiter, while `_guard` locks it
+    sha: Sha<'static, Size>,
+    _guard: ArbiterGuard<'static>,
+}
 
-impl<Size: OutputSize> Sha<'_, Size> {
+pub type SharedSha1 = SharedSha<U20>;
+pub type SharedSha256 = SharedSha<U32>;
+
+impl<Size: OutputSize> SharedSha<Size> {
+    /// Takes the peripheral from the shared arbiter, unless another task holds it.
+    pub fn try_new() -> Option<Self> {
+        let mut guard = Arbiter::shared()?.try_lock()?;
+        // the peripheral stays locked for as long as `sha` can use it
+        let hashcrypt = unsafe { &mut *(&mut *guard as *mut Hashcrypt<Enabled>) };
+        Some(Self {
+            sha: Sha::new(hashcrypt),
+            _guard: guard,
+        })
+    }
+}
+
+impl<Size: OutputSize> Default for SharedSha<Size> {
+    /// Panics where `try_new` returns `None`.
+    fn default() -> Self {
+        Self::try_new().expect("shared HASHCRYPT unavailable")
+    }
+}
+
+impl<Size: OutputSize> BlockSizeUser for SharedSha<Size> {
+    type BlockSize = BlockSize;
+}
+
+impl<Size: OutputSize> OutputSizeUser for SharedSha<Size> {
+    type OutputSize = Size;
+}
+
+impl<Size: OutputSize> HashMarker for SharedSha<Size> {}
+
+impl<Size: OutputSize> FixedOutput for SharedSha<Size> {
+    fn finalize_into(mut self, out: &mut Output<Self>) {
+        self.sha.finalize_into_dirty(out);
+    }
+}
+
+impl<Size: OutputSize> FixedOutputReset for SharedSha<Size> {
+    fn finalize_into_reset(&mut self, out: &mut Output<Self>) {
+        self.sha.finalize_into_reset(out);
+    }
+}
+
+impl<Size: OutputSize> Update for SharedSha<Size> {
     fn update(&mut self, data: &[u8]) {
+        self.sha.update(data);
+    }
+}
+
+impl<Size: OutputSize> digest::Reset for SharedSha<Size> {
+    fn reset(&mut self) {
+        self.sha.reset();
+    }
+}
+
+// `MEMCTRL.COUNT` is 11 bits
+const MAX_TRANSFER: usize = 0x7ff;
+
+// Whole blocks of input, as the peripheral takes them.
+enum Input<'b> {
+    // word-aligned blocks, at most `MAX_TRANSFER`, read by the peripheral itself
+    Transfer(&'b [Block<BlockSize>]),
+    // a misaligned block, written word by word
+    Words(&'b Block<BlockSize>),
+}
+
+// The buffering and padding of `Sha`, passing whole blocks on as `Input`.
+struct Buffer {
+    blocks: Aligned<A4, BlockBuffer<BlockSize, Eager>>,
+    // in bits
+    len: u64,
+}
+
+impl Buffer {
+    fn new() -> Self {
+        Self {
+            blocks: Aligned(Default::default()),
+            len: 0,
+        }
+    }
+
+    // `BlockBuffer` passes the whole blocks of the input in place, only a block completing
+    // buffered bytes is its own. If they are word-aligned, the peripheral reads them directly
+    // (also from flash), in as few transfers as possible. Otherwise the input is skewed
+    // against the hashed stream, by the same amount for the whole update, so no buffering
+    // realigns it: the words are then written to `INDATA` as they are read.
+    fn update(&mut self, data: &[u8], mut process: impl FnMut(Input<'_>)) {
         // Assumes that input.len() can be converted to u64 without overflow
         self.len += (data.len() as u64) << 3;
+        self.blocks.digest_blocks(data, |blocks| {
+            if blocks.as_ptr() as usize & 0x3 == 0 {
+                for blocks in blocks.chunks(MAX_TRANSFER) {
+                    process(Input::Transfer(blocks));
+                }
+            } else {
+                for block in blocks {
+                    process(Input::Words(block));
+                }
+            }
+        });
+    }
+
+    fn finish(&mut self, mut process: impl FnMut(Input<'_>)) {
+        let l = self.len;
+        self.blocks.len64_padding_be(l, |block| {
+            // input must be word-aligned
+            let block: Aligned<A4, Block<BlockSize>> = Aligned(*block);
+            process(Input::Transfer(core::slice::from_ref(&block)));
+        });
+    }
+}
+
+// the actual implementation
+
+impl<Size: OutputSize> Sha<'_, Size> {
+    fn update(&mut self, data: &[u8]) {
         // need to convince compiler we're using buffer and peripheral
         // independently, and not doing a double &mut
         let peripheral = &mut self.inner;
-        self.buffer.digest_blocks(data, |data| {
-            for data in data {
-                Self::process_block(peripheral, data)
+        self.buffer
+            .update(data, |input| Self::process(peripheral, input));
+    }
+
+    fn process(peripheral: &mut Hashcrypt<Enabled>, input: Input<'_>) {
+        match input {
+            Input::Transfer(blocks) => Self::transfer(peripheral, blocks),
+            Input::Words(block) => Self::write_block(peripheral, block),
+        }
+    }
+
+    // cf `hashcrypt_sha_ldm_stm_16_words` in `fsl_hashcrypt.c`: `INDATA` and its
+    // seven `ALIAS`es take eight words at a time, in little-endian form
+    fn write_block(peripheral: &mut Hashcrypt<Enabled>, block: &Block<BlockSize>) {
+        while peripheral.raw.status.read().waiting().is_not_waiting() {
+            continue;
+        }
+        for half in block.chunks(32) {
+            let mut words = half
+                .chunks(4)
+                .map(|word| u32::from_le_bytes(word.try_into().unwrap()));
+            let mut next = || words.next().unwrap();
+            peripheral.raw.indata.write(|w| unsafe { w.bits(next()) });
+            for alias in peripheral.raw.alias.iter() {
+                alias.write(|w| unsafe { w.bits(next()) });
             }
-        });
+        }
     }
 
     // relevant code is ~line 800 in fsl_hashcrypt.c
-    fn process_block(peripheral: &mut Hashcrypt<Enabled>, input: &GenericArray<u8, BlockSize>) {
-        // input must be word-aligned
-        let input: Aligned<A4, GenericArray<u8, BlockSize>> = Aligned(input.clone());
-        let addr: u32 = &(input.as_ref() as &[u8])[0] as *const _ as _;
+    fn transfer(peripheral: &mut Hashcrypt<Enabled>, blocks: &[Block<BlockSize>]) {
+        let addr = blocks.as_ptr() as u32;
         assert_eq!(addr & 0x3, 0);
         while peripheral.raw.status.read().waiting().is_not_waiting() {
             continue;
@@ -145,14 +307,26 @@ impl<Size: OutputSize> Sha<'_, Size> {
         peripheral
             .raw
             .memctrl
-            .write(|w| unsafe { w.master().enabled().count().bits(1) });
+            .write(|w| unsafe { w.master().enabled().count().bits(blocks.len() as u16) });
+        // `COUNT` decrements with each block copied, `blocks` must stay put until then
+        while peripheral.raw.memctrl.read().count().bits() != 0 {
+            continue;
+        }
+    }
+
+    /// Pads, finishes and reads out the digest, leaving the peripheral in a "dirty" state.
+    pub(crate) fn finalize_into_dirty(&mut self, out: &mut GenericArray<u8, Size>) {
+        self.finish();
+        // cf `hashcrypt_get_data` ~line 315 of `fsl_hashcrypt.c`
+        for i in 0..Size::to_usize() / 4 {
+            out.as_mut_slice()[4 * i..4 * i + 4]
+                .copy_from_slice(&self.inner.raw.digest0[i].read().bits().to_be_bytes());
+        }
     }
 
     fn finish(&mut self) {
         let peripheral = &mut self.inner;
-        let l = self.len;
-        self.buffer
-            .len64_padding_be(l, |block| Self::process_block(peripheral, block));
+        self.buffer.finish(|input| Self::process(peripheral, input));
         while peripheral.raw.status.read().digest().is_not_ready() {
             continue;
         }
@@ -164,3 +338,165 @@ impl<Size: OutputSize> digest::Reset for Sha<'_, Size> {
         self.reset();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::Digest;
+
+    // Model of the peripheral: the SHA-256 compression over the blocks it is given.
+    struct Model {
+        state: [u32; 8],
+        transfers: usize,
+        largest_transfer: usize,
+        words: usize,
+    }
+
+    impl Model {
+        fn new() -> Self {
+            Self {
+                // FIPS 180-4, 5.3.3
+                state: [
+                    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c,
+                    0x1f83d9ab, 0x5be0cd19,
+                ],
+                transfers: 0,
+                largest_transfer: 0,
+                words: 0,
+            }
+        }
+
+        fn process(&mut self, input: Input<'_>) {
+            match input {
+                Input::Transfer(blocks) => {
+                    assert_eq!(blocks.as_ptr() as usize & 0x3, 0);
+                    assert!(blocks.len() <= MAX_TRANSFER);
+                    sha2::compress256(&mut self.state, blocks);
+                    self.transfers += 1;
+                    self.largest_transfer = self.largest_transfer.max(blocks.len());
+                }
+                Input::Words(block) => {
+                    sha2::compress256(&mut self.state, core::slice::from_ref(block));
+                    self.words += 1;
+                }
+            }
+        }
+
+        fn digest(&self) -> [u8; 32] {
+            let mut digest = [0u8; 32];
+            for (bytes, word) in digest.chunks_mut(4).zip(self.state.iter()) {
+                bytes.copy_from_slice(&word.to_be_bytes());
+            }
+            digest
+        }
+    }
+
+    // Hashes `data` in updates ending at `splits`, returns the model afterwards.
+    fn hash(data: &[u8], splits: &[usize]) -> Model {
+        let mut model = Model::new();
+        let mut buffer = Buffer::new();
+        let mut start = 0;
+        for end in splits.iter().copied().chain(Some(data.len())) {
+            buffer.update(&data[start..end], |input| model.process(input));
+            start = end;
+        }
+        buffer.finish(|input| model.process(input));
+        assert_eq!(
+            model.digest()[..],
+            sha2::Sha256::digest(data)[..],
+            "{} bytes, split at {:?}",
+            data.len(),
+            splits
+        );
+        model
+    }
+
+    fn pattern<const N: usize>() -> Aligned<A4, [u8; N]> {
+        let mut data = [0u8; N];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = (i * 31 + 7) as u8;
+        }
+        Aligned(data)
+    }
+
+    #[test]
+    fn padding() {
+        let data = pattern::<200>();
+        // the length goes into the last block if there is room (55 bytes),
+        // otherwise a block of padding follows
+        for len in [0, 1, 55, 56, 63, 64, 65, 119, 120, 128, 200] {
+            hash(&data[..len], &[]);
+        }
+    }
+
+    #[test]
+    fn aligned_updates() {
+        let data = pattern::<640>();
+        // whole blocks in place, as one transfer per update
+        let model = hash(&data[..], &[128, 512]);
+        assert_eq!(model.words, 0);
+        // three updates, then the padding
+        assert_eq!(model.transfers, 4);
+        assert_eq!(model.largest_transfer, 6);
+
+        // a block completing buffered bytes is transferred from the buffer
+        let model = hash(&data[..], &[4, 100]);
+        assert_eq!(model.words, 0);
+    }
+
+    #[test]
+    fn unaligned_updates() {
+        let data = pattern::<640>();
+        // skewed by one byte: all whole blocks are written word by word
+        let model = hash(&data[1..], &[]);
+        assert_eq!(model.words, 9);
+        // 63 bytes left over: the padding takes two blocks
+        assert_eq!(model.transfers, 2);
+
+        for splits in [[1, 2], [3, 70], [63, 65], [65, 300], [130, 131]] {
+            hash(&data[..], &splits);
+            hash(&data[1..], &splits);
+        }
+    }
+
+    #[test]
+    fn transfers_are_chunked() {
+        const BLOCKS: usize = MAX_TRANSFER + 2;
+        let data = pattern::<{ 64 * BLOCKS }>();
+        let model = hash(&data[..], &[]);
+        // MAX_TRANSFER blocks, the other two, and the padding
+        assert_eq!(model.transfers, 3);
+        assert_eq!(model.largest_transfer, MAX_TRANSFER);
+        assert_eq!(model.words, 0);
+    }
+
+    #[test]
+    fn sha1_padding() {
+        let data = pattern::<130>();
+        for len in [0, 3, 55, 56, 64, 130] {
+            let mut state = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+            let mut buffer = Buffer::new();
+            let mut compress = |input: Input<'_>| {
+                let blocks = match input {
+                    Input::Transfer(blocks) => blocks,
+                    Input::Words(block) => core::slice::from_ref(block),
+                };
+                for block in blocks {
+                    sha1::compress(&mut state, core::slice::from_ref(block));
+                }
+            };
+            buffer.update(&data[..len], &mut compress);
+            buffer.finish(&mut compress);
+            let mut digest = [0u8; 20];
+            for (bytes, word) in digest.chunks_mut(4).zip(state.iter()) {
+                bytes.copy_from_slice(&word.to_be_bytes());
+            }
+            assert_eq!(
+                digest[..],
+                sha1::Sha1::digest(&data[..len])[..],
+                "{} bytes",
+                len
+            );
+        }
+    }
+}
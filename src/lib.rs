@@ -57,6 +57,8 @@ pub use peripherals::{
 };
 
 pub mod drivers;
+#[cfg(test)]
+mod testing;
 pub use drivers::{ClockRequirements, FlashGordon, I2cMaster, Pin, Pins, SpiMaster, UsbBus};
 
 pub fn new() -> Peripherals {
@@ -21,6 +21,8 @@ impl<State> Casper<State> {
     }
 }
 
+/// Operation codes (`CTRL1.MODE`), see `drivers::casper` for their use
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Operations {
     /// Walking 1 or more of J loop, doing r=a*b using 64x64=128
     Mul6464NoSum = 0x01,
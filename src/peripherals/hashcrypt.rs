@@ -1,15 +1,24 @@
+use core::{
+    cell::{Cell, UnsafeCell},
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use cortex_m::interrupt::{self, Mutex};
+
 use crate::traits::digest::generic_array::GenericArray;
 
 use crate::{
-    drivers::{aes, Aes, AesKey, Sha1, Sha256},
-    peripherals::syscon,
+    drivers::{aes, Aes, AesKey, HkdfSha256, HmacSha256, Sha1, Sha256},
+    peripherals::{puf::AesKeyLoaded, syscon},
     raw,
     typestates::init_state,
 };
 
 crate::wrap_stateful_peripheral!(Hashcrypt, HASHCRYPT);
 
-impl<State> core::ops::Deref for Hashcrypt<State> {
+impl<State> Deref for Hashcrypt<State> {
     type Target = raw::hashcrypt::RegisterBlock;
     fn deref(&self) -> &Self::Target {
         &self.raw
@@ -48,6 +57,16 @@ impl Hashcrypt<init_state::Enabled> {
         Sha256::from(self)
     }
 
+    /// HMAC-SHA256, as in RustCrypto `Mac` trait
+    pub fn hmac_sha256<'a>(&'a mut self, key: &[u8]) -> HmacSha256<'a> {
+        HmacSha256::new(self, key)
+    }
+
+    /// HKDF-SHA256, after the extract step
+    pub fn hkdf_sha256<'a>(&'a mut self, salt: Option<&[u8]>, ikm: &[u8]) -> HkdfSha256<'a> {
+        HkdfSha256::new(self, salt, ikm)
+    }
+
     /// AES-128 "ECB", as in RustCrypto `block-cipher` trait
     pub fn aes128<'a>(&'a mut self, key: &[u8; 16]) -> aes::Aes128<'a> {
         let key = AesKey::User(GenericArray::clone_from_slice(key));
@@ -68,9 +87,95 @@ impl Hashcrypt<init_state::Enabled> {
 
     /// AES "ECB" with PUF key, for use as in RustCrypto `block-cipher` trait
     ///
-    /// DOES NOT PROPERLY CHECK IF PUF AES KEY IS SETUP YET!
-    /// TODO: have user pass in some token signaling PUF AES key is setup
-    pub fn puf_aes<'a>(&'a mut self) -> aes::Aes256<'a> {
-        Aes::new(self, AesKey::Puf, aes::Mode::Encrypt)
+    /// The key must have been loaded with `Puf::load_aes_key`.
+    pub fn puf_aes<'a>(&'a mut self, key: &'a AesKeyLoaded<'_>) -> aes::Aes256<'a> {
+        Aes::new(self, AesKey::puf(key), aes::Mode::Encrypt)
+    }
+}
+
+/// Shares the enabled HASHCRYPT between tasks of different priority.
+///
+/// A task takes the peripheral with `try_lock`, which fails while another task holds it,
+/// instead of waiting for a task it may have preempted. A hash cannot be suspended
+/// (the HASHCRYPT registers to reload a running hash are undocumented), so a task
+/// holds the peripheral from the start of a hash to its end:
+///
+/// ```ignore
+/// // low priority
+/// if let Some(mut hashcrypt) = arbiter.try_lock() {
+///     let digest = hashcrypt.sha256().chain(message).finalize_fixed();
+/// }
+///
+/// // high priority
+/// let mut hashcrypt = arbiter.try_lock().unwrap();
+/// hashcrypt.aes128(&key).encrypt_block(&mut block);
+/// ```
+pub struct Arbiter {
+    hashcrypt: UnsafeCell<Hashcrypt<init_state::Enabled>>,
+    locked: Mutex<Cell<bool>>,
+}
+
+// the `UnsafeCell` is only accessed by the holder of the lock
+unsafe impl Sync for Arbiter {}
+
+impl Arbiter {
+    pub fn new(hashcrypt: Hashcrypt<init_state::Enabled>) -> Self {
+        Self {
+            hashcrypt: UnsafeCell::new(hashcrypt),
+            locked: Mutex::new(Cell::new(false)),
+        }
+    }
+
+    /// Takes the peripheral until the guard is dropped, unless another task holds it.
+    pub fn try_lock(&self) -> Option<ArbiterGuard<'_>> {
+        interrupt::free(|cs| {
+            let locked = self.locked.borrow(cs);
+            if locked.get() {
+                None
+            } else {
+                locked.set(true);
+                Some(ArbiterGuard { arbiter: self })
+            }
+        })
+    }
+
+    /// Makes this the arbiter that `SharedSha1`/`SharedSha256` take the peripheral from.
+    pub fn share(&'static self) {
+        SHARED.store(self as *const Self as *mut Self, Ordering::Release);
+    }
+
+    pub(crate) fn shared() -> Option<&'static Self> {
+        unsafe { SHARED.load(Ordering::Acquire).as_ref() }
+    }
+
+    pub fn into_inner(self) -> Hashcrypt<init_state::Enabled> {
+        self.hashcrypt.into_inner()
+    }
+}
+
+// set by `Arbiter::share`, only ever to a `&'static Arbiter`
+static SHARED: AtomicPtr<Arbiter> = AtomicPtr::new(ptr::null_mut());
+
+/// Access to the peripheral held by an `Arbiter`, released on drop.
+pub struct ArbiterGuard<'a> {
+    arbiter: &'a Arbiter,
+}
+
+impl Deref for ArbiterGuard<'_> {
+    type Target = Hashcrypt<init_state::Enabled>;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.arbiter.hashcrypt.get() }
+    }
+}
+
+impl DerefMut for ArbiterGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.arbiter.hashcrypt.get() }
+    }
+}
+
+impl Drop for ArbiterGuard<'_> {
+    fn drop(&mut self) {
+        interrupt::free(|cs| self.arbiter.locked.borrow(cs).set(false));
     }
 }
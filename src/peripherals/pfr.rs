@@ -1,6 +1,58 @@
+use core::convert::TryInto;
 use core::result::Result;
 // use cortex_m_semihosting::{heprint,heprintln};
-use crate::{drivers::clocks::Clocks, typestates::init_state};
+use crate::{
+    drivers::clocks::Clocks,
+    peripherals::{
+        hashcrypt::Hashcrypt,
+        pfr::debug::{Access, DebugPolicy},
+        prince::{self, Region, RegionConfig, Subregions},
+    },
+    traits::digest::{generic_array::GenericArray, Update},
+    typestates::init_state,
+};
+
+pub mod debug;
+pub mod provision;
+pub mod rollback;
+pub mod rotkh;
+
+/// PFR error
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// A ROM API call returned this status code.
+    Rom(u32),
+    /// The CFPA version cannot be incremented any further.
+    VersionOverflow,
+    /// A monotonic counter (firmware versions, image key revocation) would decrease.
+    CounterDecreased,
+    /// The current CFPA page carries a SHA-256 digest, but no `Hashcrypt` was passed to update it.
+    DigestRequired,
+    /// The page read back after writing does not match what was written.
+    VerifyFailed,
+    /// The CMPA page was rejected before writing.
+    UnsafeCmpa(CmpaIssue),
+}
+
+/// Reasons for `Cmpa::validate` to reject a page.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CmpaIssue {
+    /// Secure boot or sealing needs a RoTKH, but it is blank.
+    RotkhMissing,
+    /// A `DCFG_CC_SOCU_*` word does not match its inverse value.
+    InvalidDcfg,
+    /// A `DFLT` bit is set without the corresponding `PIN` bit.
+    DcfgMismatch,
+    /// ISP is disabled and neither debug nor debug authentication can be used,
+    /// leaving no way to recover the device.
+    NoRecoveryPath,
+}
+
+impl From<u32> for Error {
+    fn from(status: u32) -> Self {
+        Error::Rom(status)
+    }
+}
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum KeyType {
@@ -12,6 +64,276 @@ pub enum KeyType {
     PrinceRegion2 = 0x05,
 }
 
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Several CMPA settings are two-bit fields where `00` means "off"
+/// and any other value means "on". We always write `11`.
+fn two_bit_flag(word: u32, shift: u32) -> bool {
+    (word >> shift) & 0b11 != 0
+}
+
+fn set_two_bit_flag(word: &mut u32, shift: u32, value: bool) {
+    *word &= !(0b11 << shift);
+    if value {
+        *word |= 0b11 << shift;
+    }
+}
+
+/// Default ISP mode, `BOOT_CFG[6:4]`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IspMode {
+    Auto,
+    UsbHidMsc,
+    Uart,
+    SpiSlave,
+    I2cSlave,
+    /// Do not fall through to ISP if there is no valid image.
+    Disabled,
+    Reserved(u8),
+}
+
+impl From<u8> for IspMode {
+    fn from(bits: u8) -> Self {
+        match bits & 0x7 {
+            0 => IspMode::Auto,
+            1 => IspMode::UsbHidMsc,
+            2 => IspMode::Uart,
+            3 => IspMode::SpiSlave,
+            4 => IspMode::I2cSlave,
+            7 => IspMode::Disabled,
+            bits => IspMode::Reserved(bits),
+        }
+    }
+}
+
+impl From<IspMode> for u8 {
+    fn from(mode: IspMode) -> u8 {
+        match mode {
+            IspMode::Auto => 0,
+            IspMode::UsbHidMsc => 1,
+            IspMode::Uart => 2,
+            IspMode::SpiSlave => 3,
+            IspMode::I2cSlave => 4,
+            IspMode::Disabled => 7,
+            IspMode::Reserved(bits) => bits & 0x7,
+        }
+    }
+}
+
+/// Core clock used by the boot ROM, `BOOT_CFG[8:7]`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BootSpeed {
+    /// Defined by `NMPA.SYSTEM_SPEED_CODE`
+    Nmpa,
+    Fro96Mhz,
+    Fro48Mhz,
+    Reserved,
+}
+
+impl From<u8> for BootSpeed {
+    fn from(bits: u8) -> Self {
+        match bits & 0x3 {
+            0 => BootSpeed::Nmpa,
+            1 => BootSpeed::Fro96Mhz,
+            2 => BootSpeed::Fro48Mhz,
+            _ => BootSpeed::Reserved,
+        }
+    }
+}
+
+impl From<BootSpeed> for u8 {
+    fn from(speed: BootSpeed) -> u8 {
+        match speed {
+            BootSpeed::Nmpa => 0,
+            BootSpeed::Fro96Mhz => 1,
+            BootSpeed::Fro48Mhz => 2,
+            BootSpeed::Reserved => 3,
+        }
+    }
+}
+
+/// Decoded `CMPA.BOOT_CFG`. Bits without a typed accessor are preserved.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BootCfg(pub u32);
+
+impl BootCfg {
+    pub fn isp_mode(&self) -> IspMode {
+        IspMode::from(((self.0 >> 4) & 0x7) as u8)
+    }
+
+    pub fn set_isp_mode(&mut self, mode: IspMode) -> &mut Self {
+        self.0 = (self.0 & !(0x7 << 4)) | ((u8::from(mode) as u32) << 4);
+        self
+    }
+
+    pub fn boot_speed(&self) -> BootSpeed {
+        BootSpeed::from(((self.0 >> 7) & 0x3) as u8)
+    }
+
+    pub fn set_boot_speed(&mut self, speed: BootSpeed) -> &mut Self {
+        self.0 = (self.0 & !(0x3 << 7)) | ((u8::from(speed) as u32) << 7);
+        self
+    }
+
+    /// GPIO `(port, pin)` toggled by the ROM to signal the boot failure reason.
+    pub fn boot_failure_pin(&self) -> (u8, u8) {
+        let bits = (self.0 >> 24) as u8;
+        (bits & 0x7, bits >> 3)
+    }
+
+    pub fn set_boot_failure_pin(&mut self, port: u8, pin: u8) -> &mut Self {
+        assert!(port < 8 && pin < 32);
+        let bits = (port | (pin << 3)) as u32;
+        self.0 = (self.0 & 0x00ff_ffff) | (bits << 24);
+        self
+    }
+}
+
+/// TrustZone-M setup applied by the ROM, `SECURE_BOOT_CFG[9:8]`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TzmImageType {
+    /// TrustZone-M mode is taken from the image header.
+    FromImageHeader,
+    /// TrustZone-M disabled, boots to non-secure.
+    Disabled,
+    /// TrustZone-M enabled, boots to secure.
+    Enabled,
+    /// TrustZone-M preset from the image header.
+    Preset,
+}
+
+/// Decoded `CMPA.SECURE_BOOT_CFG`. Bits without a typed accessor are preserved.
+///
+/// All boolean settings are two-bit fields in hardware, `00` meaning "off".
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SecureBootCfg(pub u32);
+
+impl SecureBootCfg {
+    /// Only accept RSA-4096 keys (otherwise RSA-2048 and bigger).
+    pub fn rsa4096_only(&self) -> bool {
+        two_bit_flag(self.0, 0)
+    }
+
+    pub fn set_rsa4096_only(&mut self, value: bool) -> &mut Self {
+        set_two_bit_flag(&mut self.0, 0, value);
+        self
+    }
+
+    /// Include the NXP area in the DICE computation.
+    pub fn dice_include_nxp_area(&self) -> bool {
+        two_bit_flag(self.0, 2)
+    }
+
+    pub fn set_dice_include_nxp_area(&mut self, value: bool) -> &mut Self {
+        set_two_bit_flag(&mut self.0, 2, value);
+        self
+    }
+
+    /// Include the customer factory area (including keys) in the DICE computation.
+    pub fn dice_include_customer_area(&self) -> bool {
+        two_bit_flag(self.0, 4)
+    }
+
+    pub fn set_dice_include_customer_area(&mut self, value: bool) -> &mut Self {
+        set_two_bit_flag(&mut self.0, 4, value);
+        self
+    }
+
+    pub fn skip_dice(&self) -> bool {
+        two_bit_flag(self.0, 6)
+    }
+
+    pub fn set_skip_dice(&mut self, value: bool) -> &mut Self {
+        set_two_bit_flag(&mut self.0, 6, value);
+        self
+    }
+
+    pub fn tzm_image_type(&self) -> TzmImageType {
+        match (self.0 >> 8) & 0x3 {
+            0 => TzmImageType::FromImageHeader,
+            1 => TzmImageType::Disabled,
+            2 => TzmImageType::Enabled,
+            _ => TzmImageType::Preset,
+        }
+    }
+
+    pub fn set_tzm_image_type(&mut self, tzm: TzmImageType) -> &mut Self {
+        let bits = match tzm {
+            TzmImageType::FromImageHeader => 0,
+            TzmImageType::Disabled => 1,
+            TzmImageType::Enabled => 2,
+            TzmImageType::Preset => 3,
+        };
+        self.0 = (self.0 & !(0x3 << 8)) | (bits << 8);
+        self
+    }
+
+    /// Block PUF key code generation.
+    pub fn block_set_key(&self) -> bool {
+        two_bit_flag(self.0, 10)
+    }
+
+    pub fn set_block_set_key(&mut self, value: bool) -> &mut Self {
+        set_two_bit_flag(&mut self.0, 10, value);
+        self
+    }
+
+    /// Block further PUF enrollment.
+    pub fn block_enroll(&self) -> bool {
+        two_bit_flag(self.0, 12)
+    }
+
+    pub fn set_block_enroll(&mut self, value: bool) -> &mut Self {
+        set_two_bit_flag(&mut self.0, 12, value);
+        self
+    }
+
+    /// Include the security epoch in the DICE computation.
+    pub fn dice_include_security_epoch(&self) -> bool {
+        two_bit_flag(self.0, 14)
+    }
+
+    pub fn set_dice_include_security_epoch(&mut self, value: bool) -> &mut Self {
+        set_two_bit_flag(&mut self.0, 14, value);
+        self
+    }
+
+    /// Only boot signed images.
+    pub fn secure_boot_enabled(&self) -> bool {
+        two_bit_flag(self.0, 30)
+    }
+
+    pub fn set_secure_boot_enabled(&mut self, value: bool) -> &mut Self {
+        set_two_bit_flag(&mut self.0, 30, value);
+        self
+    }
+}
+
+/// USB identifiers used by the ROM bootloader, `CMPA.USB_ID`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UsbId {
+    /// Not programmed, the ROM uses NXP's VID/PID.
+    NxpDefault,
+    Custom {
+        vid: u16,
+        pid: u16,
+    },
+}
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct IvCodePrinceRegion {
@@ -19,6 +341,38 @@ pub struct IvCodePrinceRegion {
     pub iv: [u8; 52],
 }
 
+impl IvCodePrinceRegion {
+    pub const SIZE: usize = 56;
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        IvCodePrinceRegion {
+            keycode_header: read_u32(bytes, 0),
+            iv: bytes[4..].try_into().unwrap(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        write_u32(&mut bytes, 0, self.keycode_header);
+        bytes[4..].copy_from_slice(&self.iv);
+        bytes
+    }
+}
+
+/// Confirmation that the CMPA page is to be sealed.
+///
+/// Once sealed, the CMPA page can never be changed again.
+pub struct Seal(());
+
+impl Seal {
+    pub fn permanently_seal_device() -> Self {
+        Seal(())
+    }
+}
+
+/// Value of `ENABLE_FA_MODE` that makes the ROM enter fault analysis mode.
+pub const FA_MODE_MAGIC: u32 = 0xC33C_A55A;
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct Cfpa {
@@ -49,6 +403,97 @@ pub struct Cfpa {
 }
 
 impl Cfpa {
+    /// Parses a raw CFPA page (little-endian, layout as in UM11126).
+    pub fn from_bytes(bytes: &[u8; 512]) -> Self {
+        let iv_code = |i: usize| {
+            let offset = 0x30 + i * IvCodePrinceRegion::SIZE;
+            IvCodePrinceRegion::from_bytes(
+                bytes[offset..offset + IvCodePrinceRegion::SIZE]
+                    .try_into()
+                    .unwrap(),
+            )
+        };
+        Cfpa {
+            header: read_u32(bytes, 0x00),
+            version: read_u32(bytes, 0x04),
+            secure_fw_version: read_u32(bytes, 0x08),
+            ns_fw_version: read_u32(bytes, 0x0c),
+            image_key_revoke: read_u32(bytes, 0x10),
+            reserved0: bytes[0x14..0x18].try_into().unwrap(),
+            rotkh_revoke: read_u32(bytes, 0x18),
+            vendor_usage: read_u32(bytes, 0x1c),
+            dcfg_ns_pin: read_u32(bytes, 0x20),
+            dcfg_ns_dflt: read_u32(bytes, 0x24),
+            enable_fa_mode: read_u32(bytes, 0x28),
+            reserved1: bytes[0x2c..0x30].try_into().unwrap(),
+            iv_code_prince_region: [iv_code(0), iv_code(1), iv_code(2)],
+            reserved2: bytes[0xd8..0x100].try_into().unwrap(),
+            customer_data: bytes[0x100..0x1e0].try_into().unwrap(),
+            sha256: bytes[0x1e0..0x200].try_into().unwrap(),
+        }
+    }
+
+    /// Serializes to a raw CFPA page; inverse of `from_bytes`.
+    pub fn to_bytes(&self) -> [u8; 512] {
+        let mut bytes = [0u8; 512];
+        write_u32(&mut bytes, 0x00, self.header);
+        write_u32(&mut bytes, 0x04, self.version);
+        write_u32(&mut bytes, 0x08, self.secure_fw_version);
+        write_u32(&mut bytes, 0x0c, self.ns_fw_version);
+        write_u32(&mut bytes, 0x10, self.image_key_revoke);
+        bytes[0x14..0x18].copy_from_slice(&self.reserved0);
+        write_u32(&mut bytes, 0x18, self.rotkh_revoke);
+        write_u32(&mut bytes, 0x1c, self.vendor_usage);
+        write_u32(&mut bytes, 0x20, self.dcfg_ns_pin);
+        write_u32(&mut bytes, 0x24, self.dcfg_ns_dflt);
+        write_u32(&mut bytes, 0x28, self.enable_fa_mode);
+        bytes[0x2c..0x30].copy_from_slice(&self.reserved1);
+        for (i, iv_code) in self.iv_code_prince_region.iter().enumerate() {
+            let offset = 0x30 + i * IvCodePrinceRegion::SIZE;
+            bytes[offset..offset + IvCodePrinceRegion::SIZE].copy_from_slice(&iv_code.to_bytes());
+        }
+        bytes[0xd8..0x100].copy_from_slice(&self.reserved2);
+        bytes[0x100..0x1e0].copy_from_slice(&self.customer_data);
+        bytes[0x1e0..0x200].copy_from_slice(&self.sha256);
+        bytes
+    }
+
+    /// Computes the SHA-256 over the page (excluding the digest itself) and stores it.
+    pub fn fill_sha256(&mut self, hashcrypt: &mut Hashcrypt<init_state::Enabled>) {
+        let bytes = self.to_bytes();
+        let mut sha = hashcrypt.sha256();
+        sha.update(&bytes[..0x1e0]);
+        let mut digest = GenericArray::default();
+        sha.finalize_into_dirty(&mut digest);
+        self.sha256.copy_from_slice(&digest);
+    }
+
+    /// Raw `VENDOR_USAGE` word (lower half-word, upper half-word its inverse).
+    pub fn vendor_usage(&self) -> u32 {
+        self.vendor_usage
+    }
+
+    pub fn set_vendor_usage(&mut self, vendor_usage: u32) {
+        self.vendor_usage = vendor_usage;
+    }
+
+    /// Raw `ENABLE_FA_MODE` word, see `FA_MODE_MAGIC`.
+    pub fn enable_fa_mode(&self) -> u32 {
+        self.enable_fa_mode
+    }
+
+    pub fn set_enable_fa_mode(&mut self, enable_fa_mode: u32) {
+        self.enable_fa_mode = enable_fa_mode;
+    }
+
+    pub fn sha256(&self) -> &[u8; 32] {
+        &self.sha256
+    }
+
+    pub fn set_sha256(&mut self, sha256: &[u8; 32]) {
+        self.sha256 = *sha256;
+    }
+
     /// Check if everything has been done to set up a particular HW key.
     pub fn key_provisioned(&self, key_type: KeyType) -> bool {
         match key_type {
@@ -91,15 +536,233 @@ pub struct Cmpa {
     sha256: [u8; 32],
 }
 
-// This compile time guarantees that Cmpa and Cfpa are 512 bytes.
-#[allow(unreachable_code)]
-fn _compile_time_assert() {
-    unsafe {
-        core::mem::transmute::<Cmpa, [u8; 512]>(panic!());
-        core::mem::transmute::<Cfpa, [u8; 512]>(panic!());
+impl Cmpa {
+    /// Parses a raw CMPA page (little-endian, layout as in UM11126).
+    pub fn from_bytes(bytes: &[u8; 512]) -> Self {
+        Cmpa {
+            boot_cfg: read_u32(bytes, 0x00),
+            spi_flash_cfg: read_u32(bytes, 0x04),
+            usb_vid: read_u16(bytes, 0x08),
+            usb_pid: read_u16(bytes, 0x0a),
+            sdio_cfg: read_u32(bytes, 0x0c),
+            dcfg_pin: read_u32(bytes, 0x10),
+            dcfg_dflt: read_u32(bytes, 0x14),
+            dap_vendor_usage: read_u32(bytes, 0x18),
+            secure_boot_cfg: read_u32(bytes, 0x1c),
+            prince_base_addr: read_u32(bytes, 0x20),
+            prince_sr: [
+                read_u32(bytes, 0x24),
+                read_u32(bytes, 0x28),
+                read_u32(bytes, 0x2c),
+            ],
+            reserved0: bytes[0x30..0x50].try_into().unwrap(),
+            rotkh: bytes[0x50..0x70].try_into().unwrap(),
+            reserved1: bytes[0x70..0x100].try_into().unwrap(),
+            customer_data: bytes[0x100..0x1e0].try_into().unwrap(),
+            sha256: bytes[0x1e0..0x200].try_into().unwrap(),
+        }
+    }
+
+    /// Serializes to a raw CMPA page; inverse of `from_bytes`.
+    pub fn to_bytes(&self) -> [u8; 512] {
+        let mut bytes = [0u8; 512];
+        write_u32(&mut bytes, 0x00, self.boot_cfg);
+        write_u32(&mut bytes, 0x04, self.spi_flash_cfg);
+        write_u16(&mut bytes, 0x08, self.usb_vid);
+        write_u16(&mut bytes, 0x0a, self.usb_pid);
+        write_u32(&mut bytes, 0x0c, self.sdio_cfg);
+        write_u32(&mut bytes, 0x10, self.dcfg_pin);
+        write_u32(&mut bytes, 0x14, self.dcfg_dflt);
+        write_u32(&mut bytes, 0x18, self.dap_vendor_usage);
+        write_u32(&mut bytes, 0x1c, self.secure_boot_cfg);
+        write_u32(&mut bytes, 0x20, self.prince_base_addr);
+        for (i, sr) in self.prince_sr.iter().enumerate() {
+            write_u32(&mut bytes, 0x24 + 4 * i, *sr);
+        }
+        bytes[0x30..0x50].copy_from_slice(&self.reserved0);
+        bytes[0x50..0x70].copy_from_slice(&self.rotkh);
+        bytes[0x70..0x100].copy_from_slice(&self.reserved1);
+        bytes[0x100..0x1e0].copy_from_slice(&self.customer_data);
+        bytes[0x1e0..0x200].copy_from_slice(&self.sha256);
+        bytes
+    }
+
+    pub fn boot_config(&self) -> BootCfg {
+        BootCfg(self.boot_cfg)
+    }
+
+    pub fn set_boot_config(&mut self, boot_cfg: BootCfg) {
+        self.boot_cfg = boot_cfg.0;
+    }
+
+    pub fn secure_boot_config(&self) -> SecureBootCfg {
+        SecureBootCfg(self.secure_boot_cfg)
+    }
+
+    pub fn set_secure_boot_config(&mut self, secure_boot_cfg: SecureBootCfg) {
+        self.secure_boot_cfg = secure_boot_cfg.0;
+    }
+
+    /// Base address (`PRINCE_BASE_ADDR.ADDRn_PRG`) and sub-regions (`PRINCE_SRn`) of a
+    /// PRINCE region.
+    ///
+    /// `ADDRn_PRG` has four bits, but the peripheral only two: larger values are an error
+    /// rather than truncated to another base.
+    pub fn prince_region(&self, region: Region) -> prince::Result<RegionConfig> {
+        let index = region as usize;
+        let prg = (self.prince_base_addr >> (4 * index)) & 0xf;
+        if prg > 3 {
+            return Err(prince::Error::InvalidBase);
+        }
+        Ok(RegionConfig::new(
+            prg as usize * prince::REGION_SIZE,
+            Subregions::from_bits(self.prince_sr[index]),
+        ))
+    }
+
+    /// Leaves the other fields of `PRINCE_BASE_ADDR` (locks, erase checks) alone.
+    pub fn set_prince_region(&mut self, region: Region, config: &RegionConfig) {
+        let index = region as usize;
+        let prg = (config.base() / prince::REGION_SIZE) as u32;
+        self.prince_base_addr &= !(0xf << (4 * index));
+        self.prince_base_addr |= prg << (4 * index);
+        self.prince_sr[index] = config.subregions().bits();
+    }
+
+    pub fn usb_id(&self) -> UsbId {
+        match (self.usb_vid, self.usb_pid) {
+            (0, 0) => UsbId::NxpDefault,
+            (vid, pid) => UsbId::Custom { vid, pid },
+        }
+    }
+
+    pub fn set_usb_id(&mut self, usb_id: UsbId) {
+        let (vid, pid) = match usb_id {
+            UsbId::NxpDefault => (0, 0),
+            UsbId::Custom { vid, pid } => (vid, pid),
+        };
+        self.usb_vid = vid;
+        self.usb_pid = pid;
+    }
+
+    /// Checks the page for combinations that would brick the device.
+    ///
+    /// `seal` is whether the page is going to be sealed. Sealing requires a RoTKH,
+    /// as does secure boot. Debug access must be left recoverable by some means:
+    /// ISP, default-enabled debug, or debug authentication (which itself needs a RoTKH).
+    pub fn validate(&self, seal: bool) -> Result<(), CmpaIssue> {
+        let rotkh_blank =
+            self.rotkh.iter().all(|byte| *byte == 0) || self.rotkh.iter().all(|byte| *byte == 0xff);
+        if rotkh_blank && (seal || self.secure_boot_config().secure_boot_enabled()) {
+            return Err(CmpaIssue::RotkhMissing);
+        }
+
+        // erased words decode to the ROM's default, debug left open
+        let policy = DebugPolicy::decode(self.dcfg_pin, self.dcfg_dflt)?;
+
+        let isp = self.boot_config().isp_mode() != IspMode::Disabled;
+        // invasive debug of CPU0, secure or non-secure
+        let debug = [policy.nonsecure_debug, policy.secure_debug];
+        let debug_by_default = debug.contains(&Access::Enabled);
+        let debug_by_authentication = debug.contains(&Access::Authenticated) && !rotkh_blank;
+        if !(isp || debug_by_default || debug_by_authentication) {
+            return Err(CmpaIssue::NoRecoveryPath);
+        }
+
+        Ok(())
+    }
+
+    /// Raw 32 bytes at offset 0x30, containing the crystal trim words.
+    pub fn xtal_trim_area(&self) -> &[u8; 32] {
+        &self.reserved0
+    }
+
+    pub fn sha256(&self) -> &[u8; 32] {
+        &self.sha256
+    }
+
+    pub fn set_sha256(&mut self, sha256: &[u8; 32]) {
+        self.sha256 = *sha256;
+    }
+}
+
+/// Value of the keystore header marking a valid activation code.
+pub const KEYSTORE_HEADER: u32 = 0x9595_9595;
+
+/// The PUF keystore in the CMPA key pages, as understood by the ROM.
+///
+/// Holds the PUF activation code and a key code for each `KeyType`.
+#[derive(Copy, Clone)]
+pub struct Keystore {
+    pub header: u32,
+    pub puf_discharge_time_ms: u32,
+    pub activation_code: [u8; 1192],
+    /// Indexed by `KeyType`
+    pub key_codes: [[u8; 52]; 6],
+    reserved: [u8; 24],
+}
+
+impl Keystore {
+    /// Three flash pages
+    pub const SIZE: usize = 3 * 512;
+
+    /// An otherwise empty keystore for a freshly enrolled PUF.
+    pub fn new(activation_code: &[u8; 1192]) -> Self {
+        Keystore {
+            header: KEYSTORE_HEADER,
+            puf_discharge_time_ms: 0,
+            activation_code: *activation_code,
+            key_codes: [[0u8; 52]; 6],
+            reserved: [0u8; 24],
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let mut key_codes = [[0u8; 52]; 6];
+        for (i, key_code) in key_codes.iter_mut().enumerate() {
+            let offset = 0x4b0 + 52 * i;
+            key_code.copy_from_slice(&bytes[offset..offset + 52]);
+        }
+        Keystore {
+            header: read_u32(bytes, 0),
+            puf_discharge_time_ms: read_u32(bytes, 4),
+            activation_code: bytes[8..0x4b0].try_into().unwrap(),
+            key_codes,
+            reserved: bytes[0x5e8..].try_into().unwrap(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        write_u32(&mut bytes, 0, self.header);
+        write_u32(&mut bytes, 4, self.puf_discharge_time_ms);
+        bytes[8..0x4b0].copy_from_slice(&self.activation_code);
+        for (i, key_code) in self.key_codes.iter().enumerate() {
+            let offset = 0x4b0 + 52 * i;
+            bytes[offset..offset + 52].copy_from_slice(key_code);
+        }
+        bytes[0x5e8..].copy_from_slice(&self.reserved);
+        bytes
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.header == KEYSTORE_HEADER
+    }
+
+    pub fn key_code(&self, key_type: KeyType) -> &[u8; 52] {
+        &self.key_codes[key_type as usize]
+    }
+
+    pub fn set_key_code(&mut self, key_type: KeyType, key_code: &[u8; 52]) {
+        self.key_codes[key_type as usize] = *key_code;
     }
 }
 
+const KEYSTORE_ADDR: usize = 0x0009_E600;
+
+const CFPA_PING_ADDR: usize = 0x0009_DE00 + 512;
+const CFPA_PONG_ADDR: usize = 0x0009_DE00 + 512 + 512;
+
 // #define BOOTLOADER_API_TREE_POINTER (bootloader_tree_t*) 0x130010f0
 #[repr(C)]
 struct BootloaderTree {
@@ -164,8 +827,7 @@ struct FlashDriverInterface {
         len: u32,
     ) -> u32,
 
-    // TODO
-    ffr_keystore_write: unsafe extern "C" fn(config: &mut FlashConfig) -> u32,
+    ffr_keystore_write: unsafe extern "C" fn(config: &mut FlashConfig, key_store: *const u8) -> u32,
     ffr_keystore_get_ac:
         unsafe extern "C" fn(config: &mut FlashConfig, activation_code: *mut u8) -> u32,
     ffr_keystore_get_kc:
@@ -292,9 +954,33 @@ impl Pfr<init_state::Enabled> {
         // heprintln!("cfpa:").ok();
         // dump_hex!(cfpa_bytes, 512);
 
-        let cmpa: &Cmpa = unsafe { core::mem::transmute(cmpa_bytes.as_ptr()) };
+        Ok(Cmpa::from_bytes(&cmpa_bytes))
+    }
+
+    /// Writes the CMPA page, optionally sealing it.
+    ///
+    /// The page is first checked with `Cmpa::validate`, and read back afterwards.
+    /// When sealing, the ROM computes and stores the digest.
+    pub fn write_cmpa(&mut self, cmpa: &Cmpa, seal: Option<Seal>) -> Result<(), Error> {
+        cmpa.validate(seal.is_some()).map_err(Error::UnsafeCmpa)?;
+
+        let ffr_cust_factory_page_write = Self::bootloader_api_tree()
+            .flash_driver
+            .ffr_cust_factory_page_write;
+        let cmpa_bytes = cmpa.to_bytes();
+        Self::check_error(unsafe {
+            ffr_cust_factory_page_write(&mut self.flash_config, cmpa_bytes.as_ptr(), seal.is_some())
+        })?;
+
+        let written = self.read_cmpa()?.to_bytes();
+        if written[..0x1e0] != cmpa_bytes[..0x1e0] {
+            return Err(Error::VerifyFailed);
+        }
+        if seal.is_some() && written[0x1e0..].iter().all(|byte| *byte == 0) {
+            return Err(Error::VerifyFailed);
+        }
 
-        Ok(*cmpa)
+        Ok(())
     }
 
     /// Get a readonly static reference to the customer data in CMPA.
@@ -324,71 +1010,51 @@ impl Pfr<init_state::Enabled> {
         // heprintln!("cfpa:").ok();
         // dump_hex!(cfpa_bytes, 512);
 
-        let cfpa: &Cfpa = unsafe { core::mem::transmute(cfpa_bytes.as_ptr()) };
-
-        Ok(*cfpa)
+        Ok(Cfpa::from_bytes(&cfpa_bytes))
     }
 
     /// Reads CFPA without use of bootrom.  Appears that the bootrom method sometimes
     /// returns previous versions of the CFPA page (not seen on scratch, ping, or pong pages).
     /// This method always returns the most recently updated Cfpa from ping or pong pages.
     pub fn read_latest_cfpa(&mut self) -> Result<Cfpa, u32> {
-        let mut cfpa_bytes = [0u32; 128];
-
-        let ping_ptr = (0x0009_DE00 + 512) as *const u32;
-        let pong_ptr = (0x0009_DE00 + 512 + 512) as *const u32;
+        let ping_version: u32 = unsafe { *(CFPA_PING_ADDR as *const u32).offset(1) };
+        let pong_version: u32 = unsafe { *(CFPA_PONG_ADDR as *const u32).offset(1) };
 
-        let ping_version: u32 = unsafe { *(ping_ptr).offset(1) };
-        let pong_version: u32 = unsafe { *(pong_ptr).offset(1) };
-
-        let cfpa_ptr: *const u32 = if ping_version > pong_version {
-            ping_ptr
+        let cfpa_addr = if ping_version > pong_version {
+            CFPA_PING_ADDR
         } else {
-            pong_ptr
+            CFPA_PONG_ADDR
         };
 
-        for i in 0..128 {
-            cfpa_bytes[i] = unsafe { *cfpa_ptr.offset(i as isize) };
-        }
-
-        let cfpa: &Cfpa = unsafe { core::mem::transmute(cfpa_bytes.as_ptr()) };
-
-        Ok(*cfpa)
+        Ok(Self::read_cfpa_at(cfpa_addr))
     }
 
     pub fn read_cfpa_ping(&mut self) -> Result<Cfpa, u32> {
-        let mut cfpa_bytes = [0u32; 128];
-
-        const CFPA_PTR: *const u32 = (0x0009_DE00 + 512) as *const u32;
-        for i in 0..128 {
-            cfpa_bytes[i] = unsafe { *CFPA_PTR.offset(i as isize) };
-        }
-
-        let cfpa: &Cfpa = unsafe { core::mem::transmute(cfpa_bytes.as_ptr()) };
-
-        Ok(*cfpa)
+        Ok(Self::read_cfpa_at(CFPA_PING_ADDR))
     }
 
     pub fn read_cfpa_pong(&mut self) -> Result<Cfpa, u32> {
-        let mut cfpa_bytes = [0u32; 128];
+        Ok(Self::read_cfpa_at(CFPA_PONG_ADDR))
+    }
 
-        const CFPA_PTR: *const u32 = (0x0009_DE00 + 512 + 512) as *const u32;
-        for i in 0..128 {
-            cfpa_bytes[i] = unsafe { *CFPA_PTR.offset(i as isize) };
-        }
+    fn read_cfpa_at(addr: usize) -> Cfpa {
+        let mut cfpa_bytes = [0u8; 512];
 
-        let cfpa: &Cfpa = unsafe { core::mem::transmute(cfpa_bytes.as_ptr()) };
+        let cfpa_ptr = addr as *const u32;
+        for (i, word) in cfpa_bytes.chunks_exact_mut(4).enumerate() {
+            word.copy_from_slice(&unsafe { *cfpa_ptr.add(i) }.to_le_bytes());
+        }
 
-        Ok(*cfpa)
+        Cfpa::from_bytes(&cfpa_bytes)
     }
 
     pub fn write_cfpa(&mut self, cfpa: &Cfpa) -> Result<(), u32> {
         let ffr_infield_page_write = Self::bootloader_api_tree()
             .flash_driver
             .ffr_infield_page_write;
-        let cfpa_bytes: *const u8 = unsafe { core::mem::transmute(cfpa as *const Cfpa) };
+        let cfpa_bytes = cfpa.to_bytes();
         Self::check_error(unsafe {
-            ffr_infield_page_write(&mut self.flash_config, cfpa_bytes, 512)
+            ffr_infield_page_write(&mut self.flash_config, cfpa_bytes.as_ptr(), 512)
         })?;
         Ok(())
     }
@@ -412,6 +1078,96 @@ impl Pfr<init_state::Enabled> {
         Ok(ac)
     }
 
+    /// Read-modify-write of the CFPA page.
+    ///
+    /// Reads the newest of the ping/pong pages, lets `f` modify it, and writes it back
+    /// with the version incremented (or as set by `f`, if higher). The monotonic counters
+    /// may not decrease. If `hashcrypt` is passed, the SHA-256 digest is filled in;
+    /// it is required if the current page carries a digest.
+    ///
+    /// Finally, the newest ping/pong page is read back and compared to what was written.
+    /// Returns the written page.
+    pub fn update_cfpa(
+        &mut self,
+        hashcrypt: Option<&mut Hashcrypt<init_state::Enabled>>,
+        f: impl FnOnce(&mut Cfpa),
+    ) -> Result<Cfpa, Error> {
+        let current = self.read_latest_cfpa()?;
+        let mut cfpa = current;
+        f(&mut cfpa);
+
+        if cfpa.version <= current.version {
+            cfpa.version = current
+                .version
+                .checked_add(1)
+                .ok_or(Error::VersionOverflow)?;
+        }
+
+        if cfpa.secure_fw_version < current.secure_fw_version
+            || cfpa.ns_fw_version < current.ns_fw_version
+            || cfpa.image_key_revoke < current.image_key_revoke
+        {
+            return Err(Error::CounterDecreased);
+        }
+
+        match hashcrypt {
+            Some(hashcrypt) => cfpa.fill_sha256(hashcrypt),
+            None if current.sha256.iter().any(|byte| *byte != 0) => {
+                return Err(Error::DigestRequired)
+            }
+            None => cfpa.sha256 = [0u8; 32],
+        }
+
+        self.write_cfpa(&cfpa)?;
+
+        let written = self.read_latest_cfpa()?;
+        if written.to_bytes()[..] != cfpa.to_bytes()[..] {
+            return Err(Error::VerifyFailed);
+        }
+
+        Ok(cfpa)
+    }
+
+    /// Reads the whole PUF keystore.
+    pub fn read_keystore(&mut self) -> Result<Keystore, u32> {
+        let mut bytes = [0u8; Keystore::SIZE];
+        let words = KEYSTORE_ADDR as *const u32;
+        for (i, word) in bytes[..8].chunks_exact_mut(4).enumerate() {
+            word.copy_from_slice(&unsafe { *words.add(i) }.to_le_bytes());
+        }
+        let mut keystore = Keystore::from_bytes(&bytes);
+
+        keystore.activation_code = self.read_activation_code()?;
+        for (i, key_type) in [
+            KeyType::Sbkek,
+            KeyType::User,
+            KeyType::Uds,
+            KeyType::PrinceRegion0,
+            KeyType::PrinceRegion1,
+            KeyType::PrinceRegion2,
+        ]
+        .iter()
+        .enumerate()
+        {
+            keystore.key_codes[i] = self.read_key_code(*key_type)?;
+        }
+
+        Ok(keystore)
+    }
+
+    /// Writes the PUF keystore through the ROM, and reads it back.
+    pub fn write_keystore(&mut self, keystore: &Keystore) -> Result<(), Error> {
+        let ffr_keystore_write = Self::bootloader_api_tree().flash_driver.ffr_keystore_write;
+        let bytes = keystore.to_bytes();
+        Self::check_error(unsafe { ffr_keystore_write(&mut self.flash_config, bytes.as_ptr()) })?;
+
+        let written = self.read_keystore()?;
+        if written.to_bytes()[..0x5e8] != bytes[..0x5e8] {
+            return Err(Error::VerifyFailed);
+        }
+        Ok(())
+    }
+
     /// Set write protection to PFR pages.  Lasts until next power on reset.
     pub fn lock_all(&mut self) -> Result<(), u32> {
         let ffr_lock_all = Self::bootloader_api_tree().flash_driver.ffr_lock_all;
@@ -419,3 +1175,217 @@ impl Pfr<init_state::Enabled> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::hex;
+
+    // Rows of 16 bytes at their page offset, like `hexdump -C`; rows not listed are zero.
+    fn page(rows: &[(usize, &str)]) -> [u8; 512] {
+        let mut page = [0u8; 512];
+        for (offset, row) in rows {
+            page[*offset..*offset + 16].copy_from_slice(&hex::<16>(row));
+        }
+        page
+    }
+
+    // CMPA for a device with secure boot (TrustZone enabled, PUF enrollment blocked),
+    // USB ISP at 96 MHz signalling boot failures on P1_5, debug only after
+    // authentication with UUID check, PRINCE region 2 at 512KB, and a RoTKH.
+    const CMPA: &[(usize, &str)] = &[
+        (0x00, "90000029 00000000 091200b0 00000000"),
+        (0x10, "0080ff7f 0000ffff 00000000 003200c0"),
+        (0x20, "00020000 00000000 00000000 ffff0000"),
+        (0x50, "27aa7ca2 411e943c fa99d26f 3f1346fb"),
+        (0x60, "59dc0d59 0cbd2eb8 270e9e33 01fccc75"),
+    ];
+
+    // CFPA at version 2, with firmware versions 1, RoTK0 enabled and
+    // the IV code of PRINCE region 2 (key index 3, 64 bits).
+    const CFPA: &[(usize, &str)] = &[
+        (0x00, "00000000 02000000 01000000 01000000"),
+        (0x10, "00000000 00000000 01000000 00000000"),
+        (0xa0, "00000000 01030001 5c4f7e21 a90d33c6"),
+        (0xb0, "18e2b7f0 4da1653c 9e07c2d8 71b54f0a"),
+        (0xc0, "e3269d15 b8c04f72 0a6e91d3 27fc58b6"),
+        (0xd0, "4190e62d 3bc7a805 00000000 00000000"),
+    ];
+
+    #[test]
+    fn provisioned_cmpa() {
+        let page = page(CMPA);
+        let cmpa = Cmpa::from_bytes(&page);
+
+        let boot_cfg = cmpa.boot_config();
+        assert_eq!(boot_cfg.isp_mode(), IspMode::UsbHidMsc);
+        assert_eq!(boot_cfg.boot_speed(), BootSpeed::Fro96Mhz);
+        assert_eq!(boot_cfg.boot_failure_pin(), (1, 5));
+        assert_eq!(
+            cmpa.usb_id(),
+            UsbId::Custom {
+                vid: 0x1209,
+                pid: 0xb000
+            }
+        );
+
+        assert_eq!(
+            DebugPolicy::from_words(cmpa.dcfg_pin, cmpa.dcfg_dflt),
+            Some(
+                DebugPolicy::default()
+                    .all(Access::Authenticated)
+                    .uuid_check(true)
+            )
+        );
+
+        let secure_boot_cfg = cmpa.secure_boot_config();
+        assert!(secure_boot_cfg.secure_boot_enabled());
+        assert_eq!(secure_boot_cfg.tzm_image_type(), TzmImageType::Enabled);
+        assert!(secure_boot_cfg.block_enroll());
+        assert!(!secure_boot_cfg.block_set_key());
+        assert!(!secure_boot_cfg.rsa4096_only());
+        assert!(!secure_boot_cfg.skip_dice());
+
+        assert_eq!(
+            cmpa.prince_region(Region::Region2),
+            Ok(RegionConfig::new(
+                2 * prince::REGION_SIZE,
+                Subregions::from_bits(0xffff)
+            ))
+        );
+        assert!(cmpa.prince_region(Region::Region0).unwrap().is_empty());
+        assert_eq!(
+            cmpa.rotkh,
+            hex::<32>("27aa7ca2411e943cfa99d26f3f1346fb59dc0d590cbd2eb8270e9e3301fccc75")
+        );
+        assert_eq!(cmpa.validate(true), Ok(()));
+        assert_eq!(cmpa.sha256(), &[0u8; 32]);
+
+        assert_eq!(cmpa.to_bytes()[..], page[..]);
+    }
+
+    #[test]
+    fn cfpa_with_iv_code() {
+        let page = page(CFPA);
+        let cfpa = Cfpa::from_bytes(&page);
+
+        assert_eq!(cfpa.header, 0);
+        assert_eq!(cfpa.version, 2);
+        assert_eq!(cfpa.secure_fw_version, 1);
+        assert_eq!(cfpa.ns_fw_version, 1);
+        assert_eq!(cfpa.image_key_revoke, 0);
+        assert_eq!(cfpa.root_key_state(0), Ok(rotkh::KeyState::Enabled));
+        assert_eq!(cfpa.root_key_state(1), Ok(rotkh::KeyState::Invalid));
+        assert_eq!(
+            DebugPolicy::from_words(cfpa.dcfg_ns_pin, cfpa.dcfg_ns_dflt),
+            Some(DebugPolicy::default())
+        );
+        assert_eq!(cfpa.enable_fa_mode(), 0);
+
+        assert!(!cfpa.key_provisioned(KeyType::PrinceRegion0));
+        assert!(!cfpa.key_provisioned(KeyType::PrinceRegion1));
+        assert!(cfpa.key_provisioned(KeyType::PrinceRegion2));
+        let iv_code = &cfpa.iv_code_prince_region[2].iv;
+        let header =
+            crate::peripherals::puf::KeyCodeHeader::from_bytes(iv_code[..4].try_into().unwrap());
+        assert_eq!(header.index, 3);
+        assert_eq!(header.key_size, 64);
+        assert_eq!(header.key_code_size(), 52);
+
+        assert_eq!(cfpa.to_bytes()[..], page[..]);
+    }
+
+    // every byte distinct from its neighbours, so a field read from the wrong offset shows
+    fn page_dump() -> [u8; 512] {
+        let mut page = [0u8; 512];
+        for (i, byte) in page.iter_mut().enumerate() {
+            *byte = (i * 7 + 3) as u8;
+        }
+        page
+    }
+
+    fn word(page: &[u8; 512], offset: usize) -> u32 {
+        read_u32(page, offset)
+    }
+
+    #[test]
+    fn cmpa_round_trip() {
+        let page = page_dump();
+        let cmpa = Cmpa::from_bytes(&page);
+        assert_eq!(cmpa.boot_cfg, word(&page, 0x00));
+        assert_eq!(cmpa.spi_flash_cfg, word(&page, 0x04));
+        assert_eq!(cmpa.usb_vid, read_u16(&page, 0x08));
+        assert_eq!(cmpa.usb_pid, read_u16(&page, 0x0a));
+        assert_eq!(cmpa.dcfg_pin, word(&page, 0x10));
+        assert_eq!(cmpa.dcfg_dflt, word(&page, 0x14));
+        assert_eq!(cmpa.secure_boot_cfg, word(&page, 0x1c));
+        assert_eq!(cmpa.prince_base_addr, word(&page, 0x20));
+        assert_eq!(cmpa.prince_sr[2], word(&page, 0x2c));
+        assert_eq!(cmpa.rotkh[..], page[0x50..0x70]);
+        assert_eq!(cmpa.customer_data[..], page[0x100..0x1e0]);
+        assert_eq!(cmpa.sha256()[..], page[0x1e0..]);
+        assert_eq!(cmpa.to_bytes()[..], page[..]);
+    }
+
+    #[test]
+    fn cfpa_round_trip() {
+        let page = page_dump();
+        let cfpa = Cfpa::from_bytes(&page);
+        assert_eq!(cfpa.header, word(&page, 0x00));
+        assert_eq!(cfpa.version, word(&page, 0x04));
+        assert_eq!(cfpa.secure_fw_version, word(&page, 0x08));
+        assert_eq!(cfpa.ns_fw_version, word(&page, 0x0c));
+        assert_eq!(cfpa.image_key_revoke, word(&page, 0x10));
+        assert_eq!(cfpa.rotkh_revoke, word(&page, 0x18));
+        assert_eq!(cfpa.vendor_usage(), word(&page, 0x1c));
+        assert_eq!(cfpa.dcfg_ns_pin, word(&page, 0x20));
+        assert_eq!(cfpa.dcfg_ns_dflt, word(&page, 0x24));
+        assert_eq!(cfpa.enable_fa_mode(), word(&page, 0x28));
+        let iv_code = &cfpa.iv_code_prince_region[2];
+        assert_eq!(iv_code.keycode_header, word(&page, 0xa0));
+        assert_eq!(iv_code.iv[..], page[0xa4..0xd8]);
+        assert_eq!(cfpa.customer_data[..], page[0x100..0x1e0]);
+        assert_eq!(cfpa.sha256()[..], page[0x1e0..]);
+        assert_eq!(cfpa.to_bytes()[..], page[..]);
+    }
+
+    #[test]
+    fn erased_pages() {
+        let page = [0u8; 512];
+        let cmpa = Cmpa::from_bytes(&page);
+        assert_eq!(cmpa.to_bytes()[..], page[..]);
+
+        let cfpa = Cfpa::from_bytes(&page);
+        assert!(!cfpa.key_provisioned(KeyType::PrinceRegion0));
+        assert_eq!(cfpa.to_bytes()[..], page[..]);
+    }
+
+    #[test]
+    fn prince_regions() {
+        let mut cmpa = Cmpa::from_bytes(&[0u8; 512]);
+        // lock and erase check bits
+        cmpa.prince_base_addr = 0x0333_0000;
+        let config = RegionConfig::new(2 * prince::REGION_SIZE, Subregions::from_bits(0xff));
+        cmpa.set_prince_region(Region::Region1, &config);
+        assert_eq!(cmpa.prince_base_addr, 0x0333_0020);
+        assert_eq!(cmpa.prince_sr[1], 0xff);
+        assert_eq!(cmpa.prince_region(Region::Region1), Ok(config));
+        assert_eq!(
+            cmpa.prince_region(Region::Region0),
+            Ok(RegionConfig::new(0, Subregions::empty()))
+        );
+
+        // 4 or 7 would be read as 0 or 3 by the peripheral
+        cmpa.prince_base_addr |= 0x700;
+        assert_eq!(
+            cmpa.prince_region(Region::Region2),
+            Err(prince::Error::InvalidBase)
+        );
+        cmpa.prince_base_addr ^= 0x300;
+        assert_eq!(
+            cmpa.prince_region(Region::Region2),
+            Err(prince::Error::InvalidBase)
+        );
+        assert!(prince::Layout::from_cmpa(&cmpa).is_err());
+    }
+}
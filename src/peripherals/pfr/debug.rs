@@ -0,0 +1,386 @@
+//! Debug access policy, as encoded in the `DCFG_CC_SOCU_PIN`/`DCFG_CC_SOCU_DFLT`
+//! words of CMPA (and further restricted by their `_NS_` counterparts in CFPA).
+//!
+//! For each debug feature, the `PIN` bit fixes the setting to its `DFLT` bit.
+//! If the `PIN` bit is clear, the feature is off by default but can be enabled
+//! at runtime through debug authentication. Each word carries its inverse
+//! in the upper half-word; the ROM rejects words where this does not match.
+//!
+//! ```ignore
+//! let policy = DebugPolicy::default()
+//!     .secure_debug(Access::Authenticated)
+//!     .nonsecure_debug(Access::Authenticated)
+//!     .fault_analysis(Access::Disabled);
+//! cmpa.set_debug_policy(&policy);
+//! ```
+
+use crate::peripherals::pfr::{Cfpa, Cmpa, CmpaIssue, FA_MODE_MAGIC};
+
+/// How a debug feature is made available.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Access {
+    /// Always enabled (`PIN` = 1, `DFLT` = 1).
+    Enabled,
+    /// Disabled, unless enabled through debug authentication (`PIN` = 0, `DFLT` = 0).
+    Authenticated,
+    /// Permanently disabled (`PIN` = 1, `DFLT` = 0).
+    Disabled,
+}
+
+impl Access {
+    fn encode(self) -> (bool, bool) {
+        match self {
+            Access::Enabled => (true, true),
+            Access::Authenticated => (false, false),
+            Access::Disabled => (true, false),
+        }
+    }
+
+    fn decode(pin: bool, dflt: bool) -> Option<Self> {
+        match (pin, dflt) {
+            (true, true) => Some(Access::Enabled),
+            (false, false) => Some(Access::Authenticated),
+            (true, false) => Some(Access::Disabled),
+            (false, true) => None,
+        }
+    }
+
+    /// Whether `self` grants no more than `other`.
+    pub fn is_at_most(self, other: Access) -> bool {
+        let rank = |access| match access {
+            Access::Disabled => 0,
+            Access::Authenticated => 1,
+            Access::Enabled => 2,
+        };
+        rank(self) <= rank(other)
+    }
+}
+
+/// Typed debug access policy. The default leaves everything `Enabled`,
+/// which is what the ROM does for erased (all zero) words.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DebugPolicy {
+    pub nonsecure_non_invasive: Access,
+    pub nonsecure_debug: Access,
+    pub secure_non_invasive: Access,
+    pub secure_debug: Access,
+    pub jtag_tap: Access,
+    pub cpu1_debug: Access,
+    pub cpu1_non_invasive: Access,
+    /// ISP boot command via the debug mailbox
+    pub isp_command: Access,
+    /// Fault analysis command via the debug mailbox
+    pub fault_analysis: Access,
+    /// Flash mass erase command via the debug mailbox
+    pub mass_erase_command: Access,
+    /// Require the device UUID in debug credentials
+    pub uuid_check: bool,
+}
+
+impl Default for DebugPolicy {
+    fn default() -> Self {
+        DebugPolicy {
+            nonsecure_non_invasive: Access::Enabled,
+            nonsecure_debug: Access::Enabled,
+            secure_non_invasive: Access::Enabled,
+            secure_debug: Access::Enabled,
+            jtag_tap: Access::Enabled,
+            cpu1_debug: Access::Enabled,
+            cpu1_non_invasive: Access::Enabled,
+            isp_command: Access::Enabled,
+            fault_analysis: Access::Enabled,
+            mass_erase_command: Access::Enabled,
+            uuid_check: false,
+        }
+    }
+}
+
+impl DebugPolicy {
+    /// Sets every feature to the same access.
+    pub fn all(self, access: Access) -> Self {
+        DebugPolicy {
+            nonsecure_non_invasive: access,
+            nonsecure_debug: access,
+            secure_non_invasive: access,
+            secure_debug: access,
+            jtag_tap: access,
+            cpu1_debug: access,
+            cpu1_non_invasive: access,
+            isp_command: access,
+            fault_analysis: access,
+            mass_erase_command: access,
+            uuid_check: self.uuid_check,
+        }
+    }
+
+    /// Non-secure debug, invasive and non-invasive.
+    pub fn nonsecure_debug(mut self, access: Access) -> Self {
+        self.nonsecure_debug = access;
+        self.nonsecure_non_invasive = access;
+        self
+    }
+
+    /// Secure debug, invasive and non-invasive.
+    pub fn secure_debug(mut self, access: Access) -> Self {
+        self.secure_debug = access;
+        self.secure_non_invasive = access;
+        self
+    }
+
+    pub fn jtag_tap(mut self, access: Access) -> Self {
+        self.jtag_tap = access;
+        self
+    }
+
+    /// Debug of the second core, invasive and non-invasive.
+    pub fn cpu1_debug(mut self, access: Access) -> Self {
+        self.cpu1_debug = access;
+        self.cpu1_non_invasive = access;
+        self
+    }
+
+    /// The debug mailbox command set: ISP boot and flash mass erase.
+    pub fn isp_commands(mut self, access: Access) -> Self {
+        self.isp_command = access;
+        self.mass_erase_command = access;
+        self
+    }
+
+    pub fn fault_analysis(mut self, access: Access) -> Self {
+        self.fault_analysis = access;
+        self
+    }
+
+    pub fn uuid_check(mut self, uuid_check: bool) -> Self {
+        self.uuid_check = uuid_check;
+        self
+    }
+
+    /// Whether any feature requires debug authentication.
+    pub fn requires_authentication(&self) -> bool {
+        self.accesses().contains(&Access::Authenticated)
+    }
+
+    /// Whether `self` grants no more than `other`, feature by feature.
+    ///
+    /// The non-secure policy in CFPA can only restrict the one in CMPA.
+    pub fn is_at_most(&self, other: &DebugPolicy) -> bool {
+        self.accesses()
+            .iter()
+            .zip(other.accesses().iter())
+            .all(|(this, other)| this.is_at_most(*other))
+    }
+
+    fn accesses(&self) -> [Access; 10] {
+        [
+            self.nonsecure_non_invasive,
+            self.nonsecure_debug,
+            self.secure_non_invasive,
+            self.secure_debug,
+            self.jtag_tap,
+            self.cpu1_debug,
+            self.isp_command,
+            self.fault_analysis,
+            self.mass_erase_command,
+            self.cpu1_non_invasive,
+        ]
+    }
+
+    /// The `(PIN, DFLT)` flash words, including the inverse values.
+    pub fn to_words(&self) -> (u32, u32) {
+        let mut pin = if self.uuid_check { UUID_CHECK } else { 0 };
+        let mut dflt = 0;
+        // `accesses` is in the bit order of the words
+        for (bit, access) in self.accesses().iter().enumerate() {
+            let (pin_bit, dflt_bit) = access.encode();
+            pin |= (pin_bit as u16) << bit;
+            dflt |= (dflt_bit as u16) << bit;
+        }
+        (to_word(pin), to_word(dflt))
+    }
+
+    /// Decodes a `(PIN, DFLT)` word pair.
+    ///
+    /// Returns `None` if a word is corrupt or a `DFLT` bit is set without its `PIN` bit.
+    pub fn from_words(pin: u32, dflt: u32) -> Option<Self> {
+        Self::decode(pin, dflt).ok()
+    }
+
+    pub(crate) fn decode(pin: u32, dflt: u32) -> Result<Self, CmpaIssue> {
+        let (pin, dflt) = match (pin, dflt) {
+            (0, 0) => return Ok(DebugPolicy::default()),
+            (0, _) | (_, 0) => return Err(CmpaIssue::InvalidDcfg),
+            (pin, dflt) => (
+                from_word(pin).ok_or(CmpaIssue::InvalidDcfg)?,
+                from_word(dflt).ok_or(CmpaIssue::InvalidDcfg)?,
+            ),
+        };
+        let access = |bit: u32| {
+            Access::decode((pin >> bit) & 1 != 0, (dflt >> bit) & 1 != 0)
+                .ok_or(CmpaIssue::DcfgMismatch)
+        };
+        Ok(DebugPolicy {
+            nonsecure_non_invasive: access(0)?,
+            nonsecure_debug: access(1)?,
+            secure_non_invasive: access(2)?,
+            secure_debug: access(3)?,
+            jtag_tap: access(4)?,
+            cpu1_debug: access(5)?,
+            isp_command: access(6)?,
+            fault_analysis: access(7)?,
+            mass_erase_command: access(8)?,
+            cpu1_non_invasive: access(9)?,
+            uuid_check: pin & UUID_CHECK != 0,
+        })
+    }
+}
+
+// enforce UUID match during debug authentication; PIN word only
+const UUID_CHECK: u16 = 1 << 15;
+
+// flags in the lower half-word, their inverse in the upper one
+fn to_word(flags: u16) -> u32 {
+    ((!flags as u32) << 16) | flags as u32
+}
+
+fn from_word(word: u32) -> Option<u16> {
+    let flags = word as u16;
+    if (word >> 16) as u16 == !flags {
+        Some(flags)
+    } else {
+        None
+    }
+}
+
+impl Cmpa {
+    pub fn debug_policy(&self) -> Option<DebugPolicy> {
+        DebugPolicy::from_words(self.dcfg_pin, self.dcfg_dflt)
+    }
+
+    pub fn set_debug_policy(&mut self, policy: &DebugPolicy) {
+        let (pin, dflt) = policy.to_words();
+        self.dcfg_pin = pin;
+        self.dcfg_dflt = dflt;
+    }
+}
+
+impl Cfpa {
+    pub fn ns_debug_policy(&self) -> Option<DebugPolicy> {
+        DebugPolicy::from_words(self.dcfg_ns_pin, self.dcfg_ns_dflt)
+    }
+
+    pub fn set_ns_debug_policy(&mut self, policy: &DebugPolicy) {
+        let (pin, dflt) = policy.to_words();
+        self.dcfg_ns_pin = pin;
+        self.dcfg_ns_dflt = dflt;
+    }
+
+    /// Whether the ROM enters fault analysis mode on next boot.
+    pub fn fa_mode(&self) -> bool {
+        self.enable_fa_mode() == FA_MODE_MAGIC
+    }
+
+    pub fn set_fa_mode(&mut self, enable: bool) {
+        self.set_enable_fa_mode(if enable { FA_MODE_MAGIC } else { 0 });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peripherals::pfr::IspMode;
+
+    // (PIN, DFLT) words for uniform policies, worked out by hand from the
+    // register layout: bits 0..=9 select the ten access rights, the upper
+    // half holds their inverse.
+    const ALL_ENABLED: (u32, u32) = (0xfc00_03ff, 0xfc00_03ff);
+    const ALL_AUTHENTICATED: (u32, u32) = (0xffff_0000, 0xffff_0000);
+    const ALL_DISABLED: (u32, u32) = (0xfc00_03ff, 0xffff_0000);
+
+    #[test]
+    fn uniform_policies() {
+        for (words, access) in [
+            (ALL_ENABLED, Access::Enabled),
+            (ALL_AUTHENTICATED, Access::Authenticated),
+            (ALL_DISABLED, Access::Disabled),
+        ]
+        .iter()
+        {
+            let policy = DebugPolicy::default().all(*access);
+            assert_eq!(policy.to_words(), *words);
+            assert_eq!(DebugPolicy::from_words(words.0, words.1), Some(policy));
+        }
+
+        // UUID_CHECK is bit 15 of the PIN word only
+        let policy = DebugPolicy::default().uuid_check(true);
+        assert_eq!(policy.to_words(), (0x7c00_83ff, 0xfc00_03ff));
+    }
+
+    #[test]
+    fn erased_words_leave_debug_open() {
+        assert_eq!(DebugPolicy::from_words(0, 0), Some(DebugPolicy::default()));
+        assert_eq!(
+            DebugPolicy::decode(0, ALL_ENABLED.1),
+            Err(CmpaIssue::InvalidDcfg)
+        );
+    }
+
+    #[test]
+    fn invalid_words() {
+        // inverse value does not match
+        assert_eq!(
+            DebugPolicy::decode(0xfc00_03fe, 0xffff_0000),
+            Err(CmpaIssue::InvalidDcfg)
+        );
+        // DFLT set without PIN: secure debug
+        assert_eq!(
+            DebugPolicy::decode(0xffff_0000, 0xfff7_0008),
+            Err(CmpaIssue::DcfgMismatch)
+        );
+    }
+
+    #[test]
+    fn mixed_policy_round_trip() {
+        let policy = DebugPolicy::default()
+            .secure_debug(Access::Authenticated)
+            .nonsecure_debug(Access::Enabled)
+            .isp_commands(Access::Disabled)
+            .fault_analysis(Access::Authenticated)
+            .uuid_check(true);
+        let (pin, dflt) = policy.to_words();
+        // secure (bits 2, 3) and fault analysis (bit 7) authenticated
+        assert_eq!(pin as u16, 0x8373);
+        // non-secure (bits 0, 1), TAP and CPU1 (bits 4, 5, 9) enabled
+        assert_eq!(dflt as u16, 0x0233);
+        assert_eq!(DebugPolicy::from_words(pin, dflt), Some(policy));
+        assert!(policy.requires_authentication());
+    }
+
+    #[test]
+    fn restriction() {
+        let cmpa = DebugPolicy::default().all(Access::Authenticated);
+        let stricter = cmpa.secure_debug(Access::Disabled);
+        assert!(stricter.is_at_most(&cmpa));
+        assert!(!cmpa.is_at_most(&stricter));
+        assert!(!DebugPolicy::default().is_at_most(&cmpa));
+    }
+
+    #[test]
+    fn recovery_path() {
+        let mut cmpa = Cmpa::from_bytes(&[0u8; 512]);
+        let mut boot_cfg = cmpa.boot_config();
+        boot_cfg.set_isp_mode(IspMode::Disabled);
+        cmpa.set_boot_config(boot_cfg);
+        cmpa.set_debug_policy(&DebugPolicy::default().all(Access::Disabled));
+        assert_eq!(cmpa.validate(false), Err(CmpaIssue::NoRecoveryPath));
+
+        // authentication needs a RoTKH
+        cmpa.set_debug_policy(&DebugPolicy::default().all(Access::Authenticated));
+        assert_eq!(cmpa.validate(false), Err(CmpaIssue::NoRecoveryPath));
+        cmpa.rotkh = [0x5a; 32];
+        assert_eq!(cmpa.validate(false), Ok(()));
+
+        cmpa.dcfg_dflt ^= 1 << 16;
+        assert_eq!(cmpa.validate(false), Err(CmpaIssue::InvalidDcfg));
+    }
+}
@@ -0,0 +1,535 @@
+//! Resumable provisioning of PUF keys for the PRINCE regions.
+//!
+//! An encrypted PRINCE region needs, in this order:
+//! 1. an enrolled PUF, whose activation code is stored in the keystore,
+//! 2. a key code for the region's 128-bit key in the keystore,
+//! 3. an IV code for the region in CFPA,
+//! 4. the region's base address and sub-region enables in CMPA.
+//!
+//! At boot, the ROM starts the PUF from the keystore and loads the keys of
+//! all regions enabled in CMPA into PRINCE. Enabling a region in CMPA is
+//! therefore the last step, so an interrupted provisioning never leaves
+//! a region enabled without its key.
+//!
+//! `Pfr::provision_prince` skips the steps that are already done, so it
+//! can simply be called again after a reset partway through. IV codes are
+//! only kept if the PUF accepts them: a run interrupted between writing a
+//! fresh keystore and CFPA leaves IV codes of the previous enrollment behind.
+//!
+//! ```ignore
+//! let status = pfr.provision_prince(puf, Some(&mut hashcrypt), &[RegionSetup {
+//!     region: Region::Region2,
+//!     config: RegionConfig::new(0x8_0000, Subregions::from_bits(0xffff)),
+//! }])?;
+//! assert!(status.region(Region::Region2).is_provisioned());
+//! // reset, so the ROM loads the keys
+//! ```
+
+use core::convert::TryInto;
+
+use crate::{
+    peripherals::{
+        hashcrypt::Hashcrypt,
+        pfr::{self, Cfpa, Cmpa, KeyType, Keystore, Pfr, KEYSTORE_ADDR},
+        prince::{Region, RegionConfig},
+        puf::{self, KeyCode, KeyCodeHeader, Puf, Started},
+    },
+    raw,
+    typestates::init_state,
+};
+
+/// Provisioning error
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// A region's CMPA settings differ from the requested ones, but CMPA is sealed.
+    CmpaSealed,
+    /// A `RegionSetup` enables no sub-regions.
+    EmptyRegion(Region),
+    /// A region is listed more than once.
+    DuplicateRegion(Region),
+    Pfr(pfr::Error),
+    Puf(puf::Error),
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl From<pfr::Error> for Error {
+    fn from(error: pfr::Error) -> Self {
+        Error::Pfr(error)
+    }
+}
+
+impl From<puf::Error> for Error {
+    fn from(error: puf::Error) -> Self {
+        Error::Puf(error)
+    }
+}
+
+/// A PRINCE region to provision.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RegionSetup {
+    pub region: Region,
+    /// Base address and sub-regions to be encrypted, for CMPA. Must not be empty.
+    pub config: RegionConfig,
+}
+
+/// Which provisioning steps are done for a region.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RegionStatus {
+    /// The keystore holds a key code for a 128-bit PRINCE key.
+    pub key_code: bool,
+    /// CFPA holds an IV code (in `prince_status`, not checked against the PUF).
+    pub iv_code: bool,
+    /// CMPA enables sub-regions of the region, at a valid base address.
+    pub enabled: bool,
+}
+
+impl RegionStatus {
+    pub fn is_provisioned(&self) -> bool {
+        self.key_code && self.iv_code && self.enabled
+    }
+}
+
+/// Provisioning state of the PUF keystore and the PRINCE regions.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Status {
+    /// The keystore holds an activation code.
+    pub enrolled: bool,
+    /// Indexed by `Region`
+    pub regions: [RegionStatus; 3],
+}
+
+impl Status {
+    pub fn region(&self, region: Region) -> RegionStatus {
+        self.regions[region as usize]
+    }
+
+    /// The regions whose key the ROM loads at boot.
+    pub fn provisioned_regions(&self) -> impl Iterator<Item = Region> + '_ {
+        REGIONS
+            .iter()
+            .copied()
+            .filter(move |region| self.enrolled && self.region(*region).is_provisioned())
+    }
+}
+
+const REGIONS: [Region; 3] = [Region::Region0, Region::Region1, Region::Region2];
+
+const KEY_CODE_SIZE: usize = puf::key_code_size(128);
+const IV_CODE_SIZE: usize = puf::key_code_size(64);
+
+// The ROM reads IVs back as output keys, so any index but 0 works; a distinct
+// one per region tags which region an IV code was generated for.
+fn iv_key_index(region: Region) -> u8 {
+    1 + region as u8
+}
+
+fn key_type(region: Region) -> KeyType {
+    match region {
+        Region::Region0 => KeyType::PrinceRegion0,
+        Region::Region1 => KeyType::PrinceRegion1,
+        Region::Region2 => KeyType::PrinceRegion2,
+    }
+}
+
+fn key_code_present(keystore: &Keystore, region: Region) -> bool {
+    let key_code = keystore.key_code(key_type(region));
+    let header = KeyCodeHeader::from_bytes(key_code[..4].try_into().unwrap());
+    header.index == 0 && header.key_size == 128
+}
+
+fn iv_code_present(cfpa: &Cfpa, region: Region) -> bool {
+    cfpa.key_provisioned(key_type(region))
+}
+
+// Whether the IV code of `region` is present and belongs to this PUF, i.e. GetKey
+// accepts it. IV codes of an earlier enrollment are rejected as corrupted.
+fn iv_code_valid(
+    puf: &Puf<init_state::Enabled<Started>>,
+    cfpa: &Cfpa,
+    region: Region,
+) -> Result<bool> {
+    if !iv_code_present(cfpa, region) {
+        return Ok(false);
+    }
+    let iv_code = &cfpa.iv_code_prince_region[region as usize].iv;
+    let header = KeyCodeHeader::from_bytes(iv_code[..4].try_into().unwrap());
+    if header.index != iv_key_index(region) || header.key_size != 64 {
+        return Ok(false);
+    }
+    let mut iv = [0u8; 8];
+    match puf.get_key(raw::puf::keyenable::KEY_A::NONE, iv_code, &mut iv) {
+        Ok(_) => Ok(true),
+        Err(puf::Error::KeyCodeCorrupted) => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
+
+// Whether CMPA enables sub-regions of `region`, if given exactly as in `expected`.
+fn region_enabled(cmpa: &Cmpa, region: Region, expected: Option<&RegionConfig>) -> bool {
+    match cmpa.prince_region(region) {
+        Ok(config) => !config.is_empty() && *expected.unwrap_or(&config) == config,
+        Err(_) => false,
+    }
+}
+
+fn is_sealed(cmpa: &Cmpa) -> bool {
+    cmpa.sha256().iter().any(|byte| *byte != 0)
+}
+
+fn check_setups(regions: &[RegionSetup]) -> Result<()> {
+    for (i, setup) in regions.iter().enumerate() {
+        if setup.config.is_empty() {
+            return Err(Error::EmptyRegion(setup.region));
+        }
+        if regions[..i]
+            .iter()
+            .any(|other| other.region == setup.region)
+        {
+            return Err(Error::DuplicateRegion(setup.region));
+        }
+    }
+    Ok(())
+}
+
+// `keystore` is `None` before a fresh enrollment.
+fn status(keystore: Option<&Keystore>, cfpa: &Cfpa, cmpa: &Cmpa) -> Status {
+    let mut status = Status {
+        enrolled: keystore.is_some(),
+        ..Status::default()
+    };
+    for region in REGIONS.iter().copied() {
+        status.regions[region as usize] = RegionStatus {
+            key_code: matches!(keystore, Some(keystore) if key_code_present(keystore, region)),
+            iv_code: iv_code_present(cfpa, region),
+            enabled: region_enabled(cmpa, region, None),
+        };
+    }
+    status
+}
+
+/// The steps `provision_regions` takes, indexed by `Region`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct Steps {
+    key_code: [bool; 3],
+    iv_code: [bool; 3],
+    cmpa: [bool; 3],
+}
+
+// `keystore` is `None` before a fresh enrollment, which makes all key and IV codes
+// stale; `iv_codes_valid` is whether the started PUF accepts each region's IV code.
+fn steps(
+    regions: &[RegionSetup],
+    keystore: Option<&Keystore>,
+    iv_codes_valid: [bool; 3],
+    cmpa: &Cmpa,
+) -> Steps {
+    let mut steps = Steps::default();
+    for setup in regions {
+        let index = setup.region as usize;
+        steps.key_code[index] =
+            !matches!(keystore, Some(keystore) if key_code_present(keystore, setup.region));
+        steps.iv_code[index] = keystore.is_none() || !iv_codes_valid[index];
+        steps.cmpa[index] = !region_enabled(cmpa, setup.region, Some(&setup.config));
+    }
+    steps
+}
+
+impl Pfr<init_state::Enabled> {
+    /// Reports which provisioning steps are done, without touching the PUF.
+    pub fn prince_status(&mut self) -> Result<Status> {
+        let cmpa = self.read_cmpa().map_err(pfr::Error::Rom)?;
+        let cfpa = self.read_latest_cfpa().map_err(pfr::Error::Rom)?;
+        let keystore = self.read_keystore_if_valid()?;
+        Ok(status(keystore.as_ref(), &cfpa, &cmpa))
+    }
+
+    /// Provisions the given PRINCE regions, resuming where a previous call stopped.
+    ///
+    /// Enrolls the PUF if the keystore holds no activation code (in which case any
+    /// existing key and IV codes are stale and get replaced), otherwise starts it
+    /// from the keystore. Then generates the missing key codes, IV codes (also
+    /// replacing those the PUF rejects) and CMPA settings. Regions that are not
+    /// listed are left alone; each region may be listed once, with some sub-regions.
+    ///
+    /// Consumes the PUF: reset the device afterwards, so the ROM loads the keys.
+    pub fn provision_prince(
+        &mut self,
+        puf: Puf<init_state::Enabled>,
+        hashcrypt: Option<&mut Hashcrypt<init_state::Enabled>>,
+        regions: &[RegionSetup],
+    ) -> Result<Status> {
+        check_setups(regions)?;
+        let cmpa = self.read_cmpa().map_err(pfr::Error::Rom)?;
+
+        match self.read_keystore_if_valid()? {
+            Some(keystore) => {
+                let puf = puf.try_start(&keystore.activation_code)?;
+                let cfpa = self.read_latest_cfpa().map_err(pfr::Error::Rom)?;
+                let mut iv_codes_valid = [false; 3];
+                for setup in regions {
+                    iv_codes_valid[setup.region as usize] =
+                        iv_code_valid(&puf, &cfpa, setup.region)?;
+                }
+                let steps = steps(regions, Some(&keystore), iv_codes_valid, &cmpa);
+                self.provision_regions(&puf, keystore, cmpa, steps, hashcrypt, regions)?;
+            }
+            None => {
+                let steps = steps(regions, None, [false; 3], &cmpa);
+                let mut activation_code = [0u8; 1192];
+                let puf = puf.enroll(&mut activation_code)?;
+                let keystore = Keystore::new(&activation_code);
+                self.write_keystore(&keystore)?;
+                self.provision_regions(&puf, keystore, cmpa, steps, hashcrypt, regions)?;
+            }
+        }
+
+        self.prince_status()
+    }
+
+    fn provision_regions<T>(
+        &mut self,
+        puf: &Puf<init_state::Enabled<T>>,
+        mut keystore: Keystore,
+        mut cmpa: Cmpa,
+        steps: Steps,
+        hashcrypt: Option<&mut Hashcrypt<init_state::Enabled>>,
+        regions: &[RegionSetup],
+    ) -> Result<()> {
+        // key codes
+        let mut keystore_changed = false;
+        for setup in regions {
+            if steps.key_code[setup.region as usize] {
+                let key_code: KeyCode<KEY_CODE_SIZE> = puf.generate_key_code(128, 0)?;
+                keystore.set_key_code(key_type(setup.region), &key_code.to_bytes());
+                keystore_changed = true;
+            }
+        }
+        if keystore_changed {
+            self.write_keystore(&keystore)?;
+        }
+
+        // IV codes
+        let mut iv_codes: [Option<KeyCode<IV_CODE_SIZE>>; 3] = [None, None, None];
+        for setup in regions {
+            if steps.iv_code[setup.region as usize] {
+                let index = iv_key_index(setup.region);
+                iv_codes[setup.region as usize] = Some(puf.generate_key_code(64, index)?);
+            }
+        }
+        if iv_codes.iter().any(Option::is_some) {
+            self.update_cfpa(hashcrypt, |cfpa| {
+                for (iv_code, region) in iv_codes.iter().zip(cfpa.iv_code_prince_region.iter_mut())
+                {
+                    if let Some(iv_code) = iv_code {
+                        region.iv = iv_code.to_bytes();
+                    }
+                }
+            })?;
+        }
+
+        // CMPA, last
+        let mut cmpa_changed = false;
+        for setup in regions {
+            if steps.cmpa[setup.region as usize] {
+                cmpa.set_prince_region(setup.region, &setup.config);
+                cmpa_changed = true;
+            }
+        }
+        if cmpa_changed {
+            if is_sealed(&cmpa) {
+                return Err(Error::CmpaSealed);
+            }
+            self.write_cmpa(&cmpa, None)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_keystore_if_valid(&mut self) -> Result<Option<Keystore>> {
+        let header = unsafe { *(KEYSTORE_ADDR as *const u32) };
+        if header != pfr::KEYSTORE_HEADER {
+            return Ok(None);
+        }
+        Ok(Some(self.read_keystore().map_err(pfr::Error::Rom)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peripherals::prince::{Subregions, REGION_SIZE};
+
+    fn setup() -> RegionSetup {
+        RegionSetup {
+            region: Region::Region2,
+            config: RegionConfig::new(2 * REGION_SIZE, Subregions::from_bits(0xffff)),
+        }
+    }
+
+    fn keystore_with_key_code(region: Region) -> Keystore {
+        let mut keystore = Keystore::new(&[0u8; 1192]);
+        let mut key_code = [0u8; KEY_CODE_SIZE];
+        // KEYSIZE 2 (128 bits), index 0
+        key_code[..4].copy_from_slice(&[0x01, 0x00, 0x00, 0x02]);
+        keystore.set_key_code(key_type(region), &key_code);
+        keystore
+    }
+
+    fn cfpa_with_iv_code(region: Region) -> Cfpa {
+        let mut cfpa = Cfpa::from_bytes(&[0u8; 512]);
+        // KEYSIZE 1 (64 bits), IV key index
+        cfpa.iv_code_prince_region[region as usize].iv[..4].copy_from_slice(&[
+            0x01,
+            iv_key_index(region),
+            0x00,
+            0x01,
+        ]);
+        cfpa
+    }
+
+    fn cmpa_with(setup: &RegionSetup) -> Cmpa {
+        let mut cmpa = Cmpa::from_bytes(&[0u8; 512]);
+        cmpa.set_prince_region(setup.region, &setup.config);
+        cmpa
+    }
+
+    #[test]
+    fn setups() {
+        assert_eq!(check_setups(&[setup()]), Ok(()));
+        let empty = RegionSetup {
+            region: Region::Region0,
+            config: RegionConfig::new(0, Subregions::empty()),
+        };
+        assert_eq!(
+            check_setups(&[setup(), empty]),
+            Err(Error::EmptyRegion(Region::Region0))
+        );
+        let mut other = setup();
+        other.config = RegionConfig::new(0, Subregions::from_bits(1));
+        assert_eq!(
+            check_setups(&[setup(), other]),
+            Err(Error::DuplicateRegion(Region::Region2))
+        );
+    }
+
+    #[test]
+    fn fresh_device() {
+        let cmpa = Cmpa::from_bytes(&[0u8; 512]);
+        let cfpa = Cfpa::from_bytes(&[0u8; 512]);
+        assert_eq!(status(None, &cfpa, &cmpa), Status::default());
+
+        let steps = steps(&[setup()], None, [false; 3], &cmpa);
+        assert_eq!(
+            steps,
+            Steps {
+                key_code: [false, false, true],
+                iv_code: [false, false, true],
+                cmpa: [false, false, true],
+            }
+        );
+    }
+
+    #[test]
+    fn provisioned() {
+        let keystore = keystore_with_key_code(Region::Region2);
+        let cfpa = cfpa_with_iv_code(Region::Region2);
+        let cmpa = cmpa_with(&setup());
+
+        let status = status(Some(&keystore), &cfpa, &cmpa);
+        assert!(status.enrolled);
+        assert!(status.region(Region::Region2).is_provisioned());
+        assert_eq!(status.region(Region::Region0), RegionStatus::default());
+        assert!(status
+            .provisioned_regions()
+            .eq([Region::Region2].iter().copied()));
+
+        let steps = steps(&[setup()], Some(&keystore), [false, false, true], &cmpa);
+        assert_eq!(steps, Steps::default());
+    }
+
+    #[test]
+    fn resume_after_key_codes() {
+        // reset after writing the keystore, before CFPA
+        let keystore = keystore_with_key_code(Region::Region2);
+        let cfpa = Cfpa::from_bytes(&[0u8; 512]);
+        let cmpa = Cmpa::from_bytes(&[0u8; 512]);
+
+        let status = status(Some(&keystore), &cfpa, &cmpa);
+        assert_eq!(
+            status.region(Region::Region2),
+            RegionStatus {
+                key_code: true,
+                iv_code: false,
+                enabled: false,
+            }
+        );
+        assert_eq!(status.provisioned_regions().count(), 0);
+
+        let steps = steps(&[setup()], Some(&keystore), [false; 3], &cmpa);
+        assert_eq!(
+            steps,
+            Steps {
+                key_code: [false; 3],
+                iv_code: [false, false, true],
+                cmpa: [false, false, true],
+            }
+        );
+    }
+
+    #[test]
+    fn resume_after_iv_codes() {
+        let keystore = keystore_with_key_code(Region::Region2);
+        let cmpa = Cmpa::from_bytes(&[0u8; 512]);
+
+        // the PUF accepts the IV code: only CMPA is left
+        let steps_valid = steps(&[setup()], Some(&keystore), [false, false, true], &cmpa);
+        assert_eq!(steps_valid.key_code, [false; 3]);
+        assert_eq!(steps_valid.iv_code, [false; 3]);
+        assert_eq!(steps_valid.cmpa, [false, false, true]);
+
+        // an IV code of an earlier enrollment is replaced
+        let steps_stale = steps(&[setup()], Some(&keystore), [false; 3], &cmpa);
+        assert_eq!(steps_stale.iv_code, [false, false, true]);
+    }
+
+    #[test]
+    fn reenrollment_replaces_codes() {
+        // keystore lost (or never written), CMPA already set from an earlier run
+        let cmpa = cmpa_with(&setup());
+        let steps = steps(&[setup()], None, [false, false, true], &cmpa);
+        assert_eq!(
+            steps,
+            Steps {
+                key_code: [false, false, true],
+                iv_code: [false, false, true],
+                cmpa: [false; 3],
+            }
+        );
+    }
+
+    #[test]
+    fn changed_region_config() {
+        let keystore = keystore_with_key_code(Region::Region2);
+        let cmpa = cmpa_with(&setup());
+        let mut moved = setup();
+        moved.config = RegionConfig::new(3 * REGION_SIZE, Subregions::from_bits(0xffff));
+
+        let moved_steps = steps(&[moved], Some(&keystore), [false, false, true], &cmpa);
+        assert_eq!(moved_steps.cmpa, [false, false, true]);
+
+        // other regions are not touched
+        let region0 = RegionSetup {
+            region: Region::Region0,
+            config: RegionConfig::new(0, Subregions::from_bits(1)),
+        };
+        assert_eq!(
+            steps(&[region0], Some(&keystore), [false; 3], &cmpa),
+            Steps {
+                key_code: [true, false, false],
+                iv_code: [true, false, false],
+                cmpa: [true, false, false],
+            }
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Anti-rollback enforcement, based on the monotonic firmware version counters in CFPA.
+//!
+//! An image embeds its version. It may only run if that version is not below the
+//! counter in CFPA, and once it has proven itself, the application commits its
+//! version to CFPA, which locks out all older images.
+//!
+//! ```ignore
+//! const VERSION: u32 = 7;
+//! let cfpa = pfr.read_latest_cfpa()?;
+//! rollback::check(&cfpa, Counter::Secure, VERSION)?;
+//! // ...
+//! pfr.commit_fw_version(Some(&mut hashcrypt), Counter::Secure, VERSION)?;
+//! ```
+
+use crate::{
+    peripherals::{
+        hashcrypt::Hashcrypt,
+        pfr::{self, Cfpa, Pfr},
+    },
+    typestates::init_state,
+};
+
+/// Anti-rollback error
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The image version is below the minimum version recorded in CFPA.
+    Rollback {
+        version: u32,
+        minimum: u32,
+    },
+    Pfr(pfr::Error),
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl From<pfr::Error> for Error {
+    fn from(error: pfr::Error) -> Self {
+        Error::Pfr(error)
+    }
+}
+
+/// Which of the CFPA counters to use.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Counter {
+    /// `SECURE_FW_VERSION`
+    Secure,
+    /// `NS_FW_VERSION`
+    NonSecure,
+}
+
+impl Cfpa {
+    pub fn fw_version(&self, counter: Counter) -> u32 {
+        match counter {
+            Counter::Secure => self.secure_fw_version,
+            Counter::NonSecure => self.ns_fw_version,
+        }
+    }
+
+    fn fw_version_mut(&mut self, counter: Counter) -> &mut u32 {
+        match counter {
+            Counter::Secure => &mut self.secure_fw_version,
+            Counter::NonSecure => &mut self.ns_fw_version,
+        }
+    }
+}
+
+/// Checks that an image of version `version` may run.
+pub fn check(cfpa: &Cfpa, counter: Counter, version: u32) -> Result<()> {
+    let minimum = cfpa.fw_version(counter);
+    if version < minimum {
+        Err(Error::Rollback { version, minimum })
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether committing `version` would change the counter.
+pub fn needs_commit(cfpa: &Cfpa, counter: Counter, version: u32) -> bool {
+    version > cfpa.fw_version(counter)
+}
+
+impl Pfr<init_state::Enabled> {
+    /// Raises the firmware version counter to `version`, locking out older images.
+    ///
+    /// Fails with `Error::Rollback` if `version` is below the current counter.
+    /// Does not write CFPA if the counter is already at `version`.
+    pub fn commit_fw_version(
+        &mut self,
+        hashcrypt: Option<&mut Hashcrypt<init_state::Enabled>>,
+        counter: Counter,
+        version: u32,
+    ) -> Result<Cfpa> {
+        let cfpa = self.read_latest_cfpa().map_err(pfr::Error::Rom)?;
+        check(&cfpa, counter, version)?;
+        if !needs_commit(&cfpa, counter, version) {
+            return Ok(cfpa);
+        }
+
+        Ok(self.update_cfpa(hashcrypt, |cfpa| {
+            *cfpa.fw_version_mut(counter) = version;
+        })?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfpa(secure: u32, non_secure: u32) -> Cfpa {
+        let mut cfpa = Cfpa::from_bytes(&[0u8; 512]);
+        cfpa.secure_fw_version = secure;
+        cfpa.ns_fw_version = non_secure;
+        cfpa
+    }
+
+    #[test]
+    fn older_images_are_rejected() {
+        let cfpa = cfpa(7, 3);
+        assert_eq!(
+            check(&cfpa, Counter::Secure, 6),
+            Err(Error::Rollback {
+                version: 6,
+                minimum: 7
+            })
+        );
+        assert_eq!(check(&cfpa, Counter::Secure, 7), Ok(()));
+        assert_eq!(check(&cfpa, Counter::Secure, 8), Ok(()));
+    }
+
+    #[test]
+    fn counters_are_independent() {
+        let cfpa = cfpa(7, 3);
+        assert_eq!(check(&cfpa, Counter::NonSecure, 3), Ok(()));
+        assert!(check(&cfpa, Counter::NonSecure, 2).is_err());
+        assert_eq!(cfpa.fw_version(Counter::Secure), 7);
+        assert_eq!(cfpa.fw_version(Counter::NonSecure), 3);
+    }
+
+    #[test]
+    fn commit_only_raises() {
+        let cfpa = cfpa(7, 0);
+        assert!(!needs_commit(&cfpa, Counter::Secure, 6));
+        assert!(!needs_commit(&cfpa, Counter::Secure, 7));
+        assert!(needs_commit(&cfpa, Counter::Secure, 8));
+        // erased counters accept any version
+        assert!(needs_commit(&cfpa, Counter::NonSecure, 1));
+        assert_eq!(check(&cfpa, Counter::NonSecure, 0), Ok(()));
+    }
+}
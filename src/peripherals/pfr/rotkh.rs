@@ -0,0 +1,381 @@
+//! Root of trust key hash (RoTKH) computation and verification.
+//!
+//! The ROM authenticates images with one of up to four root of trust keys (RoTK).
+//! CMPA only stores a hash over the hashes of these keys:
+//!
+//! ```text
+//! RoTKH = SHA-256(H(RoTK0) || H(RoTK1) || H(RoTK2) || H(RoTK3))
+//! ```
+//!
+//! where `H` is SHA-256 over the big-endian public key data (see `PublicKey`),
+//! and unused slots contribute 32 zero bytes.
+//! Individual keys can be revoked in CFPA (`ROTKH_REVOKE`).
+
+use core::marker::PhantomData;
+
+use crate::{
+    peripherals::{
+        hashcrypt::Hashcrypt,
+        pfr::{self, Cfpa, Cmpa, Pfr},
+    },
+    traits::digest::{
+        generic_array::{typenum::U32, GenericArray},
+        Digest, OutputSizeUser, Update,
+    },
+    typestates::init_state,
+};
+
+pub const MAX_KEYS: usize = 4;
+
+/// RoTKH error
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// More than `MAX_KEYS` keys were passed.
+    TooManyKeys,
+    /// The RoTKH in CMPA does not match the key set.
+    Mismatch,
+    /// The key index is not below `MAX_KEYS`.
+    InvalidIndex,
+    /// The key slot was never enabled in CFPA.
+    KeyNotEnabled,
+    /// The key has been revoked in CFPA.
+    KeyRevoked,
+    /// Revoking this key would leave no enabled key.
+    LastKey,
+    Pfr(pfr::Error),
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl From<pfr::Error> for Error {
+    fn from(error: pfr::Error) -> Self {
+        Error::Pfr(error)
+    }
+}
+
+/// Something that computes SHA-256: `Hashcrypt` on target, or `Software` elsewhere.
+pub trait Sha256Engine {
+    /// SHA-256 over the concatenation of `parts`.
+    fn sha256(&mut self, parts: &[&[u8]]) -> [u8; 32];
+}
+
+impl Sha256Engine for Hashcrypt<init_state::Enabled> {
+    fn sha256(&mut self, parts: &[&[u8]]) -> [u8; 32] {
+        let mut sha = Hashcrypt::sha256(self);
+        for part in parts {
+            sha.update(part);
+        }
+        let mut digest = GenericArray::default();
+        sha.finalize_into_dirty(&mut digest);
+        digest.into()
+    }
+}
+
+/// Software fallback, wrapping a RustCrypto implementation such as `sha2::Sha256`.
+pub struct Software<D>(PhantomData<D>);
+
+impl<D> Software<D> {
+    pub fn new() -> Self {
+        Software(PhantomData)
+    }
+}
+
+impl<D> Default for Software<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: Digest + OutputSizeUser<OutputSize = U32>> Sha256Engine for Software<D> {
+    fn sha256(&mut self, parts: &[&[u8]]) -> [u8; 32] {
+        let mut digest = D::new();
+        for part in parts {
+            Digest::update(&mut digest, part);
+        }
+        digest.finalize().into()
+    }
+}
+
+/// A root of trust public key, all integers big-endian.
+///
+/// Leading zero bytes are ignored, matching NXP's tooling.
+#[derive(Copy, Clone, Debug)]
+pub enum PublicKey<'a> {
+    Rsa {
+        modulus: &'a [u8],
+        exponent: &'a [u8],
+    },
+    Ecc {
+        x: &'a [u8],
+        y: &'a [u8],
+    },
+}
+
+fn strip_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|byte| *byte != 0)
+        .unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+impl PublicKey<'_> {
+    /// The per-key hash that enters the RoTKH.
+    pub fn hash(&self, engine: &mut impl Sha256Engine) -> [u8; 32] {
+        let (first, second) = match *self {
+            PublicKey::Rsa { modulus, exponent } => (modulus, exponent),
+            PublicKey::Ecc { x, y } => (x, y),
+        };
+        engine.sha256(&[strip_leading_zeros(first), strip_leading_zeros(second)])
+    }
+}
+
+/// Computes the RoTKH from precomputed key hashes; missing slots are zero.
+pub fn compute_from_hashes(
+    engine: &mut impl Sha256Engine,
+    key_hashes: &[[u8; 32]],
+) -> Result<[u8; 32]> {
+    if key_hashes.len() > MAX_KEYS {
+        return Err(Error::TooManyKeys);
+    }
+    let mut table = [[0u8; 32]; MAX_KEYS];
+    table[..key_hashes.len()].copy_from_slice(key_hashes);
+    Ok(engine.sha256(&[&table[0], &table[1], &table[2], &table[3]]))
+}
+
+/// Computes the RoTKH from up to four public keys, in slot order.
+pub fn compute(engine: &mut impl Sha256Engine, keys: &[PublicKey<'_>]) -> Result<[u8; 32]> {
+    if keys.len() > MAX_KEYS {
+        return Err(Error::TooManyKeys);
+    }
+    let mut key_hashes = [[0u8; 32]; MAX_KEYS];
+    for (key_hash, key) in key_hashes.iter_mut().zip(keys) {
+        *key_hash = key.hash(engine);
+    }
+    compute_from_hashes(engine, &key_hashes[..keys.len()])
+}
+
+/// Checks that the RoTKH provisioned in CMPA belongs to the key set.
+pub fn verify(engine: &mut impl Sha256Engine, cmpa: &Cmpa, keys: &[PublicKey<'_>]) -> Result<()> {
+    if compute(engine, keys)? == cmpa.rotkh {
+        Ok(())
+    } else {
+        Err(Error::Mismatch)
+    }
+}
+
+/// State of a root of trust key slot, from `CFPA.ROTKH_REVOKE`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyState {
+    Invalid,
+    Enabled,
+    Revoked,
+}
+
+impl Cfpa {
+    /// Fails with `InvalidIndex` unless `index` is below `MAX_KEYS`.
+    pub fn root_key_state(&self, index: usize) -> Result<KeyState> {
+        if index >= MAX_KEYS {
+            return Err(Error::InvalidIndex);
+        }
+        Ok(match (self.rotkh_revoke >> (2 * index)) & 0b11 {
+            0b00 => KeyState::Invalid,
+            0b01 => KeyState::Enabled,
+            _ => KeyState::Revoked,
+        })
+    }
+
+    /// Fails with `InvalidIndex` unless `index` is below `MAX_KEYS`.
+    pub fn set_root_key_state(&mut self, index: usize, state: KeyState) -> Result<()> {
+        if index >= MAX_KEYS {
+            return Err(Error::InvalidIndex);
+        }
+        let bits = match state {
+            KeyState::Invalid => 0b00,
+            KeyState::Enabled => 0b01,
+            KeyState::Revoked => 0b11,
+        };
+        self.rotkh_revoke = (self.rotkh_revoke & !(0b11 << (2 * index))) | (bits << (2 * index));
+        Ok(())
+    }
+}
+
+/// Checks that the key in slot `index` may be used to authenticate images.
+pub fn check_key(cfpa: &Cfpa, index: usize) -> Result<()> {
+    match cfpa.root_key_state(index)? {
+        KeyState::Enabled => Ok(()),
+        KeyState::Invalid => Err(Error::KeyNotEnabled),
+        KeyState::Revoked => Err(Error::KeyRevoked),
+    }
+}
+
+// Revoking the key in slot `index` must leave another key enabled.
+fn check_revoke(cfpa: &Cfpa, index: usize) -> Result<()> {
+    cfpa.root_key_state(index)?;
+    let remaining = (0..MAX_KEYS)
+        .filter(|i| *i != index && cfpa.root_key_state(*i) == Ok(KeyState::Enabled))
+        .count();
+    if remaining == 0 {
+        return Err(Error::LastKey);
+    }
+    Ok(())
+}
+
+impl Pfr<init_state::Enabled> {
+    /// Revokes a root of trust key in CFPA.
+    ///
+    /// Refuses to revoke the last enabled key, as no image could be authenticated any more.
+    pub fn revoke_root_key(
+        &mut self,
+        hashcrypt: Option<&mut Hashcrypt<init_state::Enabled>>,
+        index: usize,
+    ) -> Result<Cfpa> {
+        let cfpa = self.read_latest_cfpa().map_err(pfr::Error::Rom)?;
+        check_revoke(&cfpa, index)?;
+
+        Ok(self.update_cfpa(hashcrypt, |cfpa| {
+            // `check_revoke` checked the index
+            cfpa.set_root_key_state(index, KeyState::Revoked).ok();
+        })?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::hex;
+
+    type Sha256 = Software<sha2::Sha256>;
+
+    // 2048-bit moduli, the first with exponent 65537, the second with exponent 3
+    const MODULUS0: &str = "
+        b5174bfb8e4ee0608462e4f89c3fcd0113bd687e5d6961c0c7e0d35c5bb17edc
+        091b43360558e6c5368ab4fea0d045e2076b633d18efa50541e511faec9f26ce
+        4ac20c61073377342e51fbfa8873f94b1af41127809df207fcabc55d4632ce4e
+        96f8060977db1aade06d28c39bd71b8f2dd1c52f1a083784311a8a9944213519
+        695f67ad6919cacf03023ae1ce06e5f89231ec8c21c68b86d6d47a72b1f4da96
+        3e20992c3cd6654e7208105060f1d55c6bf03e5752bb74a395a5a3eaf37825d5
+        c479922a117b0e69023a61639cd40f91c1a283378092bc63ee40a457ebfd362d
+        88d81760c8db41234a11b9b3104b10484384f4a5878f99a9de1b872efb801573";
+    const MODULUS1: &str = "
+        b6807f3bd61ba4ad80437f8f79c36213a7f2335896831d1fc2e3eb031693a958
+        377273f7436d7bad1dfd7b52fee139dff1ca382f97bc93ead93b436f76b03d14
+        ef19857490b4fcf16a9f3fccda3d084b090f6f253e36e9c241bfcd5209a0ccef
+        14496f53be3dd73e454ebc5b44d380db0bd5619ff973f1af21e3cd23c2777648
+        99beefd0cf0da8a91f11af877a9273b768f7fd6f2342958d75c96888fd4c1b2b
+        f43744aaa76a77f313b65dbb5696d9755c0cb693507312e8e3c74d918e2cf792
+        28c0e0d021f6bafae5df625293796e506d15decb69f4b4ac750d87fe504715a6
+        ac636814bac7e059c49749f33a6a4f7da44cc8c6dc45e0a1c0368fcee9a89e7b";
+
+    // computed with Python's hashlib as SPSDK 1.x does (`calc_pub_key_hash`,
+    // and the RKHT of four hashes with unused ones zero)
+    const HASH0: &str = "3c8d0bb0cc748be82a844537c958c73db106249a218d14802adaaac7569c4225";
+    const HASH1: &str = "96db9bbd83325125f632fd8c48f09f9371b91772ec290cbc4ee7971eaa26dae3";
+    const ROTKH: &str = "27aa7ca2411e943cfa99d26f3f1346fb59dc0d590cbd2eb8270e9e3301fccc75";
+    const ROTKH_KEY0_ONLY: &str =
+        "d3ecbb4a5073ca8d675b85e8ba76d727cbba89223748a8ddf352d40a55a1e4a2";
+
+    #[test]
+    fn known_rotkh() {
+        let modulus0 = hex::<256>(MODULUS0);
+        let modulus1 = hex::<256>(MODULUS1);
+        // leading zeros of modulus and exponent do not enter the hash
+        let mut padded = [0u8; 260];
+        padded[4..].copy_from_slice(&modulus1);
+        let keys = [
+            PublicKey::Rsa {
+                modulus: &modulus0,
+                exponent: &[0x01, 0x00, 0x01],
+            },
+            PublicKey::Rsa {
+                modulus: &padded,
+                exponent: &[0, 0, 0, 3],
+            },
+        ];
+
+        let engine = &mut Sha256::new();
+        assert_eq!(keys[0].hash(engine), hex::<32>(HASH0));
+        assert_eq!(keys[1].hash(engine), hex::<32>(HASH1));
+        assert_eq!(compute(engine, &keys), Ok(hex::<32>(ROTKH)));
+        assert_eq!(
+            compute_from_hashes(engine, &[hex(HASH0), hex(HASH1)]),
+            Ok(hex::<32>(ROTKH))
+        );
+        assert_eq!(compute(engine, &keys[..1]), Ok(hex::<32>(ROTKH_KEY0_ONLY)));
+        assert_eq!(compute(engine, &[keys[0]; 5]), Err(Error::TooManyKeys));
+    }
+
+    #[test]
+    fn verify_against_cmpa() {
+        let modulus0 = hex::<256>(MODULUS0);
+        let modulus1 = hex::<256>(MODULUS1);
+        let key0 = PublicKey::Rsa {
+            modulus: &modulus0,
+            exponent: &[0x01, 0x00, 0x01],
+        };
+        let key1 = PublicKey::Rsa {
+            modulus: &modulus1,
+            exponent: &[3],
+        };
+        let mut cmpa = Cmpa::from_bytes(&[0u8; 512]);
+        cmpa.rotkh = hex(ROTKH);
+
+        let engine = &mut Sha256::new();
+        assert_eq!(verify(engine, &cmpa, &[key0, key1]), Ok(()));
+        // order matters, as do missing keys
+        assert_eq!(verify(engine, &cmpa, &[key1, key0]), Err(Error::Mismatch));
+        assert_eq!(verify(engine, &cmpa, &[key0]), Err(Error::Mismatch));
+        cmpa.rotkh[31] ^= 1;
+        assert_eq!(verify(engine, &cmpa, &[key0, key1]), Err(Error::Mismatch));
+    }
+
+    #[test]
+    fn key_states() {
+        let mut cfpa = Cfpa::from_bytes(&[0u8; 512]);
+        // RoTK0 enabled, RoTK1 revoked, RoTK2 revoked (0b10 also counts), RoTK3 invalid
+        cfpa.rotkh_revoke = 0b00_10_11_01;
+        assert_eq!(cfpa.root_key_state(0), Ok(KeyState::Enabled));
+        assert_eq!(cfpa.root_key_state(1), Ok(KeyState::Revoked));
+        assert_eq!(cfpa.root_key_state(2), Ok(KeyState::Revoked));
+        assert_eq!(cfpa.root_key_state(3), Ok(KeyState::Invalid));
+        assert_eq!(cfpa.root_key_state(4), Err(Error::InvalidIndex));
+
+        assert_eq!(cfpa.set_root_key_state(3, KeyState::Enabled), Ok(()));
+        assert_eq!(cfpa.set_root_key_state(0, KeyState::Revoked), Ok(()));
+        assert_eq!(cfpa.rotkh_revoke, 0b01_10_11_11);
+        assert_eq!(
+            cfpa.set_root_key_state(4, KeyState::Revoked),
+            Err(Error::InvalidIndex)
+        );
+        assert_eq!(cfpa.rotkh_revoke, 0b01_10_11_11);
+
+        // other fields of the word are left alone
+        cfpa.rotkh_revoke |= 0xffff_0000;
+        cfpa.set_root_key_state(1, KeyState::Invalid).unwrap();
+        assert_eq!(cfpa.rotkh_revoke, 0xffff_0000 | 0b01_10_00_11);
+    }
+
+    #[test]
+    fn checked_keys() {
+        let mut cfpa = Cfpa::from_bytes(&[0u8; 512]);
+        cfpa.rotkh_revoke = 0b00_11_01_01;
+        assert_eq!(check_key(&cfpa, 0), Ok(()));
+        assert_eq!(check_key(&cfpa, 1), Ok(()));
+        assert_eq!(check_key(&cfpa, 2), Err(Error::KeyRevoked));
+        assert_eq!(check_key(&cfpa, 3), Err(Error::KeyNotEnabled));
+        assert_eq!(check_key(&cfpa, 4), Err(Error::InvalidIndex));
+
+        // the last enabled key cannot be revoked, others can
+        assert_eq!(check_revoke(&cfpa, 0), Ok(()));
+        cfpa.set_root_key_state(0, KeyState::Revoked).unwrap();
+        assert_eq!(check_revoke(&cfpa, 1), Err(Error::LastKey));
+        assert_eq!(check_revoke(&cfpa, 2), Ok(()));
+        assert_eq!(check_revoke(&cfpa, 4), Err(Error::InvalidIndex));
+
+        // nor can anything be revoked without an enabled key
+        assert_eq!(
+            check_revoke(&Cfpa::from_bytes(&[0u8; 512]), 0),
+            Err(Error::LastKey)
+        );
+    }
+}
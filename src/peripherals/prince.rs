@@ -1,15 +1,195 @@
-use crate::{peripherals::rng::Rng, raw, typestates::init_state};
+//! PRINCE on-the-fly flash encryption.
+//!
+//! Each of the three regions starts at a multiple of 256KB and is split into 32
+//! sub-regions of 8KB, which are encrypted when enabled in `SR_ENABLEn`. The `Layout`
+//! (base addresses, and the sub-regions that may be encrypted) usually comes from CMPA,
+//! where the ROM also takes it from at boot. `Prince::enable_range` enables exactly the
+//! sub-regions covering an address range, so encrypted data can sit next to plaintext
+//! code. Enabling sub-regions needs the region's key to be loaded by the PUF:
+//!
+//! ```ignore
+//! let layout = Layout::from_cmpa(&pfr.read_cmpa()?)?;
+//! let mut prince = hal.prince.enabled(&rng, &layout);
+//! let key = puf.load_prince_key(Region::Region2, &key_code)?;
+//! let mut guard = prince.enable_range(&key, DATA..DATA + 1024)?;
+//! guard.write_encrypted(|| flash.write(DATA, &data))?;
+//! ```
 
-#[derive(Copy, Clone)]
+use core::ops::Range;
+
+use crate::{
+    peripherals::{pfr::Cmpa, puf::PrinceKeyLoaded, rng::Rng},
+    raw,
+    typestates::init_state,
+};
+
+/// Size of a sub-region, the granularity of encryption
+pub const SUBREGION_SIZE: usize = 8 * 1024;
+/// Size of a region, and alignment of its base address
+pub const REGION_SIZE: usize = 32 * SUBREGION_SIZE;
+// ADDR_PRG has two bits
+const MAX_BASE: usize = 3 * REGION_SIZE;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Region {
     Region0,
     Region1,
     Region2,
 }
 
+const REGIONS: [Region; 3] = [Region::Region0, Region::Region1, Region::Region2];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The range is not within the layout's sub-regions of the key's region.
+    NotCovered,
+    /// A base address in CMPA (`ADDRn_PRG`) is above 3, beyond the peripheral's two bits.
+    InvalidBase,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// A set of sub-regions of a region, bit `n` for offsets `n * 8KB..(n + 1) * 8KB`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Subregions(u32);
+
+impl Subregions {
+    pub const fn empty() -> Self {
+        Subregions(0)
+    }
+
+    pub const fn all() -> Self {
+        Subregions(!0)
+    }
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Subregions(bits)
+    }
+
+    pub const fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// The sub-regions overlapping `offsets`, relative to the region base. Panics unless
+    /// they are within the region.
+    pub fn covering(offsets: Range<usize>) -> Self {
+        assert!(offsets.start <= offsets.end && offsets.end <= REGION_SIZE);
+        if offsets.start == offsets.end {
+            return Self::empty();
+        }
+        let first = offsets.start / SUBREGION_SIZE;
+        let last = (offsets.end - 1) / SUBREGION_SIZE;
+        let bits = (!0u32 >> (31 - last)) & (!0u32 << first);
+        Subregions(bits)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, other: Subregions) -> bool {
+        other.0 & !self.0 == 0
+    }
+
+    pub fn union(&self, other: Subregions) -> Self {
+        Subregions(self.0 | other.0)
+    }
+}
+
+/// Base address and sub-regions of a region
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RegionConfig {
+    base: usize,
+    subregions: Subregions,
+}
+
+impl RegionConfig {
+    /// Panics unless `base` is a multiple of 256KB below 1MB.
+    pub fn new(base: usize, subregions: Subregions) -> Self {
+        assert!(base & (REGION_SIZE - 1) == 0 && base <= MAX_BASE);
+        Self { base, subregions }
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn subregions(&self) -> Subregions {
+        self.subregions
+    }
+
+    /// Length up to the end of the last sub-region
+    pub fn len(&self) -> usize {
+        (32 - self.subregions.0.leading_zeros() as usize) * SUBREGION_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subregions.is_empty()
+    }
+
+    /// The sub-regions covering `addresses`, if they are all among `subregions`.
+    pub fn covering(&self, addresses: Range<usize>) -> Option<Subregions> {
+        if addresses.start < self.base
+            || addresses.end > self.base + REGION_SIZE
+            || addresses.start > addresses.end
+        {
+            return None;
+        }
+        let covering = Subregions::covering(addresses.start - self.base..addresses.end - self.base);
+        if self.subregions.contains(covering) {
+            Some(covering)
+        } else {
+            None
+        }
+    }
+}
+
+/// Configuration of the three regions
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Layout {
+    regions: [RegionConfig; 3],
+}
+
+impl Default for Layout {
+    /// Region `n` at `n * 256KB`, all sub-regions
+    fn default() -> Self {
+        let region = |index| RegionConfig::new(index * REGION_SIZE, Subregions::all());
+        Self::new([region(0), region(1), region(2)])
+    }
+}
+
+impl Layout {
+    pub fn new(regions: [RegionConfig; 3]) -> Self {
+        Self { regions }
+    }
+
+    /// From `PRINCE_BASE_ADDR` and `PRINCE_SRn`.
+    pub fn from_cmpa(cmpa: &Cmpa) -> Result<Self> {
+        Ok(Self::new([
+            cmpa.prince_region(Region::Region0)?,
+            cmpa.prince_region(Region::Region1)?,
+            cmpa.prince_region(Region::Region2)?,
+        ]))
+    }
+
+    pub fn region(&self, region: Region) -> RegionConfig {
+        self.regions[region as usize]
+    }
+
+    /// The first region whose sub-regions cover `addresses`, and those sub-regions.
+    pub fn covering(&self, addresses: Range<usize>) -> Option<(Region, Subregions)> {
+        REGIONS.iter().find_map(|region| {
+            self.region(*region)
+                .covering(addresses.clone())
+                .map(|subregions| (*region, subregions))
+        })
+    }
+}
+
 // crate::wrap_stateful_peripheral!(Rtc, RTC);
 pub struct Prince<State = init_state::Unknown> {
     pub(crate) raw: raw::PRINCE,
+    layout: Layout,
     pub _state: State,
 }
 
@@ -23,13 +203,20 @@ impl Prince {
     pub fn new(raw: raw::PRINCE) -> Self {
         Prince {
             raw,
+            layout: Layout::default(),
             _state: init_state::Unknown,
         }
     }
 
     // PRINCE doesn't actually get enabled or disabled,
     // but am using this pattern to enforce that random numbers get written to the mask registers.
-    pub fn enabled(self, rng: &Rng<init_state::Enabled>) -> Prince<init_state::Enabled> {
+    /// Sets the base addresses of `layout`; the sub-region enables are left as they are
+    /// (the ROM sets them from CMPA, code may be running from encrypted sub-regions).
+    pub fn enabled(
+        self,
+        rng: &Rng<init_state::Enabled>,
+        layout: &Layout,
+    ) -> Prince<init_state::Enabled> {
         // "It is a good practice to set this register to a different random value each time the system is booted."
         self.raw
             .mask_lsb
@@ -41,31 +228,40 @@ impl Prince {
         // Disable encrypted writes
         self.raw.enc_enable.write(|w| w.en().clear_bit());
 
-        self.raw.base_addr2.write(|w| unsafe { w.bits(0x80000) });
-        self.raw.base_addr1.write(|w| unsafe { w.bits(0x40000) });
-
-        // Default is 0.
-        // self.raw.base_addr0.write(|w| unsafe{w.bits(0x00000)});
+        let prg = |region| (layout.region(region).base() / REGION_SIZE) as u8;
+        self.raw
+            .base_addr0
+            .write(|w| unsafe { w.addr_prg().bits(prg(Region::Region0)) });
+        self.raw
+            .base_addr1
+            .write(|w| unsafe { w.addr_prg().bits(prg(Region::Region1)) });
+        self.raw
+            .base_addr2
+            .write(|w| unsafe { w.addr_prg().bits(prg(Region::Region2)) });
 
         Prince {
             raw: self.raw,
+            layout: *layout,
             _state: init_state::Enabled(()),
         }
     }
 }
 
 impl Prince<init_state::Enabled> {
+    #[deprecated(note = "enables sub-regions without a loaded key, use `set_subregions`")]
     #[inline]
     pub fn enable_all_region_2(&self) {
-        self.raw.sr_enable2.write(|w| unsafe { w.bits(0xffffffff) });
+        self.write_sr_enable(Region::Region2, 0xffffffff);
     }
+    #[deprecated(note = "enables sub-regions without a loaded key, use `set_subregions`")]
     #[inline]
     pub fn enable_all_region_1(&self) {
-        self.raw.sr_enable1.write(|w| unsafe { w.bits(0xffffffff) });
+        self.write_sr_enable(Region::Region1, 0xffffffff);
     }
+    #[deprecated(note = "enables sub-regions without a loaded key, use `set_subregions`")]
     #[inline]
     pub fn enable_all_region_0(&self) {
-        self.raw.sr_enable0.write(|w| unsafe { w.bits(0xffffffff) });
+        self.write_sr_enable(Region::Region0, 0xffffffff);
     }
 
     #[inline]
@@ -81,28 +277,80 @@ impl Prince<init_state::Enabled> {
         self.raw.sr_enable0.write(|w| unsafe { w.bits(0x0) });
     }
 
+    #[deprecated(note = "enables sub-regions without a loaded key, use `enable_range`")]
     pub fn enable_region_2_for<R>(&self, f: impl FnOnce() -> R) -> R {
-        self.enable_all_region_2();
+        self.write_sr_enable(Region::Region2, 0xffffffff);
         let result = f();
         self.disable_all_region_2();
         result
     }
 
+    #[deprecated(note = "enables sub-regions without a loaded key, use `enable_range`")]
     pub fn enable_region_1_for<R>(&self, f: impl FnOnce() -> R) -> R {
-        self.enable_all_region_1();
+        self.write_sr_enable(Region::Region1, 0xffffffff);
         let result = f();
         self.disable_all_region_1();
         result
     }
 
+    #[deprecated(note = "enables sub-regions without a loaded key, use `enable_range`")]
     pub fn enable_region_0_for<R>(&self, f: impl FnOnce() -> R) -> R {
-        self.enable_all_region_0();
+        self.write_sr_enable(Region::Region0, 0xffffffff);
         let result = f();
         self.disable_all_region_0();
         result
     }
 
-    pub fn set_region_enable(&self, region: Region, enable: u32) {
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// The currently enabled sub-regions of `region`.
+    pub fn subregions(&self, region: Region) -> Subregions {
+        let bits = match region {
+            Region::Region0 => self.raw.sr_enable0.read().bits(),
+            Region::Region1 => self.raw.sr_enable1.read().bits(),
+            Region::Region2 => self.raw.sr_enable2.read().bits(),
+        };
+        Subregions::from_bits(bits)
+    }
+
+    /// Sets the sub-region enables of the key's region.
+    pub fn set_subregions(&self, key: &PrinceKeyLoaded<'_>, subregions: Subregions) {
+        self.set_region_enable(key, subregions.bits());
+    }
+
+    /// Enables the sub-regions covering `addresses`, until the guard is dropped. The
+    /// guard only allows `write_encrypted`, so the enables cannot change meanwhile.
+    ///
+    /// Fails unless they are all within the layout's sub-regions of the key's region,
+    /// so neighbouring plaintext (e.g. code) is never decrypted.
+    pub fn enable_range<'a>(
+        &'a mut self,
+        key: &'a PrinceKeyLoaded<'_>,
+        addresses: Range<usize>,
+    ) -> Result<RangeGuard<'a>> {
+        let region = key.region();
+        let covering = self
+            .layout
+            .region(region)
+            .covering(addresses)
+            .ok_or(Error::NotCovered)?;
+        let previous = self.subregions(region);
+        self.write_sr_enable(region, previous.union(covering).bits());
+        Ok(RangeGuard {
+            prince: self,
+            region,
+            previous,
+        })
+    }
+
+    /// Sets the sub-region enables of the key's region, bit `n` for sub-region `n`.
+    pub fn set_region_enable(&self, key: &PrinceKeyLoaded<'_>, enable: u32) {
+        self.write_sr_enable(key.region(), enable);
+    }
+
+    fn write_sr_enable(&self, region: Region, enable: u32) {
         match region {
             Region::Region0 => self.raw.sr_enable0.write(|w| unsafe { w.bits(enable) }),
             Region::Region1 => self.raw.sr_enable1.write(|w| unsafe { w.bits(enable) }),
@@ -139,3 +387,146 @@ impl Prince<init_state::Enabled> {
         self.raw.enc_enable.write(|w| w.en().clear_bit());
     }
 }
+
+/// Restores the sub-region enables of its region when dropped, see
+/// `Prince::enable_range`
+pub struct RangeGuard<'a> {
+    prince: &'a mut Prince<init_state::Enabled>,
+    region: Region,
+    previous: Subregions,
+}
+
+impl RangeGuard<'_> {
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// The currently enabled sub-regions of the region.
+    pub fn subregions(&self) -> Subregions {
+        self.prince.subregions(self.region)
+    }
+
+    pub fn layout(&self) -> &Layout {
+        self.prince.layout()
+    }
+
+    /// As `Prince::write_encrypted`.
+    pub fn write_encrypted<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        self.prince.write_encrypted(|_| f())
+    }
+}
+
+impl Drop for RangeGuard<'_> {
+    fn drop(&mut self) {
+        self.prince
+            .write_sr_enable(self.region, self.previous.bits());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KB: usize = 1024;
+
+    #[test]
+    fn subregions_covering() {
+        assert_eq!(Subregions::covering(0..0), Subregions::empty());
+        assert_eq!(Subregions::covering(8 * KB..8 * KB), Subregions::empty());
+        assert_eq!(Subregions::covering(0..1), Subregions::from_bits(0b1));
+        // 8KB boundaries
+        assert_eq!(Subregions::covering(0..8 * KB), Subregions::from_bits(0b1));
+        assert_eq!(
+            Subregions::covering(0..8 * KB + 1),
+            Subregions::from_bits(0b11)
+        );
+        assert_eq!(
+            Subregions::covering(8 * KB - 1..8 * KB),
+            Subregions::from_bits(0b1)
+        );
+        assert_eq!(
+            Subregions::covering(8 * KB..16 * KB),
+            Subregions::from_bits(0b10)
+        );
+        assert_eq!(
+            Subregions::covering(8 * KB - 1..8 * KB + 1),
+            Subregions::from_bits(0b11)
+        );
+        assert_eq!(
+            Subregions::covering(REGION_SIZE - 1..REGION_SIZE),
+            Subregions::from_bits(1 << 31)
+        );
+        assert_eq!(Subregions::covering(0..REGION_SIZE), Subregions::all());
+    }
+
+    #[test]
+    #[should_panic]
+    fn subregions_covering_past_the_region() {
+        Subregions::covering(REGION_SIZE - 1..REGION_SIZE + 1);
+    }
+
+    #[test]
+    fn region_covering() {
+        let base = 2 * REGION_SIZE;
+        // sub-regions 1 to 3
+        let config = RegionConfig::new(base, Subregions::from_bits(0b1110));
+        assert_eq!(config.len(), 32 * KB);
+
+        assert_eq!(
+            config.covering(base + 8 * KB..base + 32 * KB),
+            Some(Subregions::from_bits(0b1110))
+        );
+        assert_eq!(
+            config.covering(base + 16 * KB..base + 16 * KB + 1),
+            Some(Subregions::from_bits(0b100))
+        );
+        // one byte too many on either side
+        assert_eq!(config.covering(base + 8 * KB - 1..base + 16 * KB), None);
+        assert_eq!(config.covering(base + 8 * KB..base + 32 * KB + 1), None);
+
+        // empty ranges need no sub-regions, but must be within the region
+        assert_eq!(config.covering(base..base), Some(Subregions::empty()));
+        assert_eq!(
+            config.covering(base + REGION_SIZE..base + REGION_SIZE),
+            Some(Subregions::empty())
+        );
+        assert_eq!(config.covering(base - 1..base - 1), None);
+        assert_eq!(config.covering(base + 16 * KB..base + 8 * KB), None);
+
+        // crossing the start or the end of the region
+        let config = RegionConfig::new(base, Subregions::all());
+        assert_eq!(config.covering(base - 1..base + 1), None);
+        assert_eq!(
+            config.covering(base + REGION_SIZE - 1..base + REGION_SIZE),
+            Some(Subregions::from_bits(1 << 31))
+        );
+        assert_eq!(
+            config.covering(base + REGION_SIZE - 1..base + REGION_SIZE + 1),
+            None
+        );
+    }
+
+    #[test]
+    fn layout_covering() {
+        let layout = Layout::new([
+            RegionConfig::new(0, Subregions::empty()),
+            RegionConfig::new(REGION_SIZE, Subregions::from_bits(0b1)),
+            RegionConfig::new(2 * REGION_SIZE, Subregions::all()),
+        ]);
+        assert_eq!(layout.covering(0..1), None);
+        assert_eq!(
+            layout.covering(REGION_SIZE..REGION_SIZE + 8 * KB),
+            Some((Region::Region1, Subregions::from_bits(0b1)))
+        );
+        assert_eq!(layout.covering(REGION_SIZE..REGION_SIZE + 8 * KB + 1), None);
+        assert_eq!(
+            layout.covering(3 * REGION_SIZE - 8 * KB..3 * REGION_SIZE),
+            Some((Region::Region2, Subregions::from_bits(1 << 31)))
+        );
+        // across the boundary of regions 1 and 2
+        assert_eq!(
+            layout.covering(2 * REGION_SIZE - 1..2 * REGION_SIZE + 1),
+            None
+        );
+    }
+}
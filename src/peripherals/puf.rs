@@ -1,4 +1,10 @@
-use crate::{peripherals::syscon::Syscon, raw, typestates::init_state};
+use core::{convert::TryInto, marker::PhantomData};
+
+use crate::{
+    peripherals::{pfr::Pfr, prince, syscon::Syscon},
+    raw,
+    typestates::init_state,
+};
 
 // Once a PUF is started, you can generate or derive keys.
 // Check NXP AN2324 for the best explanation.
@@ -15,16 +21,33 @@ impl PufStates for Started {}
 impl PufStates for Enrolled {}
 
 /// PUF error
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Error {
     /// PUF initialization failed.
     InitializationFailed,
-    /// PUF Command could not start
+    /// PUF Command was rejected, i.e. it is not allowed in the PUF's current state
     CommandFailedToStart,
     /// PUF Command could not complete
     CommandFailed,
     /// PUF Command is not allowed
     NotAllowed,
+    /// Reading the keystore failed with this ROM status code
+    Keystore(u32),
+    /// Start failed: the activation code does not match this device or is corrupted
+    ActivationCodeCorrupted,
+    /// GetKey failed: the key code does not belong to this PUF or is corrupted
+    KeyCodeCorrupted,
+    /// An invalid access was made to the PUF register interface
+    Interface,
+    /// The PUF is in its error or zeroized state and must be power cycled
+    ErrorState,
+    /// The caller's buffer is too small for the data in- or output by the command
+    BufferTooSmall,
+    /// The key index is above 15, or the KC's does not suit the destination (0 for AES/PRINCE, 1-15 for output)
+    KeyIndex,
+    /// The key size is not a multiple of 64 in 64-4096, does not match the user key,
+    /// or the KC's does not suit the destination
+    KeySize,
 }
 pub type Result<T> = core::result::Result<T, Error>;
 
@@ -38,6 +61,151 @@ pub enum KeyDestination {
 
 crate::wrap_stateful_peripheral!(Puf, PUF);
 
+#[derive(Clone, Copy)]
+enum Command {
+    Enroll,
+    Start,
+    GenerateKey,
+    SetKey,
+    GetKey,
+    Zeroize,
+}
+
+/// KC size for a given key size in bits: 20 bytes of header and
+/// metadata, plus the key itself, padded to at least 256 bits.
+///
+/// E.g. 52 bytes for keys of 64 up to 256 bits, 532 bytes for 4096 bits.
+/// Usable as `KeyCode<{ key_code_size(256) }>`.
+pub const fn key_code_size(key_size: u32) -> usize {
+    let key_bytes = ((key_size as usize + 63) & !63) / 8;
+    20 + if key_bytes > 32 { key_bytes } else { 32 }
+}
+
+// Key sizes are multiples of 64 bits up to 4096, key indices 0-15,
+// and the KC buffer must hold at least `key_code_size(key_size)` bytes.
+fn check_key_parameters(key_size: u32, key_index: u8, key_code_len: usize) -> Result<()> {
+    if !(64..=4096).contains(&key_size) || key_size & 63 != 0 {
+        return Err(Error::KeySize);
+    }
+    if key_index >= 16 {
+        return Err(Error::KeyIndex);
+    }
+    if key_code_len < key_code_size(key_size) {
+        return Err(Error::BufferTooSmall);
+    }
+    Ok(())
+}
+
+/// The first word of a KC, as written by `GenerateKey` or `SetKey`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyCodeHeader {
+    /// KC type, as set by the PUF
+    pub key_type: u8,
+    /// 0 for keys delivered to AES/PRINCE, 1-15 for keys that are output
+    pub index: u8,
+    /// Key size in bits, 64-4096
+    pub key_size: u32,
+}
+
+impl KeyCodeHeader {
+    pub fn from_bytes(bytes: [u8; 4]) -> Self {
+        // KEYSIZE is in units of 64 bits, 0 standing for 4096
+        let units = match bytes[3] & 0x3f {
+            0 => 64,
+            units => units as u32,
+        };
+        Self {
+            key_type: bytes[0],
+            index: bytes[1] & 0x0f,
+            key_size: units * 64,
+        }
+    }
+
+    /// Size of the key in bytes, as output by `get_key`.
+    pub fn key_len(&self) -> usize {
+        self.key_size as usize / 8
+    }
+
+    /// Size of the KC carrying this key.
+    pub fn key_code_size(&self) -> usize {
+        key_code_size(self.key_size)
+    }
+}
+
+/// A key code of `N` bytes, see `key_code_size`.
+#[derive(Clone)]
+pub struct KeyCode<const N: usize> {
+    bytes: [u8; N],
+}
+
+impl<const N: usize> KeyCode<N> {
+    /// Wraps a stored KC, checking that its header matches its length.
+    pub fn from_bytes(bytes: [u8; N]) -> Result<Self> {
+        if N < 4 {
+            return Err(Error::BufferTooSmall);
+        }
+        let key_code = Self { bytes };
+        if key_code.header().key_code_size() != N {
+            return Err(Error::KeyCodeCorrupted);
+        }
+        Ok(key_code)
+    }
+
+    pub fn to_bytes(&self) -> [u8; N] {
+        self.bytes
+    }
+
+    pub fn header(&self) -> KeyCodeHeader {
+        KeyCodeHeader::from_bytes(self.bytes[..4].try_into().unwrap())
+    }
+
+    pub fn index(&self) -> u8 {
+        self.header().index
+    }
+
+    pub fn key_size(&self) -> u32 {
+        self.header().key_size
+    }
+
+    // Checks at construction that `N` fits the requested key size.
+    fn empty(key_size: u32) -> Result<Self> {
+        if key_code_size(key_size) != N {
+            return Err(Error::BufferTooSmall);
+        }
+        Ok(Self { bytes: [0u8; N] })
+    }
+}
+
+/// Proof that a 256-bit PUF key has been loaded into the AES engine via
+/// the secure bus, as required by `Hashcrypt::puf_aes`.
+///
+/// The key stays loaded until the PUF is reset, which the borrow of the
+/// `Puf` rules out while the token exists.
+#[derive(Debug, PartialEq)]
+pub struct AesKeyLoaded<'a> {
+    _puf: PhantomData<&'a Puf<init_state::Enabled<Started>>>,
+}
+
+/// Proof that a 128-bit PUF key has been loaded into a PRINCE region
+/// via the secure bus, as required by `Prince::enable_range`.
+#[derive(Debug, PartialEq)]
+pub struct PrinceKeyLoaded<'a> {
+    region: prince::Region,
+    _puf: PhantomData<&'a Puf<init_state::Enabled<Started>>>,
+}
+
+impl PrinceKeyLoaded<'_> {
+    pub fn region(&self) -> prince::Region {
+        self.region
+    }
+}
+
+impl<const N: usize> AsRef<[u8]> for KeyCode<N> {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
 impl<State> Puf<State> {
     pub fn enabled(mut self, syscon: &mut Syscon) -> Result<Puf<init_state::Enabled>> {
         syscon.enable_clock(&mut self.raw);
@@ -73,27 +241,72 @@ impl<T> Puf<init_state::Enabled<T>> {
         {}
 
         if self.raw.stat.read().error().bit_is_set() {
-            return Err(Error::CommandFailedToStart);
+            return Err(self.interface_error().unwrap_or_else(|| {
+                if self.raw.allow.read().bits() == 0 {
+                    Error::ErrorState
+                } else {
+                    Error::CommandFailedToStart
+                }
+            }));
         }
         Ok(())
     }
 
-    fn check_success(&self) -> Result<()> {
+    // Clears and reports an APB access error, which takes precedence over
+    // whatever the command itself reports.
+    fn interface_error(&self) -> Option<Error> {
+        if self.raw.ifstat.read().error().bit_is_set() {
+            self.raw.ifstat.write(|w| w.error().set_bit());
+            Some(Error::Interface)
+        } else {
+            None
+        }
+    }
+
+    fn check_success(&self, command: Command) -> Result<()> {
+        if let Some(error) = self.interface_error() {
+            return Err(error);
+        }
         if self.raw.stat.read().success().bit_is_clear() {
-            return Err(Error::CommandFailed);
+            return Err(match command {
+                Command::Start => Error::ActivationCodeCorrupted,
+                Command::GetKey => Error::KeyCodeCorrupted,
+                _ if self.raw.allow.read().bits() == 0 => Error::ErrorState,
+                _ => Error::CommandFailed,
+            });
         }
         Ok(())
     }
 
-    fn read_data(&self, data: &mut [u8]) {
+    // Words that do not fit into `data` are still drained from CODEOUTPUT,
+    // so the command runs to completion, but the result is `BufferTooSmall`.
+    fn read_data(&self, command: Command, data: &mut [u8]) -> Result<usize> {
         let mut count = 0;
         while self.raw.stat.read().busy().bit_is_set() {
             if self.raw.stat.read().codeoutavail().bit_is_set() {
                 let word = self.raw.codeoutput.read().bits();
-                data[count..count + 4].copy_from_slice(&word.to_ne_bytes());
+                if let Some(chunk) = data.get_mut(count..count + 4) {
+                    chunk.copy_from_slice(&word.to_ne_bytes());
+                }
                 count += 4;
             }
         }
+        self.check_success(command)?;
+        if count > data.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        Ok(count)
+    }
+
+    // Next input word from `data`. Callers check up front that `data` holds all the
+    // input of the command; should the PUF still ask for more, it gets zeros.
+    fn next_word(data: &[u8], count: &mut usize) -> u32 {
+        let word = match data.get(*count..*count + 4) {
+            Some(chunk) => u32::from_ne_bytes(chunk.try_into().unwrap()),
+            None => 0,
+        };
+        *count += 4;
+        word
     }
 
     // key_size: 64-4096 bits.
@@ -114,47 +327,135 @@ impl<T> Puf<init_state::Enabled<T>> {
         if self.raw.allow.read().allowsetkey().bit_is_clear() {
             return Err(Error::NotAllowed);
         }
-        assert!(key_size >= 64);
-        assert!(key_index < 16);
-
-        for i in 0..key_code.len() {
-            key_code[i] = 0;
-        }
-
-        self.raw
-            .keysize
-            .write(|w| unsafe { w.bits(((key_size >> 6) & 0x3f) as u32) });
-        self.raw
-            .keyindex
-            .write(|w| unsafe { w.bits(((key_index) & 0x0f) as u32) });
+        self.set_key_parameters(key_size, key_index, key_code)?;
 
         self.raw.ctrl.write(|w| w.generatekey().set_bit());
 
         self.wait_for_cmd()?;
 
-        self.read_data(key_code);
-
-        self.check_success()?;
+        self.read_data(Command::GenerateKey, key_code)?;
         Ok(())
     }
 
+    // Same as `generate_key`, but wraps a key provided by the user into the KC,
+    // e.g. a vendor-provisioned AES key, so it need not be stored in plaintext.
+    //
+    // user_key: exactly key_size bits, `KeySize` otherwise.
     pub fn set_key(
         &self,
-        _key_size: u32,
-        _key_index: u8,
-        _user_key: &[u8],
-        _key_code: &mut [u8],
+        key_size: u32,
+        key_index: u8,
+        user_key: &[u8],
+        key_code: &mut [u8],
     ) -> Result<()> {
-        unimplemented!();
+        if self.raw.allow.read().allowsetkey().bit_is_clear() {
+            return Err(Error::NotAllowed);
+        }
+        if user_key.len() * 8 != key_size as usize {
+            return Err(Error::KeySize);
+        }
+        self.set_key_parameters(key_size, key_index, key_code)?;
+
+        self.raw.ctrl.write(|w| w.setkey().set_bit());
+
+        self.wait_for_cmd()?;
+
+        // the key goes in via KEYINPUT while the KC comes out via CODEOUTPUT
+        let mut count_in = 0;
+        let mut count_out = 0;
+        while self.raw.stat.read().busy().bit_is_set() {
+            if self.raw.stat.read().keyinreq().bit_is_set() {
+                let word = Self::next_word(user_key, &mut count_in);
+                self.raw.keyinput.write(|w| unsafe { w.bits(word) });
+            }
+            if self.raw.stat.read().codeoutavail().bit_is_set() {
+                let word = self.raw.codeoutput.read().bits();
+                if let Some(chunk) = key_code.get_mut(count_out..count_out + 4) {
+                    chunk.copy_from_slice(&word.to_ne_bytes());
+                }
+                count_out += 4;
+            }
+        }
+
+        self.check_success(Command::SetKey)?;
+        if count_in > user_key.len() || count_out > key_code.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        Ok(())
+    }
+
+    // Shared by `generate_key` and `set_key`: validates the parameters,
+    // clears the KC buffer and programs KEYSIZE and KEYINDEX.
+    fn set_key_parameters(&self, key_size: u32, key_index: u8, key_code: &mut [u8]) -> Result<()> {
+        check_key_parameters(key_size, key_index, key_code.len())?;
+
+        for byte in key_code.iter_mut() {
+            *byte = 0;
+        }
+
+        self.raw
+            .keysize
+            .write(|w| unsafe { w.bits((key_size >> 6) & 0x3f) });
+        self.raw
+            .keyindex
+            .write(|w| unsafe { w.bits((key_index & 0x0f) as u32) });
+        Ok(())
+    }
+
+    /// Typed variant of `generate_key`, e.g.
+    /// `let kc: KeyCode<{ key_code_size(256) }> = puf.generate_key_code(256, 1)?;`
+    ///
+    /// Fails with `BufferTooSmall` if `N` does not match `key_size`.
+    pub fn generate_key_code<const N: usize>(
+        &self,
+        key_size: u32,
+        key_index: u8,
+    ) -> Result<KeyCode<N>> {
+        let mut key_code = KeyCode::empty(key_size)?;
+        self.generate_key(key_size, key_index, &mut key_code.bytes)?;
+        Ok(key_code)
+    }
+
+    /// Typed variant of `set_key`, the key size is that of `user_key`.
+    pub fn set_key_code<const N: usize>(
+        &self,
+        key_index: u8,
+        user_key: &[u8],
+    ) -> Result<KeyCode<N>> {
+        let key_size = user_key.len() as u32 * 8;
+        let mut key_code = KeyCode::empty(key_size)?;
+        self.set_key(key_size, key_index, user_key, &mut key_code.bytes)?;
+        Ok(key_code)
     }
 
     pub fn version(&self) -> u32 {
         self.raw.version.read().bits()
     }
 
-    // Put PUF into reset state.
-    pub fn reset(&self) -> () {
-        unimplemented!();
+    /// Zeroizes the PUF, e.g. on tamper detection or at end of life.
+    ///
+    /// Wipes the PUF's internal state and the keys already delivered to
+    /// AES/PRINCE, and stops any further key delivery.  The PUF is left in
+    /// its zeroized state, in which no command is allowed until it is
+    /// disabled and enabled again (and then started from the activation code),
+    /// hence the unstarted `Puf` returned.
+    pub fn reset(self) -> Result<Puf<init_state::Enabled>> {
+        self.raw.keyenable.write(|w| w.key().none());
+        // 0b10 reinitializes the key shift registers of all four destinations
+        self.raw.keyreset.write(|w| unsafe { w.bits(0b1010_1010) });
+
+        self.raw.ctrl.write(|w| w.zeroize().set_bit());
+        self.wait_for_cmd()?;
+        while self.raw.stat.read().busy().bit_is_set() {}
+
+        self.check_success(Command::Zeroize)?;
+        if self.raw.allow.read().bits() != 0 {
+            return Err(Error::CommandFailed);
+        }
+        Ok(Puf {
+            raw: self.raw,
+            _state: init_state::Enabled(()),
+        })
     }
 }
 // Must enroll once per device.  Enrolling consumes the PUF and device must be restarted.
@@ -174,9 +475,7 @@ impl Puf<init_state::Enabled> {
 
         self.wait_for_cmd()?;
 
-        self.read_data(ac_buffer);
-
-        self.check_success()?;
+        self.read_data(Command::Enroll, ac_buffer)?;
 
         Ok(Puf {
             raw: self.raw,
@@ -184,23 +483,21 @@ impl Puf<init_state::Enabled> {
         })
     }
 
+    // The activation code is always 1192 bytes, the input of Start.
     fn do_start(&mut self, ac_buffer: &[u8; 1192]) -> Result<()> {
         self.raw.ctrl.write(|w| w.start().set_bit());
 
         self.wait_for_cmd()?;
 
-        let mut word_buf = [0u8; 4];
         let mut i = 0;
         while self.raw.stat.read().busy().bit_is_set() {
             if self.raw.stat.read().codeinreq().bit_is_set() {
-                word_buf.copy_from_slice(&ac_buffer[i..i + 4]);
-                let word = u32::from_ne_bytes(word_buf);
+                let word = Self::next_word(ac_buffer, &mut i);
                 self.raw.codeinput.write(|w| unsafe { w.bits(word) });
-                i += 4;
             }
         }
 
-        self.check_success()
+        self.check_success(Command::Start)
     }
 
     /// Starts the PUF using the activation code.
@@ -235,6 +532,15 @@ impl Puf<init_state::Enabled> {
             _state: init_state::Enabled(Started),
         })
     }
+
+    /// Like `try_start`, with the activation code taken from the PFR keystore.
+    pub fn try_start_from_keystore(
+        self,
+        pfr: &mut Pfr<init_state::Enabled>,
+    ) -> Result<Puf<init_state::Enabled<Started>>> {
+        let ac = pfr.read_activation_code().map_err(Error::Keystore)?;
+        self.try_start(&ac)
+    }
 }
 
 impl Puf<init_state::Enabled<Started>> {
@@ -247,6 +553,16 @@ impl Puf<init_state::Enabled<Started>> {
         if self.raw.allow.read().allowgetkey().bit_is_clear() {
             return Err(Error::NotAllowed);
         }
+        if key_code.len() < 4 {
+            return Err(Error::BufferTooSmall);
+        }
+        let header = KeyCodeHeader::from_bytes(key_code[..4].try_into().unwrap());
+        if key_code.len() < header.key_code_size() {
+            return Err(Error::BufferTooSmall);
+        }
+        if header.index != 0 && key.len() < header.key_len() {
+            return Err(Error::BufferTooSmall);
+        }
 
         self.raw
             .keyenable
@@ -255,29 +571,80 @@ impl Puf<init_state::Enabled<Started>> {
         self.raw.ctrl.write(|w| w.getkey().set_bit());
 
         self.wait_for_cmd()?;
-        let mut word_buf = [0u8; 4];
         let mut count_in = 0;
         let mut count_out = 0;
         while self.raw.stat.read().busy().bit_is_set() {
             if self.raw.stat.read().codeinreq().bit_is_set() {
-                word_buf.copy_from_slice(&key_code[count_in..count_in + 4]);
-                let word = u32::from_ne_bytes(word_buf);
+                let word = Self::next_word(key_code, &mut count_in);
                 self.raw.codeinput.write(|w| unsafe { w.bits(word) });
-                count_in += 4;
             }
             if self.raw.stat.read().keyoutavail().bit_is_set() {
                 self.raw.keyindex.read().bits();
                 let word = self.raw.keyoutput.read().bits();
-                key[count_out..count_out + 4].copy_from_slice(&word.to_ne_bytes());
+                if let Some(chunk) = key.get_mut(count_out..count_out + 4) {
+                    chunk.copy_from_slice(&word.to_ne_bytes());
+                }
                 count_out += 4;
             }
         }
-        self.check_success()?;
+        if count_in > key_code.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        self.check_success(Command::GetKey)?;
+        if count_out > key.len() {
+            return Err(Error::BufferTooSmall);
+        }
 
         Ok(count_out)
     }
 }
 
+impl Puf<init_state::Enabled<Started>> {
+    /// Loads the (index 0, 256-bit) key of `key_code` into the AES engine.
+    pub fn load_aes_key(&self, key_code: &[u8]) -> Result<AesKeyLoaded<'_>> {
+        self.load_key(raw::puf::keyenable::KEY_A::AES, 256, key_code)?;
+        Ok(AesKeyLoaded { _puf: PhantomData })
+    }
+
+    /// Loads the (index 0, 128-bit) key of `key_code` into the given PRINCE region.
+    pub fn load_prince_key(
+        &self,
+        region: prince::Region,
+        key_code: &[u8],
+    ) -> Result<PrinceKeyLoaded<'_>> {
+        let destination = match region {
+            prince::Region::Region0 => raw::puf::keyenable::KEY_A::PRINCE0,
+            prince::Region::Region1 => raw::puf::keyenable::KEY_A::PRINCE1,
+            prince::Region::Region2 => raw::puf::keyenable::KEY_A::PRINCE2,
+        };
+        self.load_key(destination, 128, key_code)?;
+        Ok(PrinceKeyLoaded {
+            region,
+            _puf: PhantomData,
+        })
+    }
+
+    fn load_key(
+        &self,
+        destination: raw::puf::keyenable::KEY_A,
+        key_size: u32,
+        key_code: &[u8],
+    ) -> Result<()> {
+        if key_code.len() < 4 {
+            return Err(Error::BufferTooSmall);
+        }
+        let header = KeyCodeHeader::from_bytes(key_code[..4].try_into().unwrap());
+        if header.index != 0 {
+            return Err(Error::KeyIndex);
+        }
+        if header.key_size != key_size {
+            return Err(Error::KeySize);
+        }
+        self.get_key(destination, key_code, &mut [])?;
+        Ok(())
+    }
+}
+
 impl<State> core::fmt::Debug for Puf<State> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "").unwrap();
@@ -289,3 +656,81 @@ impl<State> core::fmt::Debug for Puf<State> {
         writeln!(f, "  keyindex = x{:X}", self.raw.keyindex.read().bits())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_code_sizes() {
+        // UM11126, "PUF key code size": at least 256 bits of key material
+        for &(key_size, kc_size) in &[
+            (64, 52),
+            (128, 52),
+            (192, 52),
+            (256, 52),
+            (320, 60),
+            (512, 84),
+            (1024, 148),
+            (2048, 276),
+            (4096, 532),
+        ] {
+            assert_eq!(key_code_size(key_size), kc_size, "{} bits", key_size);
+        }
+    }
+
+    #[test]
+    fn key_parameters() {
+        assert_eq!(check_key_parameters(256, 1, 52), Ok(()));
+        assert_eq!(check_key_parameters(4096, 15, 532), Ok(()));
+        assert_eq!(check_key_parameters(256, 1, 51), Err(Error::BufferTooSmall));
+        assert_eq!(
+            check_key_parameters(4096, 0, 531),
+            Err(Error::BufferTooSmall)
+        );
+        assert_eq!(check_key_parameters(256, 16, 52), Err(Error::KeyIndex));
+        for &key_size in &[0, 32, 100, 4160] {
+            assert_eq!(
+                check_key_parameters(key_size, 0, 600),
+                Err(Error::KeySize),
+                "{} bits",
+                key_size
+            );
+        }
+    }
+
+    #[test]
+    fn header() {
+        // KEYSIZE 4 (256 bits), index 0
+        let header = KeyCodeHeader::from_bytes([0x01, 0x00, 0x00, 0x04]);
+        assert_eq!(header.key_size, 256);
+        assert_eq!(header.index, 0);
+        assert_eq!(header.key_len(), 32);
+        assert_eq!(header.key_code_size(), 52);
+
+        // KEYSIZE 0 stands for 4096 bits
+        let header = KeyCodeHeader::from_bytes([0x01, 0x0f, 0x00, 0x00]);
+        assert_eq!(header.key_size, 4096);
+        assert_eq!(header.index, 15);
+        assert_eq!(header.key_code_size(), 532);
+    }
+
+    #[test]
+    fn key_code_length_matches_header() {
+        let mut bytes = [0u8; 52];
+        bytes[3] = 2;
+        let key_code = KeyCode::from_bytes(bytes).unwrap();
+        assert_eq!(key_code.key_size(), 128);
+
+        let mut bytes = [0u8; 84];
+        bytes[3] = 2;
+        assert_eq!(
+            KeyCode::from_bytes(bytes).err(),
+            Some(Error::KeyCodeCorrupted)
+        );
+        assert_eq!(
+            KeyCode::from_bytes([0u8; 3]).err(),
+            Some(Error::BufferTooSmall)
+        );
+    }
+}
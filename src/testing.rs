@@ -0,0 +1,14 @@
+//! Helpers for the host unit tests.
+
+/// Decodes a hex string of exactly `N` bytes, ignoring whitespace.
+pub fn hex<const N: usize>(hex: &str) -> [u8; N] {
+    let mut bytes = [0u8; N];
+    let mut digits = hex.chars().filter(|c| !c.is_whitespace());
+    for byte in bytes.iter_mut() {
+        let high = digits.next().unwrap().to_digit(16).unwrap();
+        let low = digits.next().unwrap().to_digit(16).unwrap();
+        *byte = (high << 4 | low) as u8;
+    }
+    assert!(digits.next().is_none(), "more than {} bytes", N);
+    bytes
+}
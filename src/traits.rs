@@ -1,5 +1,6 @@
 use core::ops::Deref;
 
+pub use aead;
 pub use cipher;
 pub use digest;
 
@@ -13,6 +14,8 @@ pub use embedded_hal as wg;
 
 pub use rand_core;
 
+pub use signature;
+
 // TODO: Add more as needed,
 // - internal
 // - specific (CASPER, PUF, etc.)